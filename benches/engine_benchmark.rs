@@ -1,12 +1,23 @@
-use criterion::{criterion_group, criterion_main, Criterion};
-use rand::{Rng, thread_rng};
-use rand::rngs::ThreadRng;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use rand::distributions::Alphanumeric;
 use tempfile::TempDir;
-use kvs::{KvStore, SledKvsEngine, KvsEngine};
+use kvs::{CacheSize, Codec, KvStore, KvStoreConfig, SledKvsEngine, KvsEngine};
+
+/// Seed of the random number generators, so that every run writes and reads the same keys and values
+const SEED: u64 = 42;
+
+/// Number of keys written before a read benchmark, out of which it reads random keys
+const READ_KEYS: usize = 100;
+
+/// Create a random number generator which gives the same numbers on every run
+fn seeded_rng() -> StdRng {
+    StdRng::seed_from_u64(SEED)
+}
 
 /// Create a stirng with a random byte size between 0 and 100000
-pub fn get_random_string(rng: &mut ThreadRng) -> String {
+pub fn get_random_string(rng: &mut impl Rng) -> String {
     // Generate random byte size
     let size = rng.gen_range(1..100000);
 
@@ -14,65 +25,164 @@ pub fn get_random_string(rng: &mut ThreadRng) -> String {
     rng.sample_iter(&Alphanumeric).take(size).map(char::from).collect()
 }
 
+/// Measure setting random keys to random values, generated outside of the measurement
+fn bench_write(c: &mut Criterion, name: &str, store: &impl KvsEngine) {
+    let mut rng = seeded_rng();
+
+    c.bench_function(name, |b| b.iter_batched(
+        || (get_random_string(&mut rng), get_random_string(&mut rng)),
+        |(key, value)| store.set(key, value).expect("failed to set value"),
+        BatchSize::SmallInput
+    ));
+}
+
+/// Measure getting random keys out of `READ_KEYS` keys written before the measurement
+fn bench_read(c: &mut Criterion, name: &str, store: &impl KvsEngine) {
+    let mut rng = seeded_rng();
+
+    // Fill the store first, so that there are always keys to read
+    let keys: Vec<String> = (0..READ_KEYS).map(|_| {
+        let key = get_random_string(&mut rng);
+        store.set(key.clone(), get_random_string(&mut rng)).expect("failed to set value");
+        key
+    }).collect();
+
+    c.bench_function(name, |b| b.iter_batched(
+        || keys[rng.gen_range(0..keys.len())].clone(),
+        |key| store.get(key).expect("failed to get value"),
+        BatchSize::SmallInput
+    ));
+}
+
 pub fn kvs_benchmark(c: &mut Criterion) {
     // Create temporary directory and create a new database on it
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path()).expect("unable to create KvStore at the given path");
+    let store = KvStore::open(temp_dir.path()).expect("unable to create KvStore at the given path");
 
-    // Create a random number generator and a keys array that will hold 100 keys
-    let mut rng = thread_rng();
-    let mut keys = Vec::new();
+    bench_write(c, "kvs_write", &store);
+    bench_read(c, "kvs_read", &store);
+}
 
-    c.bench_function("kvs_write", |b| b.iter(|| {
-        // Generate random string key and value
-        let key = get_random_string(&mut rng);
-        let value = get_random_string(&mut rng);
+pub fn kvs_bincode_benchmark(c: &mut Criterion) {
+    // Create temporary directory and create a new database on it that serializes commands with bincode
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { codec: Codec::Bincode, ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config).expect("unable to create KvStore at the given path");
+
+    bench_write(c, "kvs_bincode_write", &store);
+    bench_read(c, "kvs_bincode_read", &store);
+}
 
-        // Store key in array
-        keys.push(key.clone());
+pub fn kvs_buffer_benchmark(c: &mut Criterion) {
+    // Create temporary directory and create a new database on it with bigger buffers than the default 8 KiB
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { buf_capacity: 64 * 1024, ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config).expect("unable to create KvStore at the given path");
 
-        // Set key-value pair in the store
-        store.set(key, value).expect("failed to set value");
-    }));
+    bench_write(c, "kvs_64k_buffer_write", &store);
+    bench_read(c, "kvs_64k_buffer_read", &store);
+}
 
-    c.bench_function("kvs_read", |b| b.iter(|| {
-        // Retrieve random key from keys array
-        let key = &keys[rng.gen_range(0..keys.len())];
+pub fn kvs_miss_benchmark(c: &mut Criterion) {
+    // Create the same database with and without a bloom filter
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let bloom_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path()).expect("unable to create KvStore at the given path");
+    let config = KvStoreConfig { bloom_filter: true, ..KvStoreConfig::default() };
+    let bloom_store = KvStore::open_with_options(bloom_dir.path(), config).expect("unable to create KvStore at the given path");
+
+    for key_id in 0..10000 {
+        store.set(format!("key{}", key_id), format!("value{}", key_id)).expect("failed to set value");
+        bloom_store.set(format!("key{}", key_id), format!("value{}", key_id)).expect("failed to set value");
+    }
+
+    // Create a random number generator for the short missing keys, so that the lookup dominates
+    let mut rng = seeded_rng();
+
+    // Get random keys which are not in the store
+    c.bench_function("kvs_miss", |b| b.iter_batched(
+        || format!("missing{}", rng.gen::<u32>()),
+        |key| store.get(key).expect("failed to get value"),
+        BatchSize::SmallInput
+    ));
+
+    c.bench_function("kvs_bloom_miss", |b| b.iter_batched(
+        || format!("missing{}", rng.gen::<u32>()),
+        |key| bloom_store.get(key).expect("failed to get value"),
+        BatchSize::SmallInput
+    ));
+}
 
-        // Get key-value pair from the store
-        store.get(key.clone()).expect("failed to get value");
-    }));
+/// Index between 0 and `cdf.len()` drawn from a Zipf distribution, given its cumulative
+/// probabilities, so that the smallest indexes are drawn most of the time
+fn sample_zipf(rng: &mut impl Rng, cdf: &[f64]) -> usize {
+    let p: f64 = rng.gen();
+    cdf.partition_point(|&cumulative| cumulative < p).min(cdf.len() - 1)
+}
+
+pub fn kvs_cache_benchmark(c: &mut Criterion) {
+    // Create the same database with and without a value cache holding a tenth of the keys
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let cache_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path()).expect("unable to create KvStore at the given path");
+    let config = KvStoreConfig { value_cache: Some(CacheSize::Entries(100)), ..KvStoreConfig::default() };
+    let cache_store = KvStore::open_with_options(cache_dir.path(), config).expect("unable to create KvStore at the given path");
+
+    let mut rng = seeded_rng();
+    for key_id in 0..1000 {
+        let value: String = (&mut rng).sample_iter(&Alphanumeric).take(1000).map(char::from).collect();
+        store.set(format!("key{}", key_id), value.clone()).expect("failed to set value");
+        cache_store.set(format!("key{}", key_id), value).expect("failed to set value");
+    }
+
+    // Key i is read with a probability proportional to 1 / (i + 1), so a few hot keys get most reads
+    let weights: Vec<f64> = (0..1000).map(|key_id| 1.0 / (key_id + 1) as f64).collect();
+    let total: f64 = weights.iter().sum();
+    let cdf: Vec<f64> = weights.iter().scan(0.0, |cumulative, weight| {
+        *cumulative += weight / total;
+        Some(*cumulative)
+    }).collect();
+
+    c.bench_function("kvs_zipf_read", |b| b.iter_batched(
+        || format!("key{}", sample_zipf(&mut rng, &cdf)),
+        |key| store.get(key).expect("failed to get value"),
+        BatchSize::SmallInput
+    ));
+
+    c.bench_function("kvs_cached_zipf_read", |b| b.iter_batched(
+        || format!("key{}", sample_zipf(&mut rng, &cdf)),
+        |key| cache_store.get(key).expect("failed to get value"),
+        BatchSize::SmallInput
+    ));
 }
 
 pub fn sled_benchmark(c: &mut Criterion) {
     // Create temporary directory and create a new database on it
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = SledKvsEngine::open(temp_dir.path()).expect("unable to create Sled store at the given path");
+    let store = SledKvsEngine::open(temp_dir.path()).expect("unable to create Sled store at the given path");
 
-    // Create a random number generator and a keys array that will hold 100 keys
-    let mut rng = thread_rng();
-    let mut keys = Vec::new();
-
-    c.bench_function("sled_write", |b| b.iter(|| {
-        // Generate random string key and value
-        let key = get_random_string(&mut rng);
-        let value = get_random_string(&mut rng);
+    bench_write(c, "sled_write", &store);
+    bench_read(c, "sled_read", &store);
+}
 
-        // Store key in array
-        keys.push(key.clone());
+pub fn kvs_open_benchmark(c: &mut Criterion) {
+    // Create temporary directory and fill it with many log files
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
 
-        // Set key-value pair in the store
-        store.set(key, value).expect("failed to set value");
-    }));
+    for file in 0..50 {
+        // Every open starts a new log file
+        let store = KvStore::open(temp_dir.path()).expect("unable to create KvStore at the given path");
 
-    c.bench_function("sled_read", |b| b.iter(|| {
-        // Retrieve random key from keys array
-        let key = &keys[rng.gen_range(0..keys.len())];
+        for key_id in 0..1000 {
+            store.set(format!("key{}", key_id), format!("value{}", file)).expect("failed to set value");
+        }
+    }
 
-        // Get key-value pair from the store
-        store.get(key.clone()).expect("failed to get value");
+    c.bench_function("kvs_open", |b| b.iter(|| {
+        // Open the store, which loads every log file into the index
+        KvStore::open(temp_dir.path()).expect("unable to open KvStore at the given path");
     }));
 }
 
-criterion_group!(benches, kvs_benchmark, sled_benchmark);
+criterion_group!(benches, kvs_benchmark, kvs_bincode_benchmark, kvs_buffer_benchmark, kvs_miss_benchmark, kvs_cache_benchmark, sled_benchmark, kvs_open_benchmark);
 criterion_main!(benches);
\ No newline at end of file
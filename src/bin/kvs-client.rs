@@ -1,26 +1,29 @@
-use kvs::KvsClient;
-use kvs::Result;
+use kvs::{ExitStatus, KvsClient};
+use std::process::ExitCode;
+use std::time::Duration;
 use structopt::StructOpt;
 
-use slog::Drain;
-use slog::o;
-
-fn main() -> Result<()> {
-    // Setup logging
-    let decorator = slog_term::TermDecorator::new().build();
-    let drain = slog_term::CompactFormat::new(decorator).build().fuse();
-    let drain = slog_async::Async::new(drain).build().fuse();
-
-    let log = slog::Logger::root(drain, o!());
-
+fn main() -> ExitCode {
     // Store command line arguments in struct
     let opt = kvs::ClientOpt::from_args();
 
+    // Setup logging
+    let log = opt.log_format.build_logger(opt.log_level);
+
     // Setup KvsClient
-    let kvs_client = KvsClient::new(opt.addr, log);
+    let mut kvs_client = KvsClient::new(opt.addr, log);
+    kvs_client.set_connect_retry(opt.retries, Duration::from_millis(opt.retry_delay_ms));
+    kvs_client.set_timeout(opt.timeout_ms.map(Duration::from_millis));
+    kvs_client.set_nodelay(!opt.nagle);
 
-    // Run KvsClient
-    kvs_client.run(opt.command)?;
+    // Run KvsClient and turn its outcome into the exit code
+    let status = match kvs_client.run(opt.command, opt.output) {
+        Ok(()) => ExitStatus::Success,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitStatus::of(&e)
+        }
+    };
 
-    Ok(())
+    ExitCode::from(status.code())
 }
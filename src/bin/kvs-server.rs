@@ -1,60 +1,194 @@
-use kvs::{Engine, KvsEngine, KvsError, Result};
+use kvs::{Backoff, Engine, KvStoreConfig, KvsEngine, KvsError, Result, ServerCommand};
 use structopt::StructOpt;
-use std::env::current_dir;
 use std::fs;
-use slog::{Drain, o, info, warn};
+use slog::{info, warn};
 use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-fn get_current_engine(logger: &slog::Logger) -> Result<Option<Engine>> {
+/// Key used by the self-test, which is removed again once the test is done
+const SELF_TEST_KEY: &str = "__kvs_self_test__";
+
+/// Subdirectory of the data directory holding the engine's data
+const LOG_DIR: &str = "logs";
+
+/// Read the engine saved in the config file of the data directory
+///
+/// A config file that cannot be parsed is an error unless `force` is set, since starting with
+/// a different engine than the one that wrote the data directory can overwrite its data.
+fn get_current_engine(data_dir: &Path, force: bool, logger: &slog::Logger) -> Result<Option<Engine>> {
     // Check if config file exists and if it does not, return None
-    let config_file = current_dir()?.join(".config");
+    let config_file = data_dir.join(".config");
 
     if !config_file.exists() {
         return Ok(None);
     }
 
-    match fs::read_to_string(".config")?.parse() {
+    match fs::read_to_string(&config_file)?.parse() {
         Ok(engine) => Ok(Some(engine)),
-        Err(e) => {
+        Err(e) if force => {
             warn!(logger, "The contents of the config file are invalid: {}", e);
             Ok(None)
+        },
+        Err(e) => Err(KvsError::InvalidEngine(format!(
+            "unreadable ({}). Starting with another engine can overwrite the existing data, \
+            pass --force-engine to start anyway",
+            e
+        )))
+    }
+}
+
+/// Run a self-test step and log how long it took
+fn timed<T>(logger: &slog::Logger, step: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let start = Instant::now();
+    let result = f();
+
+    match &result {
+        Ok(_) => info!(logger, "Self-test step {} passed in {:?}", step, start.elapsed()),
+        Err(e) => warn!(logger, "Self-test step {} failed after {:?}: {}", step, start.elapsed(), e)
+    }
+
+    result
+}
+
+/// Set, read back and remove the self-test key, checking every result
+fn self_test_cycle(engine: &dyn KvsEngine, logger: &slog::Logger) -> Result<()> {
+    let key = SELF_TEST_KEY.to_owned();
+    let value = format!("{:?}", Instant::now());
+
+    timed(logger, "set", || engine.set(key.clone(), value.clone()))?;
+
+    timed(logger, "get", || match engine.get(key.clone())? {
+        Some(found) if found == value => Ok(()),
+        found => Err(KvsError::SelfTestFailed(format!("get returned {:?} instead of {:?}", found, value)))
+    })?;
+
+    timed(logger, "remove", || engine.remove(key.clone()))?;
+
+    timed(logger, "get removed", || match engine.get(key.clone())? {
+        None => Ok(()),
+        Some(found) => Err(KvsError::SelfTestFailed(format!("removed key still holds {:?}", found)))
+    })
+}
+
+/// Open the engine in the given directory and run the self-test on it
+fn self_test(engine: &Engine, data_dir: &Path, logger: &slog::Logger) -> Result<()> {
+    let start = Instant::now();
+
+    match engine {
+        Engine::Kvs => {
+            let store = timed(logger, "open", || kvs::KvStore::open(data_dir))?;
+            self_test_cycle(&store, logger)?;
+            timed(logger, "compact", || store.compact())?;
+
+            // Compaction must keep the removed key removed
+            timed(logger, "get compacted", || match store.get(SELF_TEST_KEY.to_owned())? {
+                None => Ok(()),
+                Some(found) => Err(KvsError::SelfTestFailed(format!("compacted key holds {:?}", found)))
+            })?;
+        },
+        Engine::Sled => {
+            // Sled compacts on its own, so there is no compaction step
+            let store = timed(logger, "open", || kvs::SledKvsEngine::open(data_dir))?;
+            self_test_cycle(&store, logger)?;
         }
     }
+
+    info!(logger, "Self-test of engine {} in {} passed in {:?}", engine, data_dir.display(), start.elapsed());
+
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    // Setup logging
-    let decorator = slog_term::TermDecorator::new().build();
-    let drain = slog_term::CompactFormat::new(decorator).build().fuse();
-    let drain = slog_async::Async::new(drain).build().fuse();
+/// Open the kvs engine in the given data directory and print what a compaction would reclaim
+fn compaction_preview(engine: &Engine, data_dir: &Path, logger: &slog::Logger) -> Result<()> {
+    if *engine != Engine::Kvs {
+        warn!(logger, "Engine {} compacts on its own, so there is no compaction to preview", engine);
+        return Ok(());
+    }
+
+    let config = KvStoreConfig { log_dir: Some(LOG_DIR.into()), ..KvStoreConfig::default() };
+    let preview = kvs::KvStore::open_with_options(data_dir, config)?.compaction_preview()?;
+
+    println!("Log files: {} bytes", preview.total_bytes);
+    println!("Live data: {} bytes", preview.live_bytes);
+    println!("Reclaimable: {} bytes", preview.reclaimable_bytes);
 
-    let log = slog::Logger::root(drain, o!());
+    Ok(())
+}
 
+fn main() -> Result<()> {
     // Store command line arguments in struct
     let opt = kvs::ServerOpt::from_args();
 
+    // Setup logging
+    let log = opt.log_format.build_logger(opt.log_level);
+
     // Check if choosen engine is different from the one previously saved in config file
-    if let Some(current_engine) = get_current_engine(&log)? {
+    if let Some(current_engine) = get_current_engine(&opt.data_dir, opt.force_engine, &log)? {
         if opt.engine != current_engine {
             return Err(KvsError::InvalidEngine(current_engine.to_string()));
         }
     }
 
+    // Validate the storage and exit without saving the engine or listening
+    if let Some(ServerCommand::SelfTest { data_dir }) = &opt.command {
+        return self_test(&opt.engine, data_dir, &log);
+    }
+
+    // Report what a compaction would reclaim and exit without saving the engine or listening
+    if let Some(ServerCommand::CompactionPreview) = &opt.command {
+        return compaction_preview(&opt.engine, &opt.data_dir, &log);
+    }
+
     // Open engine config file and create it if it does not exist
-    let mut config_file = fs::File::create(".config")?;
+    fs::create_dir_all(&opt.data_dir)?;
+    let mut config_file = fs::File::create(opt.data_dir.join(".config"))?;
 
     // Write choosen engine to config file
     write!(&mut config_file, "{}", opt.engine)?;
 
     // Choose engine based on command line argument
     let engine: Box<dyn KvsEngine> = match opt.engine {
-        Engine::Kvs => Box::new(kvs::KvStore::open("./logs")?),
-        Engine::Sled => Box::new(kvs::SledKvsEngine::open("./logs")?)
+        Engine::Kvs => {
+            let config = KvStoreConfig { log_dir: Some(LOG_DIR.into()), ..KvStoreConfig::default() };
+            Box::new(kvs::KvStore::open_with_options(&opt.data_dir, config)?)
+        },
+        Engine::Sled => {
+            let mut engine = kvs::SledKvsEngine::open(opt.data_dir.join(LOG_DIR))?;
+
+            let backoff = Backoff::new(Duration::from_millis(opt.flush_retry_delay_ms), Duration::from_secs(1))
+                .with_jitter(opt.flush_retry_jitter);
+            engine.set_flush_retry(opt.flush_retries, backoff);
+
+            Box::new(engine)
+        }
     };
 
     // Setup KvsServer
     info!(log, "Using engine {}", opt.engine);
-    let mut kvs_server = kvs::KvsServer::new(opt.addr, engine, log);
+    let mut kvs_server = kvs::KvsServer::with_addrs(opt.addr, engine, log);
+
+    if let Some(threads) = opt.threads {
+        kvs_server.set_threads(threads);
+    }
+
+    kvs_server.set_data_dir(fs::canonicalize(&opt.data_dir)?);
+    kvs_server.set_max_value_size(opt.max_value_size);
+    kvs_server.set_protocol(opt.protocol);
+    kvs_server.set_read_only(opt.read_only);
+    kvs_server.set_access_log(opt.access_log);
+    kvs_server.set_nodelay(!opt.nagle);
+    kvs_server.set_backlog(opt.backlog);
+    kvs_server.set_idle_timeout(opt.idle_timeout_ms.map(Duration::from_millis));
+
+    if !opt.allow_command.is_empty() {
+        kvs_server.set_allowed_commands(Some(opt.allow_command.into_iter().collect()));
+    }
+
+    // Read the listed keys before accepting connections
+    if let Some(warmup_file) = &opt.warmup_file {
+        kvs_server.warm_up(warmup_file)?;
+    }
 
     kvs_server.run()?;
 
@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::Command;
+
+/// Value of a key cached by a `KvsClient`
+#[derive(Debug)]
+struct CachedValue {
+    value: String,
+    /// Tick of the last time the value was used, its key in the recency map
+    last_used: u64,
+}
+
+/// Least recently used cache of the values a `KvsClient` read or wrote
+///
+/// Only values of existing keys are cached, so a missing key is always asked to the server.
+#[derive(Debug)]
+pub(crate) struct ClientCache {
+    /// Maximum number of cached values
+    capacity: usize,
+    values: HashMap<String, CachedValue>,
+    /// Keys by the tick of their last use, the least recently used first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl ClientCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self { capacity, values: HashMap::new(), recency: BTreeMap::new(), tick: 0 }
+    }
+
+    /// Returns the cached value of the key, if any
+    pub(crate) fn get(&mut self, key: &str) -> Option<String> {
+        let tick = self.next_tick();
+        let cached = self.values.get_mut(key)?;
+
+        self.recency.remove(&cached.last_used);
+        self.recency.insert(tick, key.to_owned());
+        cached.last_used = tick;
+
+        Some(cached.value.clone())
+    }
+
+    /// Caches the value of the key, evicting the least recently used value if the cache is full
+    pub(crate) fn insert(&mut self, key: String, value: String) {
+        self.remove(&key);
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        let tick = self.next_tick();
+        self.recency.insert(tick, key.clone());
+        self.values.insert(key, CachedValue { value, last_used: tick });
+
+        while self.values.len() > self.capacity {
+            match self.recency.pop_first() {
+                Some((_, oldest)) => self.remove(&oldest),
+                None => break
+            }
+        }
+    }
+
+    /// Removes the cached value of the key, if any
+    pub(crate) fn remove(&mut self, key: &str) {
+        if let Some(cached) = self.values.remove(key) {
+            self.recency.remove(&cached.last_used);
+        }
+    }
+
+    /// Removes every cached value
+    pub(crate) fn clear(&mut self) {
+        self.values.clear();
+        self.recency.clear();
+    }
+
+    /// Removes the cached values of the keys a command sent by the client may write
+    ///
+    /// Batch markers do not tell which keys the batch writes, so they clear the whole cache.
+    pub(crate) fn invalidate_written(&mut self, command: &Command) {
+        match command {
+            Command::Rename { from, to } => {
+                self.remove(from);
+                self.remove(to);
+            },
+            Command::RemovePrefix { prefix } => {
+                let keys: Vec<String> = self.values.keys().filter(|key| key.starts_with(prefix.as_str())).cloned().collect();
+                keys.iter().for_each(|key| self.remove(key));
+            },
+            Command::Clear | Command::BatchBegin { .. } | Command::BatchCommit { .. } => self.clear(),
+            command if command.is_mutating() => {
+                if let Some(key) = command.key() {
+                    self.remove(key);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
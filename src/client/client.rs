@@ -1,62 +1,627 @@
-use serde::Deserialize;
-use serde_json::Deserializer;
 use slog::{Logger, info, error, debug, warn};
-use std::net::{SocketAddr, TcpStream};
-use std::io::{BufReader, BufWriter, Write};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
 
-use crate::{Command, CommandResponse, KvsError, Result};
+use super::cache::ClientCache;
+use crate::{read_frame, write_frame, Backoff, MAX_CHUNK_SIZE, Command, CommandResponse, KvsError, KeyStat, OutputFormat, Result, ServerAddr, ServerInfo, SizeStats, Stream};
 
 pub struct KvsClient {
-    addr: SocketAddr,
-    logger: Logger
+    addr: ServerAddr,
+    logger: Logger,
+    connect_retries: u32,
+    connect_backoff: Backoff,
+    timeout: Option<Duration>,
+    nodelay: bool,
+    connection: Mutex<Option<Connection>>,
+    cache: Mutex<Option<ClientCache>>
+}
+
+/// Connection kept open between requests once `KvsClient::connect` is called
+struct Connection {
+    reader: BufReader<Stream>,
+    writer: BufWriter<Stream>
+}
+
+impl Connection {
+    fn new(stream: Stream) -> Result<Self> {
+        Ok(Self { reader: BufReader::new(stream.try_clone()?), writer: BufWriter::new(stream) })
+    }
+
+    /// Send a command and wait for the server's response to it
+    ///
+    /// Returns `None` if the server closed the connection before responding.
+    fn send(&mut self, command: &Command) -> Result<Option<CommandResponse>> {
+        write_frame(&mut self.writer, command)?;
+
+        read_frame(&mut self.reader)
+    }
 }
 
 impl KvsClient {
-     pub fn new(addr: SocketAddr, logger: Logger) -> Self {
-        Self { addr, logger }
+    /// Creates a client for the server at a TCP address or a Unix domain socket
+    pub fn new(addr: impl Into<ServerAddr>, logger: Logger) -> Self {
+        Self {
+            addr: addr.into(),
+            logger,
+            connect_retries: 0,
+            connect_backoff: Backoff::new(Duration::from_millis(100), Duration::from_secs(5)),
+            timeout: None,
+            nodelay: true,
+            connection: Mutex::new(None),
+            cache: Mutex::new(None)
+        }
+    }
+
+    /// Open a connection to the server which is kept open and reused by every following
+    /// `get`, `set`, `remove` and `run` call, instead of connecting once per command
+    ///
+    /// If the server closes the connection, it is opened again on the next command. A command
+    /// which cannot change the store is then sent once more. Any other command may have run
+    /// before the connection broke, so its error is returned instead of running it twice,
+    /// leaving the caller to decide whether to send it again.
+    pub fn connect(&mut self) -> Result<()> {
+        let connection = Connection::new(self.connect_with_retry()?)?;
+        *self.connection.get_mut().expect("Client connection lock poisoned") = Some(connection);
+
+        Ok(())
+    }
+
+    /// Sets how many times a failed connection to the server is retried and the delay before
+    /// the first retry, which doubles after every attempt.
+    ///
+    /// Connections are not retried by default.
+    pub fn set_connect_retry(&mut self, retries: u32, delay: Duration) {
+        self.connect_retries = retries;
+        self.connect_backoff = Backoff::new(delay, Duration::from_secs(5));
+    }
+
+    /// Sets the time after which connecting to the server, sending a command to it or waiting
+    /// for its response fails with `KvsError::Timeout`, or removes it with `None`.
+    ///
+    /// There is no timeout by default. It applies to connections opened afterwards.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Sets whether TCP_NODELAY is set on connections to the server, which sends commands
+    /// right away instead of letting Nagle's algorithm delay them.
+    ///
+    /// It is set by default. It applies to connections opened afterwards.
+    pub fn set_nodelay(&mut self, nodelay: bool) {
+        self.nodelay = nodelay;
+    }
+
+    /// Sets the number of values cached by `get` and `set` so that a `get` of a cached key
+    /// is answered without asking the server, or disables the cache with `None`.
+    ///
+    /// The cache is disabled by default. When it is full, the least recently used value is
+    /// evicted, and only values of existing keys are cached.
+    ///
+    /// A cached value is only up to date while every write to its key goes through this
+    /// client. It is out of date after the key is written by another client or connection,
+    /// or expires on the server, until `invalidate` or `invalidate_all` is called. Writes this
+    /// client sends with `remove`, `run`, `run_batch` or `transaction` remove the cached
+    /// values of the keys they write, or every cached value if those keys are not known.
+    pub fn set_value_cache(&mut self, capacity: Option<usize>) {
+        *self.cache.get_mut().expect("Client cache lock poisoned") = capacity.map(ClientCache::new);
     }
 
-    /// Run client
-    pub fn run(&self, command: Command) -> Result<()> {
-        // Connect to kvs-server
-        match TcpStream::connect(&self.addr) {
+    /// Removes the cached value of a key, so the next `get` of it asks the server
+    pub fn invalidate(&self, key: &str) {
+        if let Some(cache) = self.cache.lock().expect("Client cache lock poisoned").as_mut() {
+            cache.remove(key);
+        }
+    }
+
+    /// Removes every cached value, so the next `get` of any key asks the server
+    pub fn invalidate_all(&self) {
+        if let Some(cache) = self.cache.lock().expect("Client cache lock poisoned").as_mut() {
+            cache.clear();
+        }
+    }
+
+    /// Runs a function on the value cache if it is enabled
+    fn with_cache<T>(&self, f: impl FnOnce(&mut ClientCache) -> T) -> Option<T> {
+        self.cache.lock().expect("Client cache lock poisoned").as_mut().map(f)
+    }
+
+    /// Turns an I/O error caused by the configured timeout into `KvsError::Timeout`
+    fn timeout_error(&self, err: KvsError) -> KvsError {
+        match (err, self.timeout) {
+            (KvsError::IOError(e), Some(timeout)) if is_timeout(&e) => KvsError::Timeout(timeout),
+            (err, _) => err
+        }
+    }
+
+    /// Connect to the server, retrying with exponential backoff as configured
+    ///
+    /// Returns the error of the last attempt once retries are exhausted.
+    fn connect_with_retry(&self) -> Result<Stream> {
+        let mut attempt = 0;
+
+        let connection = self.connect_backoff.retry(self.connect_retries, |_| true, || {
+            attempt += 1;
+
+            Stream::connect_timeout(&self.addr, self.timeout).and_then(|stream| {
+                stream.set_nodelay(self.nodelay)?;
+                Ok(stream)
+            }).map_err(|e| {
+                warn!(self.logger, "Connection attempt {} failed: {}", attempt, e);
+                e
+            })
+        });
+
+        match connection {
             Ok(stream) => {
                 info!(self.logger, "Successfully connected to server in {}", self.addr);
-                debug!(self.logger, "Sending command: {:?}", command);
+                Ok(stream)
+            },
+            Err(e) => {
+                error!(self.logger, "Failed to connect: {}", e);
+                Err(self.timeout_error(KvsError::IOError(e)))
+            }
+        }
+    }
 
-                // Create writer for stream to send command to server
-                let mut writer = BufWriter::new(&stream);
-                serde_json::to_writer(&mut writer, &command)?;
-                writer.flush()?;
+    /// Get the string value of a given string key, `None` if the key does not exist
+    ///
+    /// With the value cache enabled, a cached value is returned without asking the server.
+    pub fn get(&self, key: String) -> Result<Option<String>> {
+        if let Some(value) = self.with_cache(|cache| cache.get(&key)).flatten() {
+            return Ok(Some(value));
+        }
 
-                // Create reader for stream to receive response from server
-                let reader = BufReader::new(&stream);
-                let mut deserializer = Deserializer::from_reader(reader);
+        match self.request(Command::Get { key: key.clone(), out: None })? {
+            CommandResponse::Value(value) => {
+                self.with_cache(|cache| cache.insert(key, value.clone()));
+                Ok(Some(value))
+            },
+            CommandResponse::KeyNotFound => Ok(None),
+            response => Err(self.response_error(response))
+        }
+    }
 
-                let response = CommandResponse::deserialize(&mut deserializer)?;
-                debug!(self.logger, "Received response: {:?}", &response);
+    /// Get the string value of a given string key and write it to the writer a chunk at a time,
+    /// so that the whole value is never held in memory. Returns whether the key exists, in
+    /// which case the writer is flushed.
+    ///
+    /// The value cache is neither read nor filled. If the transfer fails part way, the writer
+    /// holds the start of the value.
+    pub fn get_to(&self, key: String, writer: &mut impl Write) -> Result<bool> {
+        self.request_then(Command::GetChunked { key, chunk_size: MAX_CHUNK_SIZE }, |response, connection| {
+            let len = match response {
+                CommandResponse::ValueStart(len) => len,
+                CommandResponse::KeyNotFound => return Ok(false),
+                response => return Err(self.response_error(response))
+            };
 
-                match response {
-                    CommandResponse::Value(value) =>  {
-                        println!("{}", value);
-                        Ok(())
-                    },
-                    CommandResponse::Success => Ok(()),
-                    CommandResponse::KeyNotFound => {
-                        warn!(self.logger, "Key not found");
-                        println!("Key not found");
-                        Ok(())
+            let mut received = 0;
+            while received < len {
+                match read_frame(&mut connection.reader).map_err(|e| self.timeout_error(e))? {
+                    Some(CommandResponse::Chunk(chunk)) => {
+                        writer.write_all(chunk.as_bytes())?;
+                        received += chunk.len() as u64;
                     },
-                    CommandResponse::Error(e) => {
-                        error!(self.logger, "{}", e);
-                        Err(KvsError::RequestError(e))
-                    }
+                    Some(response) => return Err(self.response_error(response)),
+                    None => return Err(KvsError::RequestError(format!(
+                        "Connection closed by server after {} of {} bytes of the value",
+                        received,
+                        len
+                    )))
+                }
+            }
+
+            writer.flush()?;
+            Ok(true)
+        })
+    }
+
+    /// Write the value of a key to a new file, or replace the file, a chunk at a time like
+    /// `get_to`, and return whether the key exists
+    ///
+    /// The file is removed if the key does not exist.
+    fn get_to_file(&self, key: String, path: &Path) -> Result<bool> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        if self.get_to(key, &mut file)? {
+            return Ok(true);
+        }
+
+        drop(file);
+        fs::remove_file(path)?;
+
+        Ok(false)
+    }
+
+    /// Set the value of a string key to a string
+    ///
+    /// With the value cache enabled, the value is cached once the server stored it.
+    pub fn set(&self, key: String, value: String) -> Result<()> {
+        let cached = self.with_cache(|_| (key.clone(), value.clone()));
+
+        match self.request(Command::Set { key, value, expires_at: None })? {
+            CommandResponse::Success => {
+                if let Some((key, value)) = cached {
+                    self.with_cache(|cache| cache.insert(key, value));
                 }
+                Ok(())
             },
-            Err(e) => {
-                error!(self.logger, "Failed to connect: {}", e);
-                Err(KvsError::IOError(e))
+            response => Err(self.response_error(response))
+        }
+    }
+
+    /// Remove a given string key
+    ///
+    /// It returns `KvsError::KeyNotFound` if the key does not exist.
+    pub fn remove(&self, key: String) -> Result<()> {
+        match self.request(Command::Remove { key })? {
+            CommandResponse::Success => Ok(()),
+            response => Err(self.response_error(response))
+        }
+    }
+
+    /// Run a command from the command line and print its result to stdout in the given format
+    ///
+    /// A Get of a missing key prints "Key not found", or `null` in JSON, and succeeds.
+    /// Any other error is returned without printing anything, so that `ExitStatus::of`
+    /// can tell it apart.
+    ///
+    /// A Get with an output file writes the value to it with `get_to` instead of printing it,
+    /// and a GetChunked writes it to stdout with `get_to`, both without a trailing newline.
+    pub fn run(&self, command: Command, output: OutputFormat) -> Result<()> {
+        let response = match command {
+            Command::Get { key, out: Some(path) } => chunked_response(self.get_to_file(key, &path)?),
+            Command::GetChunked { key, .. } => chunked_response(self.get_to(key, &mut io::stdout().lock())?),
+            command => self.request(command)?
+        };
+
+        let printed = match output {
+            OutputFormat::Text => self.text_output(response)?,
+            OutputFormat::Json => self.json_output(response)?
+        };
+
+        if let Some(printed) = printed {
+            println!("{}", printed);
+        }
+
+        Ok(())
+    }
+
+    /// Text printed for a response, if any
+    fn text_output(&self, response: CommandResponse) -> Result<Option<String>> {
+        let printed = match response {
+            CommandResponse::Value(value) => value,
+            CommandResponse::Values(values) => values
+                .into_iter()
+                .map(|value| value.unwrap_or_else(|| "Key not found".to_owned()))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            CommandResponse::Exists(exists) => exists.to_string(),
+            CommandResponse::Swapped(swapped) => swapped.to_string(),
+            CommandResponse::Count(count) => count.to_string(),
+            CommandResponse::Keys(keys, _) if keys.is_empty() => return Ok(None),
+            CommandResponse::Keys(keys, _) => keys.join("\n"),
+            CommandResponse::Integer(value) => value.to_string(),
+            CommandResponse::Pong(version) => version,
+            CommandResponse::ServerInfo(info) => info.to_string(),
+            CommandResponse::SizeStats(stats) => stats.to_string(),
+            CommandResponse::KeyStat(stat) => stat.to_string(),
+            CommandResponse::Success | CommandResponse::Queued => return Ok(None),
+            CommandResponse::Committed(count) => count.to_string(),
+            CommandResponse::KeyNotFound => {
+                warn!(self.logger, "Key not found");
+                "Key not found".to_owned()
+            },
+            response => return Err(self.response_error(response))
+        };
+
+        Ok(Some(printed))
+    }
+
+    /// JSON value printed for a response, if any
+    fn json_output(&self, response: CommandResponse) -> Result<Option<String>> {
+        let printed = match response {
+            CommandResponse::Value(value) => serde_json::to_string(&value)?,
+            CommandResponse::Values(values) => serde_json::to_string(&values)?,
+            CommandResponse::Exists(exists) | CommandResponse::Swapped(exists) => serde_json::to_string(&exists)?,
+            CommandResponse::Count(count) => serde_json::to_string(&count)?,
+            CommandResponse::Committed(count) => serde_json::to_string(&count)?,
+            CommandResponse::Keys(keys, next) => serde_json::to_string(&serde_json::json!({ "keys": keys, "next": next }))?,
+            CommandResponse::Integer(value) => serde_json::to_string(&value)?,
+            CommandResponse::Pong(version) => serde_json::to_string(&version)?,
+            CommandResponse::ServerInfo(info) => serde_json::to_string(&info)?,
+            CommandResponse::SizeStats(stats) => serde_json::to_string(&stats)?,
+            CommandResponse::KeyStat(stat) => serde_json::to_string(&stat)?,
+            CommandResponse::Success | CommandResponse::Queued => return Ok(None),
+            CommandResponse::KeyNotFound => "null".to_owned(),
+            response => return Err(self.response_error(response))
+        };
+
+        Ok(Some(printed))
+    }
+
+    /// Send a command and return the server's response to it
+    ///
+    /// The command goes over the connection opened by `connect` if there is one,
+    /// and over a new connection otherwise.
+    fn request(&self, command: Command) -> Result<CommandResponse> {
+        self.request_then(command, |response, _| Ok(response))
+    }
+
+    /// Send a command like `request` and hand the server's response to `then`, together with
+    /// the connection it came from, so that `then` can read the responses following it
+    ///
+    /// A connection opened by `connect` is closed if `then` fails, since responses it did not
+    /// read would be taken for the ones of the next command.
+    fn request_then<T>(&self, command: Command, then: impl FnOnce(CommandResponse, &mut Connection) -> Result<T>) -> Result<T> {
+        // Span around the request with its outcome and latency recorded once the response arrives
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "request",
+            command = command.name(),
+            key = command.key().unwrap_or_default(),
+            outcome = tracing::field::Empty,
+            latency_us = tracing::field::Empty
+        ).entered();
+        #[cfg(feature = "tracing")]
+        let start = std::time::Instant::now();
+
+        debug!(self.logger, "Sending command: {:?}", command);
+        self.with_cache(|cache| cache.invalidate_written(&command));
+
+        let mut connection = self.connection.lock().expect("Client connection lock poisoned");
+        // Connection opened for this command only, if there is no connection opened by `connect`
+        let mut single_use = None;
+
+        let response = match connection.as_mut() {
+            Some(open) => match open.send(&command).map_err(|e| self.timeout_error(e)) {
+                Ok(Some(response)) => Some(response),
+                // A late response would be taken for the one of the next command
+                Err(e @ KvsError::Timeout(_)) => {
+                    *connection = None;
+                    return Err(e);
+                },
+                // The server dropped the connection, so it is opened again
+                Ok(None) | Err(KvsError::IOError(_)) if !command.is_mutating() => {
+                    warn!(self.logger, "Connection closed by server, reconnecting");
+                    let reopened = connection.insert(Connection::new(self.connect_with_retry()?)?);
+
+                    reopened.send(&command).map_err(|e| self.timeout_error(e))?
+                },
+                // The server may have run the command before dropping the connection
+                Ok(None) | Err(KvsError::IOError(_)) => {
+                    warn!(self.logger, "Connection closed by server, reconnecting without sending the command again");
+                    *connection = Some(Connection::new(self.connect_with_retry()?)?);
+
+                    return Err(KvsError::RequestError(format!(
+                        "Connection closed by server before responding to the {} command, which may have run",
+                        command.name()
+                    )));
+                },
+                Err(e) => return Err(e)
+            },
+            None => single_use.insert(Connection::new(self.connect_with_retry()?)?)
+                .send(&command)
+                .map_err(|e| self.timeout_error(e))?
+        };
+
+        let response = response.ok_or_else(|| {
+            KvsError::RequestError("Connection closed by server".to_owned())
+        })?;
+        debug!(self.logger, "Received response: {:?}", &response);
+
+        #[cfg(feature = "tracing")]
+        {
+            span.record("outcome", response.name());
+            span.record("latency_us", start.elapsed().as_micros() as u64);
+            tracing::info!("response received");
+        }
+
+        match single_use.as_mut() {
+            Some(single_use) => then(response, single_use),
+            None => {
+                let open = connection.as_mut().expect("Client connection missing after a response");
+                let result = then(response, open);
+
+                if result.is_err() {
+                    *connection = None;
+                }
+
+                result
+            }
+        }
+    }
+
+    /// Turn a response which is not the expected one into an error
+    ///
+    /// Error responses are rebuilt into the error the server reported.
+    fn response_error(&self, response: CommandResponse) -> KvsError {
+        match response {
+            CommandResponse::Error(kind, e) => {
+                error!(self.logger, "{}", e);
+                kind.into_error(e)
+            },
+            response => KvsError::RequestError(format!("Unexpected response: {:?}", response))
+        }
+    }
+
+    /// Run the given Set and Remove commands as a single transaction
+    ///
+    /// All commands are sent over one connection between a Begin and a Commit,
+    /// so the server applies either all of them or none of them.
+    ///
+    /// Returns the number of committed commands.
+    pub fn transaction(&self, commands: Vec<Command>) -> Result<usize> {
+        let stream = self.connect_with_retry()?;
+
+        let mut writer = BufWriter::new(&stream);
+        let mut reader = BufReader::new(&stream);
+
+        // Send a command and wait for the server's response to it
+        let mut send = |command: &Command| -> Result<CommandResponse> {
+            debug!(self.logger, "Sending command: {:?}", command);
+            self.with_cache(|cache| cache.invalidate_written(command));
+            write_frame(&mut writer, command).map_err(|e| self.timeout_error(e))?;
+
+            let response: CommandResponse = read_frame(&mut reader).map_err(|e| self.timeout_error(e))?.ok_or_else(|| {
+                KvsError::RequestError("Connection closed by server".to_owned())
+            })?;
+            debug!(self.logger, "Received response: {:?}", &response);
+
+            Ok(response)
+        };
+
+        if let CommandResponse::Error(kind, e) = send(&Command::Begin)? {
+            error!(self.logger, "{}", e);
+            return Err(kind.into_error(e));
+        }
+
+        for command in &commands {
+            if let CommandResponse::Error(kind, e) = send(command)? {
+                error!(self.logger, "{}", e);
+                send(&Command::Rollback)?;
+                return Err(kind.into_error(e));
+            }
+        }
+
+        match send(&Command::Commit)? {
+            CommandResponse::Committed(count) => Ok(count),
+            CommandResponse::Error(kind, e) => {
+                error!(self.logger, "{}", e);
+                Err(kind.into_error(e))
+            },
+            response => Err(KvsError::RequestError(format!("Unexpected response: {:?}", response)))
+        }
+    }
+
+    /// Check that the server is up without touching its engine
+    ///
+    /// Returns the version of the server.
+    pub fn ping(&self) -> Result<String> {
+        match self.run_batch(vec![Command::Ping])?.pop() {
+            Some(CommandResponse::Pong(version)) => Ok(version),
+            response => Err(KvsError::RequestError(format!("Unexpected response: {:?}", response)))
+        }
+    }
+
+    /// Get the engine, version, data directory and uptime of the server
+    ///
+    /// `ServerInfo` implements `Display` to print them one per line.
+    pub fn server_info(&self) -> Result<ServerInfo> {
+        match self.request(Command::ServerInfo)? {
+            CommandResponse::ServerInfo(info) => Ok(info),
+            response => Err(self.response_error(response))
+        }
+    }
+
+    /// Get the keys starting with the prefix in key order, every key if it is `None`,
+    /// stopping after `limit` keys if it is given
+    ///
+    /// The keys are requested one page at a time, so that no response holds more than
+    /// `MAX_KEYS_PER_RESPONSE` keys however many keys there are.
+    pub fn keys(&self, prefix: Option<String>, limit: Option<usize>) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+        let mut after = None;
+
+        loop {
+            let remaining = limit.map(|limit| limit - keys.len());
+            if remaining == Some(0) {
+                return Ok(keys);
             }
+
+            match self.request(Command::Keys { prefix: prefix.clone(), after: after.take(), limit: remaining })? {
+                CommandResponse::Keys(page, next) => {
+                    keys.extend(page);
+
+                    match next {
+                        Some(next) => after = Some(next),
+                        None => return Ok(keys)
+                    }
+                },
+                response => return Err(self.response_error(response))
+            }
+        }
+    }
+
+    /// Get the number of keys in the server's store with the total, average and biggest size of their values
+    pub fn size_stats(&self) -> Result<SizeStats> {
+        match self.request(Command::Stats)? {
+            CommandResponse::SizeStats(stats) => Ok(stats),
+            response => Err(self.response_error(response))
+        }
+    }
+
+    /// Get the log file, size and expiry of the value of a key, or `None` if it is missing
+    pub fn stat_key(&self, key: String) -> Result<Option<KeyStat>> {
+        match self.request(Command::Stat { key })? {
+            CommandResponse::KeyStat(stat) => Ok(Some(stat)),
+            CommandResponse::KeyNotFound => Ok(None),
+            response => Err(self.response_error(response))
         }
     }
-}
\ No newline at end of file
+
+    /// Send all given commands over one connection and collect the server's responses
+    ///
+    /// Commands are written while responses are being read, so the batch costs a single
+    /// round-trip. The server runs the commands in the given order and the response at
+    /// each position belongs to the command at the same position.
+    ///
+    /// A failing command does not abort the batch: its response is a
+    /// `CommandResponse::Error` and the following commands still run. An error is only
+    /// returned if the connection fails or the server closes it before answering every command.
+    pub fn run_batch(&self, commands: Vec<Command>) -> Result<Vec<CommandResponse>> {
+        let stream = self.connect_with_retry()?;
+        debug!(self.logger, "Sending {} commands", commands.len());
+        self.with_cache(|cache| commands.iter().for_each(|command| cache.invalidate_written(command)));
+
+        thread::scope(|scope| {
+            // Writing on its own thread keeps the server from blocking on unread responses
+            let sender = scope.spawn(|| -> Result<()> {
+                let mut writer = BufWriter::new(&stream);
+
+                for command in &commands {
+                    write_frame(&mut writer, command).map_err(|e| self.timeout_error(e))?;
+                }
+
+                Ok(())
+            });
+
+            let mut reader = BufReader::new(&stream);
+            let mut responses = Vec::with_capacity(commands.len());
+
+            while responses.len() < commands.len() {
+                match read_frame::<_, CommandResponse>(&mut reader).map_err(|e| self.timeout_error(e))? {
+                    Some(response) => responses.push(response),
+                    None => return Err(KvsError::RequestError(format!(
+                        "Connection closed by server after {} of {} responses",
+                        responses.len(),
+                        commands.len()
+                    )))
+                }
+            }
+
+            sender.join().expect("Batch writer thread panicked")?;
+            debug!(self.logger, "Received {} responses", responses.len());
+
+            Ok(responses)
+        })
+    }
+}
+
+/// Response printed by `KvsClient::run` once a value was written by `get_to`, or was not found
+fn chunked_response(found: bool) -> CommandResponse {
+    match found {
+        true => CommandResponse::Success,
+        false => CommandResponse::KeyNotFound
+    }
+}
+
+/// Whether an I/O error comes from a read, write or connect timeout, which is reported as
+/// `WouldBlock` on some platforms
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock)
+}
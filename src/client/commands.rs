@@ -1,17 +1,237 @@
-use std::net::SocketAddr;
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+use std::str::FromStr;
 use structopt::StructOpt;
 use serde::{Serialize, Deserialize};
 
+use crate::{KvsError, LoggerFormat, Result, ServerAddr, LOG_LEVELS};
+use crate::util::logging::parse_log_level;
+
 #[derive(Debug, StructOpt, PartialEq, Serialize, Deserialize)]
 /// Command types received from the command line interface
 pub enum Command {
     /// Get the string value of a given string key
-    Get { key: String },
+    Get {
+        key: String,
+        /// Write the value to this file a chunk at a time instead of printing it, so that
+        /// the client never holds the whole value in memory. It is not sent to the server.
+        #[structopt(long, parse(from_os_str))]
+        #[serde(skip)]
+        out: Option<PathBuf>
+    },
+    /// Get the string value of a given string key as a `CommandResponse::ValueStart` response
+    /// with its length, followed by `CommandResponse::Chunk` responses of at most `chunk_size`
+    /// bytes, unless a single character is bigger. Only `KvsClient::get_to` reads them.
+    #[structopt(skip)]
+    GetChunked { key: String, chunk_size: u32 },
+    /// Get the string values of the given string keys
+    GetMany {
+        #[structopt(required = true)]
+        keys: Vec<String>
+    },
     /// Set the value of a string key to a string
-    Set { key: String, value: String},
+    Set {
+        key: String,
+        value: String,
+        /// Time after which the key counts as removed, in milliseconds since the Unix epoch.
+        /// Only `KvStore` stores it, so the server and the sled engine reject it.
+        #[structopt(skip)]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expires_at: Option<u64>
+    },
     /// Remove a given string key
     #[structopt(name="rm")]
     Remove { key: String },
+    /// Remove every key starting with a given string prefix
+    RemovePrefix { prefix: String },
+    /// Check whether a given string key exists
+    Exists { key: String },
+    /// Count the keys in the store
+    Len,
+    /// List the keys in key order, at most one page of them per response
+    Keys {
+        /// Only list the keys starting with this string, every key is listed if omitted
+        #[structopt(long)]
+        prefix: Option<String>,
+        /// Only list the keys after this one, as returned to continue from the previous page
+        #[structopt(long)]
+        after: Option<String>,
+        /// Maximum number of keys to list, which the server caps
+        #[structopt(long)]
+        limit: Option<usize>
+    },
+    /// Check that the server is up and get its version
+    Ping,
+    /// Get the engine, version, data directory and uptime of the server
+    ServerInfo,
+    /// Get the number of keys with the total, average and biggest size of their values
+    Stats,
+    /// Get the log file, size and expiry of the value of a given string key without reading it
+    Stat { key: String },
+    /// Remove every key in the store
+    Clear,
+    /// Move the value of a given string key to another string key
+    Rename { from: String, to: String },
+    /// Set or remove a given string key only if it holds the expected value
+    Cas {
+        key: String,
+        /// Value the key must hold, the key must not exist if omitted
+        #[structopt(long)]
+        expected: Option<String>,
+        /// Value to set, the key is removed if omitted
+        #[structopt(long)]
+        new: Option<String>
+    },
+    /// Add a number to the integer value of a given string key, which counts as 0 if missing
+    #[structopt(setting = structopt::clap::AppSettings::AllowNegativeNumbers)]
+    Incr { key: String, delta: i64 },
+    /// Append a string to the value of a given string key, which counts as empty if missing
+    Append { key: String, value: String },
+    /// Set the value of a string key to arbitrary bytes
+    #[structopt(skip)]
+    SetBytes { key: String, value: Vec<u8> },
+    /// Start a transaction on the current connection
+    #[structopt(skip)]
+    Begin,
+    /// Atomically apply the commands buffered since `Begin`
+    #[structopt(skip)]
+    Commit,
+    /// Discard the commands buffered since `Begin`
+    #[structopt(skip)]
+    Rollback,
+    /// Marks the start of `count` commands written to the log as a single batch
+    #[structopt(skip)]
+    BatchBegin { count: u64 },
+    /// Marks the end of a batch, whose commands only take effect once it is in the log
+    #[structopt(skip)]
+    BatchCommit { count: u64 },
+}
+
+/// Defines `Command::name` and `Command::names` from a single list of the command types
+/// and their names, split between the commands clients send and the ones only written
+/// to the log, so that `names` cannot miss a command clients can send
+macro_rules! command_names {
+    (sent: { $($sent:ident => $sent_name:literal),* $(,)? }, logged: { $($logged:ident => $logged_name:literal),* $(,)? }) => {
+        impl Command {
+            /// Name of the command type
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(Command::$sent { .. } => $sent_name,)*
+                    $(Command::$logged { .. } => $logged_name,)*
+                }
+            }
+
+            /// Names of the commands clients can send, as returned by `name`
+            pub fn names() -> &'static [&'static str] {
+                &[$($sent_name),*]
+            }
+        }
+    };
+}
+
+command_names! {
+    sent: {
+        Get => "get",
+        GetChunked => "get_chunked",
+        GetMany => "get_many",
+        Set => "set",
+        Remove => "rm",
+        RemovePrefix => "remove_prefix",
+        Exists => "exists",
+        Len => "len",
+        Keys => "keys",
+        Ping => "ping",
+        ServerInfo => "server_info",
+        Stats => "stats",
+        Stat => "stat",
+        Clear => "clear",
+        Rename => "rename",
+        Cas => "cas",
+        Incr => "incr",
+        Append => "append",
+        SetBytes => "set_bytes",
+        Begin => "begin",
+        Commit => "commit",
+        Rollback => "rollback",
+    },
+    logged: {
+        BatchBegin => "batch_begin",
+        BatchCommit => "batch_commit",
+    }
+}
+
+impl Command {
+    /// Whether the command can change the contents of the store
+    ///
+    /// Transaction commands are not, since the commands they apply are checked when queued.
+    pub fn is_mutating(&self) -> bool {
+        match self {
+            Command::Set { .. } | Command::Remove { .. } | Command::RemovePrefix { .. } | Command::Clear => true,
+            Command::Rename { .. } | Command::Cas { .. } | Command::Incr { .. } | Command::Append { .. } => true,
+            Command::SetBytes { .. } | Command::BatchBegin { .. } | Command::BatchCommit { .. } => true,
+            Command::Get { .. } | Command::GetMany { .. } | Command::Exists { .. } | Command::Len | Command::Keys { .. } => false,
+            Command::GetChunked { .. } => false,
+            Command::Ping | Command::Stat { .. } => false,
+            Command::ServerInfo | Command::Stats | Command::Begin | Command::Commit | Command::Rollback => false,
+        }
+    }
+
+    /// Checks that every key of the command is not empty and that the value it sets, if any,
+    /// is not bigger than the given maximum size in bytes
+    ///
+    /// It returns `KvsError::InvalidKey` for an empty key and `KvsError::ValueTooLarge`
+    /// for a value that is too big.
+    pub fn validate(&self, max_value_size: Option<u64>) -> Result<()> {
+        if let Some(key) = self.key() {
+            validate_key(key)?;
+        }
+
+        if let Command::Rename { to, .. } = self {
+            validate_key(to)?;
+        }
+
+        if let Command::GetMany { keys } = self {
+            keys.iter().try_for_each(|key| validate_key(key))?;
+        }
+
+        let size = match self {
+            Command::Set { value, .. } => value.len(),
+            Command::SetBytes { value, .. } => value.len(),
+            Command::Cas { new: Some(value), .. } => value.len(),
+            Command::Append { value, .. } => value.len(),
+            _ => return Ok(())
+        } as u64;
+
+        match max_value_size {
+            Some(max) if size > max => Err(KvsError::ValueTooLarge { size, max }),
+            _ => Ok(())
+        }
+    }
+
+    /// Key targeted by the command, if any
+    pub fn key(&self) -> Option<&str> {
+        match self {
+            Command::Get { key, .. } | Command::Set { key, .. } | Command::Remove { key } => Some(key),
+            Command::GetChunked { key, .. } => Some(key),
+            Command::SetBytes { key, .. } | Command::Exists { key } | Command::Cas { key, .. } => Some(key),
+            Command::Incr { key, .. } | Command::Append { key, .. } | Command::Stat { key } => Some(key),
+            Command::RemovePrefix { prefix } => Some(prefix),
+            Command::Rename { from, .. } => Some(from),
+            Command::GetMany { keys } => keys.first().map(String::as_str),
+            Command::Len | Command::Ping | Command::ServerInfo | Command::Stats | Command::Clear => None,
+            Command::Keys { .. } | Command::Begin | Command::Commit | Command::Rollback => None,
+            Command::BatchBegin { .. } | Command::BatchCommit { .. } => None,
+        }
+    }
+}
+
+/// Checks that the key is not empty, returning `KvsError::InvalidKey` otherwise
+pub fn validate_key(key: &str) -> Result<()> {
+    if key.is_empty() {
+        return Err(KvsError::InvalidKey);
+    }
+
+    Ok(())
 }
 
 #[derive(StructOpt)]
@@ -23,9 +243,121 @@ pub struct ClientOpt {
     #[structopt(
         default_value = "127.0.0.1:4000", 
         long="addr",
-        value_name = "IP:PORT",
+        value_name = "IP:PORT|unix:PATH",
         parse(try_from_str)
     )]
-    /// Connection IP address
-    pub addr: SocketAddr
-}
\ No newline at end of file
+    /// Connection IP address, or path of a Unix domain socket after `unix:`
+    pub addr: ServerAddr,
+
+    #[structopt(
+        default_value = "text",
+        long,
+        value_name = "FORMAT",
+        possible_values = &LoggerFormat::variants()
+    )]
+    /// Format of the logs written to stderr
+    pub log_format: LoggerFormat,
+
+    #[structopt(
+        default_value = "info",
+        short = "v",
+        long,
+        value_name = "LEVEL",
+        possible_values = &LOG_LEVELS,
+        parse(try_from_str = parse_log_level)
+    )]
+    /// Least severe level of the logs written to stderr
+    pub log_level: slog::Level,
+
+    #[structopt(
+        default_value = "text",
+        long,
+        value_name = "FORMAT",
+        possible_values = &OutputFormat::variants()
+    )]
+    /// Format of the results written to stdout
+    pub output: OutputFormat,
+
+    #[structopt(default_value = "0", long, value_name = "N")]
+    /// Number of times a failed connection to the server is retried
+    pub retries: u32,
+
+    #[structopt(default_value = "100", long, value_name = "MILLISECONDS")]
+    /// Delay before the first connection retry, doubled after every attempt
+    pub retry_delay_ms: u64,
+
+    #[structopt(long, value_name = "MILLISECONDS")]
+    /// Time after which connecting to the server, or waiting for its response, fails
+    pub timeout_ms: Option<u64>,
+
+    #[structopt(long)]
+    /// Let Nagle's algorithm delay small commands on TCP connections instead of sending them right away
+    pub nagle: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Format of the results printed by `KvsClient::run`
+pub enum OutputFormat {
+    /// Bare values, one per line. This is the default.
+    Text,
+    /// One JSON value per response, with `null` for keys that were not found
+    Json
+}
+
+impl OutputFormat {
+    /// Possible values of this enum
+    fn variants() -> [&'static str; 2] {
+        ["text", "json"]
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = KvsError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(KvsError::UnknownOutputFormat)
+        }
+    }
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = match *self {
+            OutputFormat::Text => "text",
+            OutputFormat::Json => "json",
+        };
+        write!(f, "{}", printable)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Outcome of a command run from the command line, which `kvs-client` exits with
+pub enum ExitStatus {
+    /// The command succeeded, including a Get of a missing key
+    Success = 0,
+    /// Any error not covered by another status
+    Error = 1,
+    /// The key targeted by the command does not exist
+    KeyNotFound = 2,
+    /// The server could not be reached or did not answer in time
+    Unavailable = 3
+}
+
+impl ExitStatus {
+    /// Status of a command which failed with the given error
+    pub fn of(err: &KvsError) -> Self {
+        match err {
+            KvsError::KeyNotFound => ExitStatus::KeyNotFound,
+            KvsError::IOError(_) | KvsError::Timeout(_) => ExitStatus::Unavailable,
+            _ => ExitStatus::Error
+        }
+    }
+
+    /// Process exit code of this status
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+}
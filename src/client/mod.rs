@@ -1,5 +1,6 @@
 pub use client::KvsClient;
-pub use commands::{ClientOpt, Command};
+pub use commands::{ClientOpt, Command, ExitStatus, OutputFormat};
 
 pub mod client;
-pub mod commands;
\ No newline at end of file
+pub mod commands;
+mod cache;
\ No newline at end of file
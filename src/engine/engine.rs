@@ -1,9 +1,132 @@
-use crate::Result;
+use std::fmt;
+use serde::{Deserialize, Serialize};
 
-pub trait KvsEngine {
-  fn set(&mut self, key: String, value: String) -> Result<()>;
+use crate::{Command, Result};
 
-  fn get(&mut self, key: String) -> Result<Option<String>>;
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+/// Number of live keys and sizes of their values, returned by `KvsEngine::size_stats`
+pub struct SizeStats {
+  pub keys: usize,
+  /// Sum of the lengths of every live value, in bytes
+  pub total_value_bytes: u64,
+  /// Average length of a live value in bytes, rounded down, 0 if there are no keys
+  pub average_value_bytes: u64,
+  /// Length of the biggest live value in bytes, 0 if there are no keys
+  pub max_value_bytes: u64
+}
 
-  fn remove(&mut self, key: String) -> Result<()>;
+impl SizeStats {
+  /// Stats of the values with the given lengths, in bytes
+  pub fn from_value_lens(value_lens: impl IntoIterator<Item = u64>) -> Self {
+    let mut stats = SizeStats::default();
+
+    for value_len in value_lens {
+      stats.keys += 1;
+      stats.total_value_bytes += value_len;
+      stats.max_value_bytes = stats.max_value_bytes.max(value_len);
+    }
+
+    if stats.keys > 0 {
+      stats.average_value_bytes = stats.total_value_bytes / stats.keys as u64;
+    }
+
+    stats
+  }
+}
+
+impl fmt::Display for SizeStats {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "keys: {}", self.keys)?;
+    writeln!(f, "total value bytes: {}", self.total_value_bytes)?;
+    writeln!(f, "average value bytes: {}", self.average_value_bytes)?;
+    write!(f, "max value bytes: {}", self.max_value_bytes)
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+/// Location, size and expiry of the value of a key, returned by `KvsEngine::stat_key`
+pub struct KeyStat {
+  /// Id of the log file holding the value, `None` for engines without log files
+  pub log_file_id: Option<u64>,
+  /// Length of the value in bytes
+  pub value_bytes: u64,
+  /// Expiry of the key in milliseconds since the Unix epoch, `None` if it never expires
+  pub expires_at: Option<u64>
+}
+
+impl fmt::Display for KeyStat {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Some(log_file_id) = self.log_file_id {
+      writeln!(f, "log file: {}", log_file_id)?;
+    }
+    if let Some(expires_at) = self.expires_at {
+      writeln!(f, "expires at: {}", expires_at)?;
+    }
+    write!(f, "value bytes: {}", self.value_bytes)
+  }
+}
+
+pub trait KvsEngine: Send + Sync {
+  /// Name of the engine, as given to `kvs-server --engine`
+  fn name(&self) -> &'static str;
+
+  fn set(&self, key: String, value: String) -> Result<()>;
+
+  fn get(&self, key: String) -> Result<Option<String>>;
+
+  /// Returns the value of the key, or the given default without storing it if the key is missing
+  fn get_or(&self, key: String, default: String) -> Result<String> {
+    Ok(self.get(key)?.unwrap_or(default))
+  }
+
+  fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+    keys.into_iter().map(|key| self.get(key)).collect()
+  }
+
+  fn contains_key(&self, key: String) -> Result<bool>;
+
+  fn len(&self) -> Result<usize>;
+
+  /// Returns every live key in key order
+  fn all_keys(&self) -> Result<Vec<String>>;
+
+  /// Returns at most `limit` live keys starting with the prefix in key order,
+  /// only counting the keys after `after` if it is given
+  fn keys_page(&self, prefix: String, after: Option<String>, limit: usize) -> Result<Vec<String>>;
+
+  /// Returns the number of live keys with the total, average and biggest size of their values
+  fn size_stats(&self) -> Result<SizeStats>;
+
+  /// Returns the location, size and expiry of the value of the key, or `None` if it is missing
+  fn stat_key(&self, key: String) -> Result<Option<KeyStat>>;
+
+  fn is_empty(&self) -> Result<bool> {
+    Ok(self.len()? == 0)
+  }
+
+  fn set_bytes(&self, key: String, value: Vec<u8>) -> Result<()>;
+
+  fn get_bytes(&self, key: String) -> Result<Option<Vec<u8>>>;
+
+  fn remove(&self, key: String) -> Result<()>;
+
+  fn remove_prefix(&self, prefix: String) -> Result<usize>;
+
+  fn set_and_get_previous(&self, key: String, value: String) -> Result<Option<String>>;
+
+  fn take(&self, key: String) -> Result<String>;
+
+  fn compare_and_swap(&self, key: String, expected: Option<String>, new: Option<String>) -> Result<bool>;
+
+  fn clear(&self) -> Result<()>;
+
+  fn increment(&self, key: String, delta: i64) -> Result<i64>;
+
+  fn append(&self, key: String, suffix: String) -> Result<usize>;
+
+  fn rename(&self, from: String, to: String) -> Result<()>;
+
+  fn transaction(&self, commands: Vec<Command>) -> Result<()>;
+
+  fn flush(&self) -> Result<()>;
 }
\ No newline at end of file
@@ -0,0 +1,144 @@
+use std::io::{BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{KvsEngine, KvsError, Result};
+
+/// Line of an export holding one key/value pair.
+///
+/// Values which are valid UTF-8 are written as they are, while other values, which only
+/// sled can hold, are written in standard base64.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedPair {
+    key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value_base64: Option<String>,
+}
+
+/// Writes every live key/value pair of the engine as a line of JSON, in key order,
+/// and returns the number of written pairs.
+///
+/// The export does not depend on the on-disk format of the engine, so `import` can load it
+/// into any engine. Keys removed while the export runs are skipped.
+///
+/// # Errors
+///
+/// It propagates errors of the engine while reading the pairs and I/O errors of the writer.
+pub fn export(engine: &dyn KvsEngine, mut writer: impl Write) -> Result<usize> {
+    let mut exported = 0;
+
+    for key in engine.all_keys()? {
+        let value = match engine.get_bytes(key.clone())? {
+            Some(value) => value,
+            None => continue
+        };
+
+        let pair = match String::from_utf8(value) {
+            Ok(value) => ExportedPair { key, value: Some(value), value_base64: None },
+            Err(err) => ExportedPair { key, value: None, value_base64: Some(encode_base64(err.as_bytes())) }
+        };
+
+        serde_json::to_writer(&mut writer, &pair)?;
+        writer.write_all(b"\n")?;
+        exported += 1;
+    }
+
+    writer.flush()?;
+
+    Ok(exported)
+}
+
+/// Sets every key/value pair of an export made by `export` in the engine
+/// and returns the number of imported pairs.
+///
+/// Existing keys are overwritten and empty lines are skipped.
+///
+/// # Errors
+///
+/// It returns `KvsError::InvalidExport` for a line which is not a pair with exactly one of
+/// `value` and `value_base64`, after the pairs of the previous lines were imported.
+///
+/// It propagates I/O errors of the reader and errors of the engine while writing the pairs.
+pub fn import(engine: &dyn KvsEngine, reader: impl BufRead) -> Result<usize> {
+    let mut imported = 0;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let invalid = |reason: String| KvsError::InvalidExport { line: index + 1, reason };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let pair: ExportedPair = serde_json::from_str(&line).map_err(|err| invalid(err.to_string()))?;
+
+        match (pair.value, pair.value_base64) {
+            (Some(value), None) => engine.set(pair.key, value)?,
+            (None, Some(value)) => {
+                let value = decode_base64(&value).ok_or_else(|| invalid("invalid base64 value".to_owned()))?;
+                engine.set_bytes(pair.key, value)?
+            },
+            _ => return Err(invalid("expected exactly one of value and value_base64".to_owned()))
+        }
+
+        imported += 1;
+    }
+
+    Ok(imported)
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes in standard base64 with padding
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| group | u32::from(*byte) << (16 - 8 * i));
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_ALPHABET[(group >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+
+    encoded
+}
+
+/// Decodes standard base64 with padding
+///
+/// Returns `None` if the text is not valid base64.
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.as_bytes();
+
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+
+    let mut decoded = Vec::with_capacity(text.len() / 4 * 3);
+
+    for (index, chunk) in text.chunks(4).enumerate() {
+        // Padding is only allowed at the end of the last group
+        let is_last = index == text.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !is_last) {
+            return None;
+        }
+
+        let mut group = 0u32;
+        for &c in &chunk[..4 - padding] {
+            let sextet = BASE64_ALPHABET.iter().position(|&a| a == c)? as u32;
+            group = group << 6 | sextet;
+        }
+        group <<= 6 * padding as u32;
+
+        decoded.extend_from_slice(&group.to_be_bytes()[1..4 - padding]);
+    }
+
+    Some(decoded)
+}
@@ -1,3 +1,5 @@
-pub use engine::KvsEngine;
+pub use engine::{KeyStat, KvsEngine, SizeStats};
+pub use export::{export, import};
 
-pub mod engine;
\ No newline at end of file
+pub mod engine;
+pub mod export;
\ No newline at end of file
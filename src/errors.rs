@@ -1,8 +1,10 @@
 use std::error;
 use std::io;
 use std::fmt;
+use std::path::PathBuf;
 use std::result;
 use std::string::FromUtf8Error;
+use std::time::Duration;
 
 /// Holds any kind of error.
 pub type Error = KvsError;
@@ -23,12 +25,27 @@ pub enum KvsError {
     /// Represents a failure to serialize or deserialize data.
     SerializationError(serde_json::Error),
 
+    /// Represents a failure to serialize or deserialize a command with bincode.
+    BincodeError(bincode::Error),
+
     /// Represents all errors of `std::io::Error`.
     IOError(io::Error),
 
     /// Represents trying to parse a string into a non-existing database engine type.
     UnknownEngine,
 
+    /// Represents trying to parse a string into a non-existing server protocol.
+    UnknownProtocol,
+
+    /// Represents trying to parse a string into a non-existing log output format.
+    UnknownLogFormat,
+
+    /// Represents trying to parse a string into a non-existing log level.
+    UnknownLogLevel,
+
+    /// Represents trying to parse a string into a non-existing client output format.
+    UnknownOutputFormat,
+
     /// Represents an error received when engine parsed from command line
     /// does not match the engine set in the config file
     InvalidEngine(String),
@@ -41,7 +58,72 @@ pub enum KvsError {
 
     /// Represents a parsing error when trying to convert a value retrieved from
     /// the sled engine into a UTF-8 sequence
-    Utf8Error(FromUtf8Error)
+    Utf8Error(FromUtf8Error),
+
+    /// Represents a failure to compact because the disk does not have enough free space
+    /// to hold a copy of all live commands.
+    InsufficientSpace { required: u64, available: u64 },
+
+    /// Represents running out of ids for new log files.
+    LogIdExhausted,
+
+    /// Represents opening a `KvStore` with a compaction threshold of 0.
+    InvalidCompactionThreshold,
+
+    /// Represents opening a `KvStore` with a maximum number of log files smaller than 2.
+    InvalidMaxLogFiles,
+
+    /// Represents opening a `KvStore` with an expiry sweep interval of 0.
+    InvalidSweepInterval,
+
+    /// Represents a self-test step that did not produce the expected result.
+    SelfTestFailed(String),
+
+    /// Represents a log record which is truncated or does not match its checksum,
+    /// with the key whose value was being read, if any.
+    CorruptLog { position: u64, key: Option<String> },
+
+    /// Represents incrementing a key whose value is not an integer.
+    NotAnInteger,
+
+    /// Represents an increment whose result does not fit in an `i64`.
+    IntegerOverflow,
+
+    /// Represents a backup which is truncated, has an unknown format or does not match
+    /// its checksum.
+    InvalidBackup(String),
+
+    /// Represents restoring a backup into a directory which already holds files.
+    DirectoryNotEmpty(PathBuf),
+
+    /// Represents an address which is neither `IP:PORT` nor `unix:PATH`.
+    InvalidAddress(String),
+
+    /// Represents using an empty key.
+    InvalidKey,
+
+    /// Represents setting a value bigger than the configured maximum value size.
+    ValueTooLarge { size: u64, max: u64 },
+
+    /// Represents a key whose command is in a log file the store has no reader for.
+    /// It indicates that the in-memory index is out of sync with the log files.
+    MissingLogFile(u64),
+
+    /// Represents a failure to listen on the given server address.
+    BindFailed(String, io::Error),
+
+    /// Represents opening a store in a directory whose lock is held by another open store.
+    AlreadyLocked(PathBuf),
+
+    /// Represents a client request to which the server did not answer within the configured timeout.
+    Timeout(Duration),
+
+    /// Represents a line of an export which is not a valid key/value pair.
+    InvalidExport { line: usize, reason: String },
+
+    /// Represents an operation which the store does not support as configured,
+    /// such as a scan of a store with a hash index.
+    Unsupported(String)
 }
 
 impl error::Error for KvsError {}
@@ -58,12 +140,27 @@ impl fmt::Display for KvsError {
             KvsError::UnexpectedCommand => {
                 write!(f, "Unexpected command")
             },
+            KvsError::BincodeError(ref err) => {
+                err.fmt(f)
+            },
             KvsError::IOError(ref err) => {
                 err.fmt(f)
             },
             KvsError::UnknownEngine => {
                 write!(f, "Unknown database engine")
             },
+            KvsError::UnknownProtocol => {
+                write!(f, "Unknown server protocol")
+            },
+            KvsError::UnknownLogFormat => {
+                write!(f, "Unknown log format")
+            },
+            KvsError::UnknownLogLevel => {
+                write!(f, "Unknown log level")
+            },
+            KvsError::UnknownOutputFormat => {
+                write!(f, "Unknown output format")
+            },
             KvsError::RequestError(e) => {
                 write!(f, "Error from server: {}", e)
             },
@@ -75,6 +172,74 @@ impl fmt::Display for KvsError {
             },
             KvsError::InvalidEngine(engine) => {
                 write!(f, "Invalid choosen engine. Your previously set engine in the config file was {}", engine)
+            },
+            KvsError::InsufficientSpace { required, available } => {
+                write!(
+                    f,
+                    "Not enough free disk space to compact: {} bytes required but only {} bytes available",
+                    required,
+                    available
+                )
+            },
+            KvsError::LogIdExhausted => {
+                write!(f, "No log file ids left for new log files")
+            },
+            KvsError::InvalidCompactionThreshold => {
+                write!(f, "The compaction threshold must be bigger than 0")
+            },
+            KvsError::InvalidSweepInterval => {
+                write!(f, "The expiry sweep interval must be bigger than 0")
+            },
+            KvsError::InvalidMaxLogFiles => {
+                write!(f, "The maximum number of log files must be at least 2")
+            },
+            KvsError::SelfTestFailed(step) => {
+                write!(f, "Self-test failed: {}", step)
+            },
+            KvsError::CorruptLog { position, key: None } => {
+                write!(f, "Corrupt log record at byte {}", position)
+            },
+            KvsError::CorruptLog { position, key: Some(key) } => {
+                write!(f, "Corrupt log record at byte {} for key {}", position, key)
+            },
+            KvsError::NotAnInteger => {
+                write!(f, "The value is not an integer")
+            },
+            KvsError::IntegerOverflow => {
+                write!(f, "The increment overflows the value")
+            },
+            KvsError::InvalidBackup(reason) => {
+                write!(f, "Invalid backup: {}", reason)
+            },
+            KvsError::DirectoryNotEmpty(path) => {
+                write!(f, "Directory is not empty: {}", path.display())
+            },
+            KvsError::InvalidAddress(reason) => {
+                write!(f, "Invalid address: {}", reason)
+            },
+            KvsError::InvalidKey => {
+                write!(f, "Keys cannot be empty")
+            },
+            KvsError::ValueTooLarge { size, max } => {
+                write!(f, "The value has {} bytes but at most {} bytes are allowed", size, max)
+            },
+            KvsError::MissingLogFile(id) => {
+                write!(f, "Log file {} is missing", id)
+            },
+            KvsError::BindFailed(addr, ref err) => {
+                write!(f, "Unable to listen on {}: {}", addr, err)
+            },
+            KvsError::AlreadyLocked(path) => {
+                write!(f, "The store in {} is already opened by another process", path.display())
+            },
+            KvsError::Timeout(timeout) => {
+                write!(f, "No answer from the server within {} ms", timeout.as_millis())
+            },
+            KvsError::InvalidExport { line, reason } => {
+                write!(f, "Invalid export at line {}: {}", line, reason)
+            },
+            KvsError::Unsupported(reason) => {
+                write!(f, "Unsupported operation: {}", reason)
             }
         }
     }
@@ -92,6 +257,12 @@ impl From<serde_json::Error> for KvsError {
     }
 }
 
+impl From<bincode::Error> for KvsError {
+    fn from(err: bincode::Error) -> KvsError {
+        KvsError::BincodeError(err)
+    }
+}
+
 impl From<sled::Error> for KvsError {
     fn from(err: sled::Error) -> KvsError {
         KvsError::SledError(err)
@@ -0,0 +1,97 @@
+use std::convert::TryInto;
+use std::io::{Read, Write};
+
+use crate::{Command, KvsError, Result};
+
+/// Bytes at the start of every backup
+const MAGIC: &[u8; 8] = b"KVSBAKUP";
+/// Version of the backup format, bumped whenever the layout changes
+const VERSION: u8 = 1;
+/// Size of the magic bytes, the version and the number of commands
+const HEADER_SIZE: usize = MAGIC.len() + 1 + 8;
+/// Size of the trailing checksum
+const CHECKSUM_SIZE: usize = 4;
+
+/// Write the given commands as a backup
+///
+/// The layout is a header with `MAGIC`, `VERSION` and the number of commands as a big-endian u64,
+/// then every command as JSON prefixed with its length as a big-endian u32, and finally the
+/// CRC32 of all previous bytes as a big-endian u32.
+pub fn write_backup(writer: &mut impl Write, commands: &[Command]) -> Result<()> {
+    let mut hasher = crc32fast::Hasher::new();
+
+    // Write the bytes and add them to the checksum
+    let mut write = |bytes: &[u8]| -> Result<()> {
+        hasher.update(bytes);
+        writer.write_all(bytes)?;
+        Ok(())
+    };
+
+    write(MAGIC)?;
+    write(&[VERSION])?;
+    write(&(commands.len() as u64).to_be_bytes())?;
+
+    for cmd in commands {
+        let payload = serde_json::to_vec(cmd)?;
+        let len: u32 = payload.len().try_into().map_err(|_| {
+            KvsError::InvalidBackup("command is too large".to_owned())
+        })?;
+
+        write(&len.to_be_bytes())?;
+        write(&payload)?;
+    }
+
+    writer.write_all(&hasher.finalize().to_be_bytes())?;
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Read the commands of a backup written by `write_backup`
+///
+/// The whole backup is verified against its checksum before any command is returned.
+pub fn read_backup(reader: &mut impl Read) -> Result<Vec<Command>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    if bytes.len() < HEADER_SIZE + CHECKSUM_SIZE || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(KvsError::InvalidBackup("not a backup".to_owned()));
+    }
+
+    if bytes[MAGIC.len()] != VERSION {
+        return Err(KvsError::InvalidBackup(format!("unsupported version {}", bytes[MAGIC.len()])));
+    }
+
+    let (body, checksum) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+
+    if crc32fast::hash(body).to_be_bytes() != checksum {
+        return Err(KvsError::InvalidBackup("checksum mismatch".to_owned()));
+    }
+
+    let count = u64::from_be_bytes(body[MAGIC.len() + 1..HEADER_SIZE].try_into().unwrap());
+    let mut rest = &body[HEADER_SIZE..];
+    let mut commands = Vec::new();
+
+    while !rest.is_empty() {
+        if rest.len() < 4 {
+            return Err(KvsError::InvalidBackup("truncated command".to_owned()));
+        }
+
+        let (len, tail) = rest.split_at(4);
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+
+        if tail.len() < len {
+            return Err(KvsError::InvalidBackup("truncated command".to_owned()));
+        }
+
+        let (payload, tail) = tail.split_at(len);
+        commands.push(serde_json::from_slice(payload)?);
+        rest = tail;
+    }
+
+    if commands.len() as u64 != count {
+        return Err(KvsError::InvalidBackup(format!("expected {} commands but found {}", count, commands.len())));
+    }
+
+    Ok(commands)
+}
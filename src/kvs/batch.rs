@@ -0,0 +1,34 @@
+use crate::Command;
+
+#[derive(Debug, Default)]
+/// Set and Remove commands written to a `KvStore` together by `KvStore::commit_batch`
+pub struct WriteBatch {
+    pub(crate) commands: Vec<Command>
+}
+
+impl WriteBatch {
+    /// Creates an empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds setting the value of a string key to a string
+    pub fn set(&mut self, key: String, value: String) {
+        self.commands.push(Command::Set { key, value, expires_at: None });
+    }
+
+    /// Adds removing a given string key
+    pub fn remove(&mut self, key: String) {
+        self.commands.push(Command::Remove { key });
+    }
+
+    /// Number of commands in the batch
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Whether no command was added to the batch
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Bits per key the filter is sized for, which gives about 1% of false positives
+const BITS_PER_KEY: usize = 10;
+/// Number of bits set for every key
+const HASHES: u64 = 7;
+/// Smallest number of keys the filter is sized for
+const MIN_CAPACITY: usize = 1024;
+
+/// Bloom filter of the keys of the index, which tells for sure when a key is missing
+///
+/// Keys are never taken out of the filter, so removed keys only add false positives until
+/// the filter is rebuilt.
+#[derive(Debug)]
+pub(crate) struct BloomFilter {
+    bits: Vec<u64>,
+    /// Number of keys the filter is sized for
+    capacity: usize,
+    /// Number of keys inserted since the filter was built
+    inserted: usize,
+}
+
+impl BloomFilter {
+    /// Builds a filter of the given keys with room for as many more
+    pub(crate) fn with_keys<'a>(keys: impl ExactSizeIterator<Item = &'a String>) -> Self {
+        let capacity = keys.len().saturating_mul(2).max(MIN_CAPACITY);
+        let mut filter = Self {
+            bits: vec![0; capacity.saturating_mul(BITS_PER_KEY).div_ceil(64)],
+            capacity,
+            inserted: 0
+        };
+
+        for key in keys {
+            filter.insert(key);
+        }
+
+        filter
+    }
+
+    pub(crate) fn insert(&mut self, key: &str) {
+        for bit in self.positions(key) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+
+        self.inserted = self.inserted.saturating_add(1);
+    }
+
+    /// Returns false if the key was never inserted, and true if it may have been
+    pub(crate) fn might_contain(&self, key: &str) -> bool {
+        self.positions(key).all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Whether more keys were inserted than the filter is sized for, so that it gives
+    /// more false positives than intended
+    pub(crate) fn is_full(&self) -> bool {
+        self.inserted >= self.capacity
+    }
+
+    /// Positions of the bits of a key, derived from two halves of a single hash
+    fn positions(&self, key: &str) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        let first = hash & u64::from(u32::MAX);
+        // An odd step never cycles back to the first bit early
+        let step = (hash >> 32) | 1;
+        let len = self.bits.len() as u64 * 64;
+
+        (0..HASHES).map(move |i| (first.wrapping_add(i.wrapping_mul(step)) % len) as usize)
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::LogPointer;
+
+/// Maximum size of the value cache of a `KvStore`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheSize {
+    /// Maximum number of cached values
+    Entries(usize),
+    /// Maximum number of bytes of the cached keys and values
+    Bytes(u64),
+}
+
+/// Counters of the value cache of a `KvStore`, returned by `KvStore::cache_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of values read from the cache instead of the log files
+    pub hits: u64,
+    /// Number of values read from the log files, which were then cached
+    pub misses: u64,
+    /// Number of cached values
+    pub entries: usize,
+    /// Number of bytes of the cached keys and values
+    pub bytes: u64,
+}
+
+/// Value of a key together with the location of the command it was read from
+#[derive(Debug)]
+struct CachedValue {
+    /// Log file id and position of the command
+    location: (u64, u64),
+    value: Vec<u8>,
+    /// Tick of the last time the value was used, its key in the recency map
+    last_used: u64,
+}
+
+/// Least recently used cache of the values read from the log files
+///
+/// A cached value is only returned for the command it was read from, so a key written again
+/// misses the cache even if its entry was not removed.
+#[derive(Debug)]
+pub(crate) struct ValueCache {
+    size: CacheSize,
+    values: HashMap<String, CachedValue>,
+    /// Keys by the tick of their last use, the least recently used first
+    recency: BTreeMap<u64, String>,
+    tick: u64,
+    stats: CacheStats,
+}
+
+impl ValueCache {
+    pub(crate) fn new(size: CacheSize) -> Self {
+        Self { size, values: HashMap::new(), recency: BTreeMap::new(), tick: 0, stats: CacheStats::default() }
+    }
+
+    /// Returns the cached value of the key if it was read from the command the log pointer refers to
+    pub(crate) fn get(&mut self, key: &str, log_pointer: &LogPointer) -> Option<&[u8]> {
+        let tick = self.next_tick();
+
+        match self.values.get_mut(key) {
+            Some(cached) if cached.location == location(log_pointer) => {
+                self.recency.remove(&cached.last_used);
+                self.recency.insert(tick, key.to_owned());
+                cached.last_used = tick;
+                self.stats.hits += 1;
+
+                Some(&cached.value)
+            },
+            _ => {
+                self.stats.misses += 1;
+
+                None
+            }
+        }
+    }
+
+    /// Caches the value of the key read from the command the log pointer refers to,
+    /// evicting the least recently used values that no longer fit
+    pub(crate) fn insert(&mut self, key: String, log_pointer: &LogPointer, value: Vec<u8>) {
+        self.remove(&key);
+
+        // A value which would not fit alone is not cached
+        let size = entry_size(&key, &value);
+        let fits = match self.size {
+            CacheSize::Entries(entries) => entries > 0,
+            CacheSize::Bytes(bytes) => size <= bytes,
+        };
+        if !fits {
+            return;
+        }
+
+        let tick = self.next_tick();
+        self.recency.insert(tick, key.clone());
+        self.values.insert(key, CachedValue { location: location(log_pointer), value, last_used: tick });
+        self.stats.entries += 1;
+        self.stats.bytes += size;
+
+        while self.is_over_capacity() {
+            match self.recency.pop_first() {
+                Some((_, oldest)) => self.remove(&oldest),
+                None => break
+            }
+        }
+    }
+
+    /// Removes the cached value of the key, if any
+    pub(crate) fn remove(&mut self, key: &str) {
+        if let Some(cached) = self.values.remove(key) {
+            self.recency.remove(&cached.last_used);
+            self.stats.entries -= 1;
+            self.stats.bytes -= entry_size(key, &cached.value);
+        }
+    }
+
+    /// Points the cached value of the key to the new location of its command, if it was read
+    /// from the command at the old one, since moving a command does not change its value
+    pub(crate) fn relocate(&mut self, key: &str, old: (u64, u64), log_pointer: &LogPointer) {
+        if let Some(cached) = self.values.get_mut(key) {
+            if cached.location == old {
+                cached.location = location(log_pointer);
+            }
+        }
+    }
+
+    /// Removes every cached value, keeping the counters of hits and misses
+    pub(crate) fn clear(&mut self) {
+        self.values.clear();
+        self.recency.clear();
+        self.stats.entries = 0;
+        self.stats.bytes = 0;
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn is_over_capacity(&self) -> bool {
+        match self.size {
+            CacheSize::Entries(entries) => self.stats.entries > entries,
+            CacheSize::Bytes(bytes) => self.stats.bytes > bytes,
+        }
+    }
+}
+
+/// Log file id and position of the command a log pointer refers to
+pub(crate) fn location(log_pointer: &LogPointer) -> (u64, u64) {
+    (log_pointer.log_file_id, log_pointer.start_position)
+}
+
+fn entry_size(key: &str, value: &[u8]) -> u64 {
+    (key.len() + value.len()) as u64
+}
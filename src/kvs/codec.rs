@@ -0,0 +1,100 @@
+use std::borrow::Cow;
+use serde::{Deserialize, Serialize};
+
+use crate::{Command, KvsError, Result};
+
+/// How commands are serialized in the log files.
+///
+/// Commands serialized with any codec can be read whatever the configured codec is,
+/// so it can be changed between openings of a store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// Commands are serialized as JSON. This is the default.
+    Json,
+    /// Commands are serialized with bincode, which is smaller and faster than JSON.
+    Bincode,
+}
+
+impl Codec {
+    /// Codec that serialized the given command
+    ///
+    /// JSON commands are objects, so they start with `{`, while bincode commands start with
+    /// the little-endian index of their variant.
+    pub fn detect(bytes: &[u8]) -> Codec {
+        match bytes.first() {
+            Some(b'{') => Codec::Json,
+            _ => Codec::Bincode
+        }
+    }
+
+    /// Serialize a command that is written to the log
+    ///
+    /// It returns `KvsError::UnexpectedCommand` for commands that are never written to the
+    /// log if bincode is used.
+    pub fn encode(self, cmd: &Command) -> Result<Vec<u8>> {
+        match self {
+            Codec::Json => Ok(serde_json::to_vec(cmd)?),
+            Codec::Bincode => Ok(bincode::serialize(&BinaryCommand::try_from(cmd)?)?)
+        }
+    }
+
+    /// Deserialize a command with the codec that serialized it
+    pub fn decode(bytes: &[u8]) -> Result<Command> {
+        match Codec::detect(bytes) {
+            Codec::Json => Ok(serde_json::from_slice(bytes)?),
+            Codec::Bincode => Ok(bincode::deserialize::<BinaryCommand>(bytes)?.into())
+        }
+    }
+}
+
+/// Commands written to the log, as serialized by bincode
+///
+/// Bincode is not self-describing, so fields cannot be skipped like the expiry of
+/// `Command::Set` is in JSON. Every field is always serialized here instead.
+#[derive(Serialize, Deserialize)]
+enum BinaryCommand<'a> {
+    Set { key: Cow<'a, str>, value: Cow<'a, str>, expires_at: Option<u64> },
+    SetBytes { key: Cow<'a, str>, value: Cow<'a, [u8]> },
+    Remove { key: Cow<'a, str> },
+    BatchBegin { count: u64 },
+    BatchCommit { count: u64 },
+}
+
+impl<'a> BinaryCommand<'a> {
+    fn try_from(cmd: &'a Command) -> Result<Self> {
+        match cmd {
+            Command::Set { key, value, expires_at } => Ok(BinaryCommand::Set {
+                key: Cow::Borrowed(key),
+                value: Cow::Borrowed(value),
+                expires_at: *expires_at
+            }),
+            Command::SetBytes { key, value } => Ok(BinaryCommand::SetBytes {
+                key: Cow::Borrowed(key),
+                value: Cow::Borrowed(value)
+            }),
+            Command::Remove { key } => Ok(BinaryCommand::Remove { key: Cow::Borrowed(key) }),
+            Command::BatchBegin { count } => Ok(BinaryCommand::BatchBegin { count: *count }),
+            Command::BatchCommit { count } => Ok(BinaryCommand::BatchCommit { count: *count }),
+            _ => Err(KvsError::UnexpectedCommand)
+        }
+    }
+}
+
+impl From<BinaryCommand<'_>> for Command {
+    fn from(cmd: BinaryCommand<'_>) -> Self {
+        match cmd {
+            BinaryCommand::Set { key, value, expires_at } => Command::Set {
+                key: key.into_owned(),
+                value: value.into_owned(),
+                expires_at
+            },
+            BinaryCommand::SetBytes { key, value } => Command::SetBytes {
+                key: key.into_owned(),
+                value: value.into_owned()
+            },
+            BinaryCommand::Remove { key } => Command::Remove { key: key.into_owned() },
+            BinaryCommand::BatchBegin { count } => Command::BatchBegin { count },
+            BinaryCommand::BatchCommit { count } => Command::BatchCommit { count },
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::borrow::Borrow;
+use std::collections::{btree_map, hash_map, BTreeMap, HashMap};
+use std::ops::RangeBounds;
+use std::vec;
+
+use crate::{KvsError, LogPointer, Result};
+
+/// Map type of the in-memory index of a `KvStore`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+    /// Keys are kept sorted in a `BTreeMap`, which supports scans and key navigation.
+    /// This is the default.
+    Ordered,
+    /// Keys are kept in a `HashMap`, which speeds up point lookups. Scans and key navigation
+    /// return `KvsError::Unsupported`, while listing keys sorts them on every call.
+    Hash,
+}
+
+/// In-memory index of the log pointer of every live key
+#[derive(Debug)]
+pub(crate) enum Index {
+    Ordered(BTreeMap<String, LogPointer>),
+    Hash(HashMap<String, LogPointer>),
+}
+
+impl Index {
+    pub(crate) fn new(kind: IndexKind) -> Self {
+        match kind {
+            IndexKind::Ordered => Index::Ordered(BTreeMap::new()),
+            IndexKind::Hash => Index::Hash(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn get(&self, key: &str) -> Option<&LogPointer> {
+        match self {
+            Index::Ordered(map) => map.get(key),
+            Index::Hash(map) => map.get(key),
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, key: &str) -> Option<&mut LogPointer> {
+        match self {
+            Index::Ordered(map) => map.get_mut(key),
+            Index::Hash(map) => map.get_mut(key),
+        }
+    }
+
+    pub(crate) fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub(crate) fn insert(&mut self, key: String, log_pointer: LogPointer) -> Option<LogPointer> {
+        match self {
+            Index::Ordered(map) => map.insert(key, log_pointer),
+            Index::Hash(map) => map.insert(key, log_pointer),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) -> Option<LogPointer> {
+        match self {
+            Index::Ordered(map) => map.remove(key),
+            Index::Hash(map) => map.remove(key),
+        }
+    }
+
+    pub(crate) fn retain(&mut self, f: impl FnMut(&String, &mut LogPointer) -> bool) {
+        match self {
+            Index::Ordered(map) => map.retain(f),
+            Index::Hash(map) => map.retain(f),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match self {
+            Index::Ordered(map) => map.clear(),
+            Index::Hash(map) => map.clear(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            Index::Ordered(map) => map.len(),
+            Index::Hash(map) => map.len(),
+        }
+    }
+
+    /// Iterates the entries, in key order only for an ordered index
+    pub(crate) fn iter(&self) -> Either<btree_map::Iter<'_, String, LogPointer>, hash_map::Iter<'_, String, LogPointer>> {
+        match self {
+            Index::Ordered(map) => Either::Left(map.iter()),
+            Index::Hash(map) => Either::Right(map.iter()),
+        }
+    }
+
+    /// Iterates the keys, in key order only for an ordered index
+    pub(crate) fn keys(&self) -> Either<btree_map::Keys<'_, String, LogPointer>, hash_map::Keys<'_, String, LogPointer>> {
+        match self {
+            Index::Ordered(map) => Either::Left(map.keys()),
+            Index::Hash(map) => Either::Right(map.keys()),
+        }
+    }
+
+    pub(crate) fn values(&self) -> Either<btree_map::Values<'_, String, LogPointer>, hash_map::Values<'_, String, LogPointer>> {
+        match self {
+            Index::Ordered(map) => Either::Left(map.values()),
+            Index::Hash(map) => Either::Right(map.values()),
+        }
+    }
+
+    /// Iterates the entries in the range in key order without looking at the other keys.
+    ///
+    /// It returns `KvsError::Unsupported` for a hash index, whose keys are not ordered.
+    pub(crate) fn range<T, R>(&self, range: R) -> Result<btree_map::Range<'_, String, LogPointer>>
+    where
+        T: Ord + ?Sized,
+        R: RangeBounds<T>,
+        String: Borrow<T>
+    {
+        match self {
+            Index::Ordered(map) => Ok(map.range(range)),
+            Index::Hash(_) => Err(KvsError::Unsupported("key ranges need an ordered index".to_owned())),
+        }
+    }
+
+    /// Iterates the entries in the range in key order, which a hash index finds by
+    /// filtering and sorting all of its entries
+    pub(crate) fn sorted_range<R>(&self, range: R) -> Either<btree_map::Range<'_, String, LogPointer>, vec::IntoIter<(&String, &LogPointer)>>
+    where
+        R: RangeBounds<str>
+    {
+        match self {
+            Index::Ordered(map) => Either::Left(map.range::<str, _>(range)),
+            Index::Hash(map) => {
+                let mut entries: Vec<(&String, &LogPointer)> = map
+                    .iter()
+                    .filter(|(key, _)| range.contains(key.as_str()))
+                    .collect();
+                entries.sort_unstable_by_key(|(key, _)| *key);
+
+                Either::Right(entries.into_iter())
+            },
+        }
+    }
+}
+
+/// Iterator over the entries of either kind of index
+pub(crate) enum Either<L, R> {
+    Left(L),
+    Right(R),
+}
+
+impl<L, R> Iterator for Either<L, R>
+where
+    L: Iterator,
+    R: Iterator<Item = L::Item>
+{
+    type Item = L::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Either::Left(iter) => iter.next(),
+            Either::Right(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Either::Left(iter) => iter.size_hint(),
+            Either::Right(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl<L, R> ExactSizeIterator for Either<L, R>
+where
+    L: ExactSizeIterator,
+    R: ExactSizeIterator<Item = L::Item>
+{}
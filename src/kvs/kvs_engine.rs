@@ -1,378 +1,2860 @@
-use std::collections::{HashMap, BTreeMap};
-use std::io::{self, Read, Seek, SeekFrom, Write};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::iter;
+use std::convert::TryFrom;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::ops::{Bound, Range, RangeBounds};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::path::{Path, PathBuf};
 use std::fs::{self, File, OpenOptions, create_dir_all, read_dir};
 use std::ffi::OsStr;
+use std::thread;
 use serde_json::Deserializer;
+use fs2::FileExt;
 
-use crate::{Command, KvsError, LogPointer, Result, KvsEngine, BufReaderWithPos, BufWriterWithPos};
+use crate::{Command, KvsError, LogPointer, Result, KvsEngine, BufReaderWithPos, BufWriterWithPos, KeyStat, SizeStats};
+use crate::client::commands::validate_key;
+use super::backup::{read_backup, write_backup};
+use super::batch::WriteBatch;
+use super::bloom::BloomFilter;
+use super::cache::{self, CacheSize, CacheStats, ValueCache};
+use super::codec::Codec;
+use super::index::{Either, Index, IndexKind};
+use super::DEFAULT_BUF_CAPACITY;
+use super::log_file::{LogFile, MemoryLog};
+use super::watch::{ChangeEvent, Subscriptions};
 
 const COMPACTION_THRESHOLD: u64 = 1024 * 1024;
+const MIN_FREE_SPACE: u64 = 1024 * 1024;
+/// Number of expired keys removed by each batch of an expiry sweep
+const EXPIRY_SWEEP_BATCH: usize = 1000;
+/// Number of bytes of live commands copied by each step of a background compaction
+const BACKGROUND_COMPACTION_STEP: u64 = 64 * 1024;
+
+/// Order in which compaction writes the live commands to the compaction file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactionOrder {
+    /// Commands are written sorted by key. This is the default.
+    KeySorted,
+    /// Commands are written in the order they were originally appended to the log,
+    /// so replaying the compacted log applies writes in the same order as the original one.
+    /// Commands already compacted in key order keep that order.
+    WriteOrder,
+}
+
+/// When compaction runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompactionMode {
+    /// Compaction runs automatically once the stale bytes cross the threshold. This is the default.
+    Automatic,
+    /// Compaction only runs when `compact` is called, and it syncs the compaction file
+    /// to disk before deleting the old log files.
+    Manual,
+    /// Compaction starts once the stale bytes cross the threshold, like `Automatic`, but runs
+    /// on a background thread in steps of `compact_step`, so writes made meanwhile only wait
+    /// for the current step.
+    ///
+    /// A step running when the last clone of the store is dropped finishes first, so the
+    /// directory can stay locked for a moment after the drop.
+    Background,
+}
+
+/// How commands are written to the log files.
+///
+/// Log files in any format can be loaded whatever the configured format is, so it can be
+/// changed between openings of a store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Every command is preceded by its length and checksum, so a corrupt command is detected
+    /// when the log is loaded. This is the default.
+    Framed,
+    /// Every command is written as JSON on its own line, so log files can be read and searched
+    /// with text tools. Corrupt commands are only detected if they are not valid JSON.
+    /// The configured codec is ignored, since commands must be text.
+    JsonLines,
+}
+
+/// Names of the log files of a `KvStore`, which are made of a prefix, the log file id
+/// and an extension.
+///
+/// The store only reads and deletes files whose name matches, so its directory can hold
+/// other files with the same extension as long as they do not share the prefix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFileNames {
+    /// Text before the log file id. It defaults to none.
+    pub prefix: String,
+    /// Extension after the log file id, without the dot. It defaults to `log`.
+    pub extension: String,
+}
+
+impl Default for LogFileNames {
+    fn default() -> Self {
+        Self {
+            prefix: String::new(),
+            extension: "log".to_owned(),
+        }
+    }
+}
+
+impl LogFileNames {
+    /// Name of the log file with the given id
+    pub fn file_name(&self, log_file_id: u64) -> String {
+        if self.extension.is_empty() {
+            format!("{}{}", self.prefix, log_file_id)
+        } else {
+            format!("{}{}.{}", self.prefix, log_file_id, self.extension)
+        }
+    }
+
+    /// Id of the log file with the given name, or `None` if it is not named like a log file
+    pub fn parse_id(&self, file_name: &str) -> Option<u64> {
+        let id = file_name.strip_prefix(self.prefix.as_str())?;
+
+        let id = if self.extension.is_empty() {
+            id
+        } else {
+            id.strip_suffix(self.extension.as_str())?.strip_suffix('.')?
+        };
+
+        // Names such as `+1.log` or `01.log` parse to an id whose log file has another name
+        id.parse().ok().filter(|&id| self.file_name(id) == file_name)
+    }
+}
+
+/// Options used when opening a `KvStore`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KvStoreConfig {
+    /// Number of stale bytes above which compaction runs automatically.
+    /// It defaults to 1 MiB and must be bigger than 0.
+    pub compaction_threshold: u64,
+    /// Whether every write is flushed from the in-memory buffer to its log file right away.
+    /// It defaults to true.
+    ///
+    /// When false, writes stay buffered until `flush` is called, the buffer fills up, a value
+    /// is read or the store is dropped. A crash can lose the most recent unflushed writes.
+    pub sync_on_write: bool,
+    /// Subdirectory of the store's path that holds the log files, created if it does not exist.
+    /// It defaults to none, which keeps the log files directly in the store's path.
+    pub log_dir: Option<PathBuf>,
+    /// Size in bytes above which writes move on to a new log file, leaving the previous one
+    /// untouched until compaction. It defaults to none, which keeps writing to the same log file.
+    pub max_log_size: Option<u64>,
+    /// Number of log files above which compaction runs automatically, whatever the number of
+    /// stale bytes. It defaults to none and must be at least 2, since compaction leaves
+    /// the compaction file and the current log file.
+    pub max_log_files: Option<usize>,
+    /// How commands are written to the log files. It defaults to `LogFormat::Framed`.
+    pub log_format: LogFormat,
+    /// How commands are serialized in framed log files. It defaults to `Codec::Json`.
+    pub codec: Codec,
+    /// Size in bytes above which values are rejected with `KvsError::ValueTooLarge`.
+    /// It defaults to none, which accepts values of any size.
+    pub max_value_size: Option<u64>,
+    /// Capacity in bytes of the buffer of every log file reader and writer.
+    /// It defaults to 8 KiB. Bigger buffers speed up bulk loads and the reads of big values.
+    pub buf_capacity: usize,
+    /// Names of the log files. It defaults to `{id}.log`.
+    pub log_file_names: LogFileNames,
+    /// Whether a bloom filter of the keys is kept in memory, so that most lookups of missing
+    /// keys are answered without searching the index. It defaults to false.
+    pub bloom_filter: bool,
+    /// Maximum size of a cache of the most recently read values, which answers reads of
+    /// cached keys without reading the log files. It defaults to none, which disables the cache.
+    pub value_cache: Option<CacheSize>,
+    /// Map type of the in-memory index. It defaults to `IndexKind::Ordered`, which supports
+    /// scans and key navigation, while `IndexKind::Hash` speeds up point lookups.
+    pub index: IndexKind,
+    /// Interval between sweeps of a background thread that removes the expired keys, like
+    /// `KvStore::sweep_expired`. It defaults to none, which leaves expired keys in the index
+    /// and on disk until they are accessed or compacted. It must be bigger than 0.
+    pub expiry_sweep_interval: Option<Duration>,
+}
+
+impl Default for KvStoreConfig {
+    fn default() -> Self {
+        Self {
+            compaction_threshold: COMPACTION_THRESHOLD,
+            sync_on_write: true,
+            log_dir: None,
+            max_log_size: None,
+            max_log_files: None,
+            log_format: LogFormat::Framed,
+            codec: Codec::Json,
+            max_value_size: None,
+            buf_capacity: DEFAULT_BUF_CAPACITY,
+            log_file_names: LogFileNames::default(),
+            bloom_filter: false,
+            value_cache: None,
+            index: IndexKind::Ordered,
+            expiry_sweep_interval: None,
+        }
+    }
+}
+
+/// Snapshot of the state that drives compaction, returned by `KvStore::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Number of stale bytes that the next compaction can delete.
+    pub uncompacted: u64,
+    /// Number of live keys.
+    pub live_keys: usize,
+    /// Number of log files, including the one currently being written to.
+    pub log_files: usize,
+    /// Number of stale bytes above which compaction runs automatically.
+    pub compaction_threshold: u64,
+    /// Number of log files above which compaction runs automatically, if any.
+    pub max_log_files: Option<usize>,
+}
+
+/// Estimate of what a compaction would reclaim, returned by `KvStore::compaction_preview`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionPreview {
+    /// Number of bytes in all log files, including buffered writes.
+    pub total_bytes: u64,
+    /// Number of bytes of the records of the live keys, which compaction copies.
+    pub live_bytes: u64,
+    /// Number of bytes a compaction is expected to free.
+    pub reclaimable_bytes: u64,
+}
 
 /// The `KvStore` stores string key/value pairs.
 ///
 /// Key/value pairs are persisted to disk in log files. Log files have
-/// increasing id numbers as names with a `log` extension type.
+/// increasing id numbers as names with a `log` extension type, unless configured
+/// otherwise with `KvStoreConfig::log_file_names`.
 /// A file reader hash map is kept in order to have one reader for each log file.
-/// An in-memory 'BTreeMap' stores the keys and the value locations.
+/// An in-memory index, a `BTreeMap` unless configured otherwise with `KvStoreConfig::index`,
+/// stores the keys and the value locations.
+///
+/// Cloning a `KvStore` is cheap and every clone refers to the same store, so it can be
+/// shared between threads. Every operation locks the whole store while it runs.
 ///
 /// ```rust
 /// # use kvs::{KvStore, Result};
 /// # use kvs::KvsEngine;
 /// # fn try_main() -> Result<()> {
 /// use std::env::current_dir;
-/// let mut store = KvStore::open(current_dir()?)?;
+/// let store = KvStore::open(current_dir()?)?;
 /// store.set("key".to_owned(), "value".to_owned())?;
 /// let val = store.get("key".to_owned())?;
 /// assert_eq!(val, Some("value".to_owned()));
 /// # Ok(())
 /// # }
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct KvStore {
-    /// Directory for saving log files.
-    path: PathBuf,
-    /// Map with log files' ids as keys and file readers as values.
-    readers: HashMap<u64, BufReaderWithPos<File>>,
-    /// File writer of the current log file.
-    writer: BufWriterWithPos<File>,
-    /// Current log file id.
-    current_log_id: u64,
-    /// In-memory index map with keys coming as the <KEY> value from the command line argument and 
-    /// values which are pointers to the location of the corresponding commands saved in the log files.
-    index: BTreeMap<String, LogPointer>,
-    /// Number of bytes representing "stale" commands that could be
-    /// deleted during compaction.
-    uncompacted: u64,
+    /// State shared by all clones of the store.
+    inner: Arc<Mutex<KvStoreInner>>,
 }
 
 impl KvStore {
-    /// Opens a `KvStore` at the given path.
+    /// Opens a `KvStore` at the given path with the default options.
     ///
     /// This will create a new directory if the given one does not exist.
     ///
     /// # Errors
     ///
-    /// It propagates I/O or deserialization errors during the log load.
+    /// It propagates the errors of `open_with_options`.
     pub fn open(path: impl Into<PathBuf>) -> Result<KvStore> {
-        // Create directory if it does not exist
-        let path = path.into();
-        create_dir_all(&path)?;
-       
-        // Get sorted vector of log file ids inside the directory
-        let file_ids = sort_log_files(&path)?;
-        
-        // Instantiate in-memory index map and file readers hash map
-        let mut index = BTreeMap::new();
-        let mut readers = HashMap::new();
-        let mut uncompacted: u64 = 0; // Number of bytes that can be saved after compaction
+        Self::open_with_options(path, KvStoreConfig::default())
+    }
+
+    /// Opens a `KvStore` at the given path with the given options.
+    ///
+    /// This will create a new directory if the given one, or the configured log subdirectory,
+    /// does not exist.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::InvalidCompactionThreshold` before touching the disk if the
+    /// compaction threshold is 0, which would compact on every write, and
+    /// `KvsError::InvalidMaxLogFiles` if the maximum number of log files is smaller than 2
+    /// and `KvsError::InvalidSweepInterval` if the expiry sweep interval is 0.
+    ///
+    /// It returns `KvsError::LogIdExhausted` if the newest log file already uses the biggest
+    /// possible id.
+    ///
+    /// It propagates I/O or deserialization errors during the log load.
+    pub fn open_with_options(path: impl Into<PathBuf>, config: KvStoreConfig) -> Result<KvStore> {
+        Self::open_inner(Some(path.into()), config, &mut |_, _, _| {})
+    }
 
-        for &id in &file_ids {
-            // Path to log file
-            let filepath = path.join(format!("{}.log", id));
+    /// Opens a `KvStore` at the given path with the default options, reporting the progress
+    /// of the log load to the given callback.
+    ///
+    /// The callback is called with the number of loaded log files, the total number of log
+    /// files and the number of bytes in the loaded log files, once before the load and then
+    /// every time a log file is loaded. Log files are loaded in parallel, so they can finish
+    /// out of order, but the callback is always called from the thread opening the store.
+    ///
+    /// # Errors
+    ///
+    /// It returns the same errors as `open_with_options`.
+    pub fn open_with_progress(path: impl Into<PathBuf>, mut progress: impl FnMut(usize, usize, u64)) -> Result<KvStore> {
+        Self::open_inner(Some(path.into()), KvStoreConfig::default(), &mut progress)
+    }
 
-            // Create reader for log file
-            let mut reader = BufReaderWithPos::new(File::open(filepath)?);
+    /// Creates an empty `KvStore` with the default options that keeps its log files in memory.
+    ///
+    /// It behaves like a store opened on disk, including compaction, but its contents are lost
+    /// once the last clone is dropped. There is no disk space check before compaction and
+    /// `log_segments_since` never returns any log file.
+    ///
+    /// # Errors
+    ///
+    /// It propagates I/O errors while creating the first log file, which cannot happen in memory.
+    pub fn new_in_memory() -> Result<KvStore> {
+        Self::new_in_memory_with_options(KvStoreConfig::default())
+    }
 
-            // Load log file and get total amount of bytes that can be deleted
-            uncompacted += load_log_file(id, &mut reader, &mut index)?;
+    /// Creates an empty `KvStore` with the given options that keeps its log files in memory,
+    /// like `new_in_memory`. The `log_dir` option is ignored.
+    ///
+    /// # Errors
+    ///
+    /// It returns the same configuration errors as `open_with_options`.
+    pub fn new_in_memory_with_options(config: KvStoreConfig) -> Result<KvStore> {
+        Self::open_inner(None, config, &mut |_, _, _| {})
+    }
 
-            // Add reader to hash map
-            readers.insert(id, reader);
-        }
+    /// Sets the number of bytes that must remain free on disk after compaction
+    /// copied all live commands. It defaults to 1 MiB.
+    pub fn set_min_free_space(&self, bytes: u64) {
+        self.lock().set_min_free_space(bytes)
+    }
 
-        // Get file id of last log file and add 1 to it for the new log file
-        let current_log_id: u64 = file_ids.last().unwrap_or(&0) + 1;
+    /// Sets the order in which compaction writes the live commands.
+    /// It defaults to `CompactionOrder::KeySorted`.
+    pub fn set_compaction_order(&self, order: CompactionOrder) {
+        self.lock().set_compaction_order(order)
+    }
 
-        // Create writer for new log file (it also creates a reader and adds it to readers hash map)
-        let writer = create_new_log_file(&path, current_log_id, &mut readers)?;
-        
-        Ok(KvStore {
-            path,
-            readers,
-            writer,
-            current_log_id,
-            index,
-            uncompacted,
-        })
+    /// Sets when compaction runs. It defaults to `CompactionMode::Automatic`.
+    pub fn set_compaction_mode(&self, mode: CompactionMode) {
+        self.lock().set_compaction_mode(mode)
     }
 
     /// Compaction is performed by going through the log files, finding all the Set commands
     /// that are still in effect and write them to a new log file.
     /// After the write operation is complete, all previous log files are removed.
-    pub fn compact(&mut self) -> Result<()> {
-        // Set log file id for compaction file
-        let compaction_log_file_id = self.current_log_id + 1;
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::InsufficientSpace` before creating any file if the disk does not
+    /// have enough free space to hold a copy of all live commands plus the configured minimum
+    /// free space.
+    ///
+    /// It returns `KvsError::LogIdExhausted` before creating any file if there are no
+    /// log file ids left for the compaction file and the new log file.
+    pub fn compact(&self) -> Result<()> {
+        self.lock().compact()
+    }
 
-        // Set log file id for new writable log file after compaction is finished
-        // The compaction file will be immutable and users will start writing new logs
-        // in a new file
-        self.current_log_id += 2;
-        self.writer = create_new_log_file(
-            &self.path, 
-            self.current_log_id, 
-            &mut self.readers
-        )?;
+    /// Runs one step of an incremental compaction, which copies at least `max_bytes` of live
+    /// commands to the compaction file, or all remaining ones, and returns whether commands
+    /// are left to copy.
+    ///
+    /// The first step starts a compaction of the log files written so far and the step which
+    /// copies the last live command deletes them, so calling it until it returns false has the
+    /// same result as `compact`. Writes can run between steps, since they go to a new log file
+    /// and keys written or removed in the meantime are not copied. Automatic compaction does
+    /// not run while an incremental compaction is in progress, and `compact` finishes it first.
+    ///
+    /// # Errors
+    ///
+    /// The first step returns the same errors as `compact` before creating any file.
+    ///
+    /// It propagates I/O or serialization errors while copying, in which case the compaction
+    /// stays in progress and the next step retries the failed command.
+    pub fn compact_step(&self, max_bytes: u64) -> Result<bool> {
+        self.lock().compact_step(max_bytes)
+    }
 
-        // Create writer for compaction file
-        let mut compaction_writer = create_new_log_file(
-            &self.path, 
-            compaction_log_file_id, 
-            &mut self.readers
-        )?;
+    /// Atomically applies every command of the batch, in order.
+    ///
+    /// The commands are written to the log between batch markers, so a crash before the
+    /// whole batch reached the log leaves none of them applied once the store is opened again.
+    ///
+    /// # Errors
+    ///
+    /// It returns the same errors as `KvsEngine::transaction`, in which case no command
+    /// is applied.
+    pub fn commit_batch(&self, batch: WriteBatch) -> Result<()> {
+        self.lock().transaction(batch.commands)
+    }
 
-        // Keep track of the last written byte's position in the compaction file
-        let mut pos: u64 = 0;
+    /// Returns the id of the newest log file which will no longer be written to.
+    ///
+    /// It can be recorded after a backup and passed to `log_segments_since` the next time
+    /// in order to only copy the log files that were created in the meantime.
+    pub fn generation(&self) -> u64 {
+        self.lock().generation()
+    }
+
+    /// Lists the immutable log files with an id bigger than the given generation,
+    /// sorted by id.
+    ///
+    /// Log files are never modified once the store stopped writing to them, so the returned
+    /// files are safe to copy while the store is in use. The log file currently being written
+    /// to is never returned. Compaction may still delete a listed log file, in which case its
+    /// live commands are found in a newer log file.
+    pub fn log_segments_since(&self, gen: u64) -> Vec<(u64, PathBuf)> {
+        self.lock().log_segments_since(gen)
+    }
+
+    /// Writes a backup of all live keys to the given writer.
+    ///
+    /// Unlike copying the log files, the backup only holds the live commands, in key order,
+    /// and ends with a checksum verified by `restore_from`. Values set as bytes and expiries
+    /// are kept. The store is locked while the backup is written, so it is a consistent snapshot.
+    ///
+    /// # Errors
+    ///
+    /// It propagates I/O or serialization errors while reading the log or writing the backup.
+    pub fn backup_to(&self, mut writer: impl Write) -> Result<()> {
+        let commands = self.lock().live_commands()?;
 
-        // Go through each value in the in-memory index map which are the latest values stored in the database
-        for log_pointer in self.index.values_mut() {
-            // Get reader of the log file to which the log pointer refers to
-            let reader = self.readers.get_mut(&log_pointer.log_file_id).expect("Log reader not found");
+        write_backup(&mut writer, &commands)
+    }
 
-            // Make sure reader starts from the start position of the log pointer
-            reader.seek(SeekFrom::Start(log_pointer.start_position))?;
+    /// Opens a new `KvStore` at the given path with the contents of a backup written by `backup_to`.
+    ///
+    /// The whole backup is verified before anything is written. If `overwrite` is set, the log
+    /// files already in the directory are removed first, while other files are left untouched.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::InvalidBackup` if the backup is truncated, has an unknown format
+    /// or does not match its checksum.
+    ///
+    /// It returns `KvsError::DirectoryNotEmpty` if the directory already holds files
+    /// and `overwrite` is not set.
+    ///
+    /// It propagates the errors of `open` and I/O or serialization errors while writing the log.
+    pub fn restore_from(path: impl Into<PathBuf>, mut reader: impl Read, overwrite: bool) -> Result<KvStore> {
+        let path = path.into();
+        let commands = read_backup(&mut reader)?;
 
-            // Create a more specific reader that will only read the bytes that pertain to the log pointer
-            let mut cmd_reader = reader.take(log_pointer.len);
+        if path.is_dir() && read_dir(&path)?.next().is_some() {
+            if !overwrite {
+                return Err(KvsError::DirectoryNotEmpty(path));
+            }
 
-            // Copy log pointer to the compaction file and get number of bytes that were copied
-            let copied_bytes = io::copy(&mut cmd_reader, &mut compaction_writer)?;
+            // The log files of a store which is still open are left alone
+            let _lock_file = lock_dir(&path)?;
 
-            // Update log pointer in the in-memory index map to refer to the compaction file
-            // instead of the original log file
-            *log_pointer = (compaction_log_file_id, pos..pos + copied_bytes).into();
+            let names = LogFileNames::default();
 
-            // Add number of bytes copied to the last byte's position tracker
-            pos += copied_bytes;
+            for id in sort_log_files(&path, &names)? {
+                fs::remove_file(path.join(names.file_name(id)))?;
+            }
         }
 
-        // Make sure all write operations are completed
-        compaction_writer.flush()?;
+        let store = KvStore::open(path)?;
 
-        // Get all log file ids which are no longer being used
-        let old_logs: Vec<u64> = self.readers
-            .keys()
-            .filter(|&&log_file_id| log_file_id < compaction_log_file_id)
-            .copied()
-            .collect();
+        {
+            let mut inner = store.lock();
 
-        // Delete unused log files
-        for old_log in old_logs.iter() {
-            // Delete log file reader
-            self.readers.remove(&old_log);
+            for cmd in commands {
+                match &cmd {
+                    Command::Set { key, .. } | Command::SetBytes { key, .. } => inner.append_set(key.clone(), &cmd)?,
+                    _ => return Err(KvsError::UnexpectedCommand)
+                }
+            }
 
-            // Delete log file from directory
-            let filepath = self.path.join(format!("{}.log", old_log));
-            fs::remove_file(filepath)?;
+            inner.flush()?;
         }
 
-        // Set KvStore's uncompacted bytes counter to 0
-        self.uncompacted = 0;
+        Ok(store)
+    }
 
-        Ok(())
+    /// Returns the state that drives compaction, such as the number of stale bytes.
+    pub fn stats(&self) -> CompactionStats {
+        self.lock().stats()
     }
-}
 
-impl KvsEngine for KvStore {
-    /// Gets the string value of a given string key.
+    /// Returns the log file, value length and expiry of a key, or `None` if it is missing.
     ///
-    /// Returns `None` if the given key does not exist.
+    /// They are read from the in-memory index, so the log files are never read.
+    pub fn stat_key(&self, key: &str) -> Option<KeyStat> {
+        self.lock().stat_key(key)
+    }
+
+    /// Returns the counters of the value cache, or `None` if it is disabled.
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.lock().cache.as_ref().map(ValueCache::stats)
+    }
+
+    /// Removes every expired key, appending a Remove command for each one, so that compaction
+    /// reclaims the space of values which expired but are never read again.
+    ///
+    /// The expired keys are found first, then removed in batches with the lock of the store
+    /// released between batches, so other operations only wait for the current batch.
+    /// Keys set again in the meantime are kept, and keys expiring in the meantime are left
+    /// for the next sweep. Returns the number of removed keys.
     ///
     /// # Errors
     ///
-    /// It returns `KvsError::UnexpectedCommand` if the given command is not a Set command.
-    fn get(&mut self, key: String) -> Result<Option<String>> {
-        match self.index.get(&key) {
-            Some(cmd) => {
-                // Retrieve reader for log file to which the log pointer refers to 
-                let reader = self.readers.get_mut(&cmd.log_file_id).expect("Log reader not found");
-
-                // Set the starting position to start reading the command from the log file
-                reader.seek(SeekFrom::Start(cmd.start_position))?;
-
-                // Create a smaller reader that will only read the bytes of the command
-                let cmd_reader = reader.take(cmd.len);
-
-                // If retrieved command is a Set command, return the value associated with it
-                if let Command::Set { value, ..} = serde_json::from_reader(cmd_reader)? {
-                    Ok(Some(value))
-                } else {
-                    Err(KvsError::UnexpectedCommand)
-                }
-            }, 
-            None => Ok(None)
+    /// It propagates I/O or serialization errors while writing to the log, in which case
+    /// the keys removed by the previous batches stay removed.
+    pub fn sweep_expired(&self) -> Result<usize> {
+        let expired = self.lock().expired_keys();
+        let mut removed = 0;
+
+        for batch in expired.chunks(EXPIRY_SWEEP_BATCH) {
+            removed += self.lock().sweep_expired(batch)?;
+
+            // Let waiting operations take the lock before the next batch
+            thread::yield_now();
         }
+
+        Ok(removed)
     }
 
-    /// Sets the value of a string key to a string.
+    /// Estimates how many bytes a compaction would free, without writing anything.
     ///
-    /// If the key already exists, the previous value will be overwritten.
+    /// Live bytes are summed from the in-memory index, so the estimate reflects the keys at the
+    /// time of the call. Log files written in another format than the configured one are
+    /// rewritten in it by compaction, so their live bytes can change size.
     ///
     /// # Errors
     ///
-    /// It propagates I/O or serialization errors while writing to the log
-    fn set(&mut self, key: String, value: String) -> Result<()> {
-        let cmd = Command::Set {
-            key: key.clone(),
-            value
-        };
-        
-        // Get last byte's position in the log file
-        let pos = self.writer.pos;
-        
-        // Serialize the command and append it to the file
-        serde_json::to_writer(&mut self.writer, &cmd)?;
-        self.writer.flush()?;
-
-        // Create log pointer for the appended command
-        let end_pos = self.writer.pos; // Get new last byte's position in the log file
-        let value: LogPointer = (self.current_log_id, pos..end_pos).into();
-        
-        // Insert log pointer in the in-memory index map
-        // If the key already existed, add the bytes of the old value to the uncompacted property
-        if let Some(old_cmd) = self.index.insert(key, value) {
-            self.uncompacted += old_cmd.len;
-        };
+    /// It propagates I/O errors while reading the sizes of the log files.
+    pub fn compaction_preview(&self) -> Result<CompactionPreview> {
+        self.lock().compaction_preview()
+    }
 
-        // Perform compaction if uncompacted property is bigger than the defined threshold
-        if self.uncompacted > COMPACTION_THRESHOLD {
-            self.compact()?;
-        }
+    /// Checks whether the stale bytes exceed the compaction threshold, or the log files
+    /// exceed the configured maximum number.
+    ///
+    /// Automatic compaction runs as soon as this holds, so it is mostly useful in manual mode
+    /// to decide when to call `compact`.
+    pub fn needs_compaction(&self) -> bool {
+        self.lock().needs_compaction()
+    }
 
-        Ok(())
+    /// Sets the value of a string key to a string that expires after the given time to live.
+    ///
+    /// Once expired, the key behaves as if it was removed, and compaction deletes it.
+    /// The expiry is stored in the log, so it also holds after reopening the store.
+    ///
+    /// # Errors
+    ///
+    /// It propagates I/O or serialization errors while writing to the log
+    pub fn set_with_ttl(&self, key: String, value: String, ttl: Duration) -> Result<()> {
+        self.lock().set_with_ttl(key, value, ttl)
     }
 
-    /// Removes a given key.
+    /// Replaces the value of a string key with the result of the given function applied to it.
+    ///
+    /// The function gets `None` if the key does not exist. The key is set to the value it
+    /// returns, keeping its expiry, or removed if it returns `None`, which does nothing if the
+    /// key did not exist. The store stays locked while the function runs, so no other write
+    /// can happen in between, and the function must not use the store itself.
     ///
     /// # Errors
     ///
-    /// It returns `KvsError::KeyNotFound` if the given key is not found.
+    /// It returns `KvsError::InvalidKey` if the key is empty, `KvsError::Utf8Error` if the value
+    /// was set as bytes that are not valid UTF-8 and `KvsError::ValueTooLarge` if the new value
+    /// is bigger than the configured maximum value size. The function is not called if the
+    /// current value cannot be read.
     ///
-    /// It propagates I/O or serialization errors while writing to the log.
-    fn remove(&mut self, key: String) -> Result<()> {
-        match self.index.remove(&key) {
-            Some(cmd) => {
-                // Add removed command's length to the uncompacted property
-                self.uncompacted += cmd.len;
-        
-                // Get last byte's position in the log file
-                let pos = self.writer.pos;
-                
-                // Remove command to be added to the log file
-                let cmd = Command::Remove { key: key.clone() };
-                
-                // Serialize the command and append it to the file
-                serde_json::to_writer(&mut self.writer, &cmd)?;
-                self.writer.flush()?;
-
-                // Get new last byte's position in the log file
-                let end_pos = self.writer.pos;
-                
-                // Add appended command's length to the uncompacted property
-                self.uncompacted += end_pos - pos;
-
-                // Perform compaction if uncompacted property is bigger than the defined threshold
-                if self.uncompacted > COMPACTION_THRESHOLD {
-                    self.compact()?;
-                }
+    /// It propagates I/O or serialization errors while reading from or writing to the log
+    pub fn update<F>(&self, key: String, f: F) -> Result<()>
+    where
+        F: FnOnce(Option<String>) -> Option<String>
+    {
+        self.lock().update(key, f)
+    }
 
-                Ok(())
-            },
-            None => Err(KvsError::KeyNotFound)
+    /// Returns a receiver of an event every time the given key is set or removed.
+    ///
+    /// Events are sent once the change is written to the log, in the order the changes are
+    /// made, by every clone of the store. Keys removed by `clear` get a Remove event, while
+    /// expired keys and compaction do not send any. Dropping the receiver ends the subscription.
+    pub fn subscribe(&self, key: String) -> Receiver<ChangeEvent> {
+        self.lock().subscriptions.subscribe(key)
+    }
+
+    /// Iterates the live key/value pairs whose keys are in the given range, in key order.
+    ///
+    /// The keys are collected when the iterator is created, while values are read from the
+    /// log files lazily as the iterator advances, so a large range does not need to fit in
+    /// memory. Keys removed in the meantime are skipped and values set in the meantime are
+    /// returned.
+    ///
+    /// # Errors
+    ///
+    /// Each item propagates I/O or deserialization errors while reading its value from the log.
+    /// With a hash index, the only item is `KvsError::Unsupported`.
+    pub fn scan(&self, range: impl RangeBounds<String>) -> impl Iterator<Item = Result<(String, String)>> + '_ {
+        let keys = self.lock().index.range(range).map(|range| range.map(|(key, _)| key.clone()).collect());
+
+        match keys {
+            Ok(keys) => Either::Left(self.read_keys(keys)),
+            Err(e) => Either::Right(iter::once(Err(e)))
         }
     }
-}
 
-/// Get sorted vector of log file ids inside the given directory
-fn sort_log_files(path: &PathBuf) -> Result<Vec<u64>> {
-    let mut file_ids: Vec<u64> = read_dir(&path)?
-        .flat_map(|entry| -> Result<_> { Ok(entry?.path()) }) // Get path for each entry in the directory, ignoring errors by using flat_map
-        .filter(|path| path.is_file() && path.extension() == Some("log".as_ref())) // Filter entries which are files and have .log extension
-        .flat_map(|file| { // flat_map ignores None values, keeping only Some(value)
-            file.file_name()
-            .and_then(OsStr::to_str)
-            .map(|s| s.trim_end_matches(".log")) // Remove .log from file name and keep only log file id number
-            .map(|s| s.parse::<u64>())
-        })
-        .flatten() // Ignore errors coming from Result<u64, ParseIntError> and keep only Ok values
-        .collect();
+    /// Iterates all live key/value pairs in key order, reading the values lazily like `scan`.
+    ///
+    /// # Errors
+    ///
+    /// Each item propagates I/O or deserialization errors while reading its value from the log.
+    pub fn iter(&self) -> impl Iterator<Item = Result<(String, String)>> + '_ {
+        let keys = self.lock().keys();
 
-    // sort_unstable is faster than stable sort in some cases
-    file_ids.sort_unstable();
+        self.read_keys(keys)
+    }
 
-    Ok(file_ids)
-}
+    /// Iterates all live keys in key order using only the in-memory index,
+    /// so the log files are never read.
+    ///
+    /// The keys are collected when the iterator is created.
+    pub fn keys(&self) -> impl Iterator<Item = String> {
+        self.lock().keys().into_iter()
+    }
 
-/// Load log file and save log pointers of commands to in-memory index map
-///
-/// Returns the total number of bytes in the file that can be saved in compaction
-fn load_log_file(
-    id: u64,
-    reader: &mut BufReaderWithPos<File>, 
-    index: &mut BTreeMap<String, LogPointer>
-) -> Result<u64> {
-    // Deserialize commands comming from file reader stream
-    let mut pos: u64 = reader.seek(SeekFrom::Start(0))?; // Make sure file starts being read from first byte
-    let mut stream = Deserializer::from_reader(reader).into_iter::<Command>();
-    let mut uncompacted = 0;
-
-    // Run loop until None is received from stream.next()
-    while let Some(cmd) = stream.next() {
-        let end_pos = stream.byte_offset() as u64; // How many bytes were read from the iteration
-
-        match cmd? {
-            Command::Set { key, .. } => {
-                // Insert returns None if key-value pair did not exist
-                // or returns the previous value if it already existed
-                if let Some(old_cmd) = index.insert(key, (id, pos..end_pos).into()) {
-                    // Add old command's bytes to uncompacted counter
-                    uncompacted += old_cmd.len;
-                }
-            },
-            Command::Remove { key } => {
-                if let Some(old_cmd) = index.remove(&key) {
-                    // Add old command's bytes to uncompacted counter
-                    uncompacted += old_cmd.len;
-                };
+    /// Returns the smallest live key, using only the in-memory index.
+    ///
+    /// Like the other navigation methods, it reflects the live keys at the time of the call.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::Unsupported` with a hash index, like the other navigation methods.
+    pub fn first_key(&self) -> Result<Option<String>> {
+        self.lock().first_key()
+    }
 
-                // The "remove" command itself can be deleted in the next compaction
-                // so we add its length to the uncompacted counter
-                uncompacted += end_pos - pos;
-            },
-            _ => {}
-        }
+    /// Returns the biggest live key, using only the in-memory index.
+    pub fn last_key(&self) -> Result<Option<String>> {
+        self.lock().last_key()
+    }
 
-        // end_pos becomes pos for the next iteration
-        pos = end_pos;
+    /// Returns the smallest live key bigger than `after`, using only the in-memory index.
+    ///
+    /// Starting from `first_key`, it allows paging through the keys without keeping a
+    /// cursor open, since keys set or removed between calls are taken into account.
+    pub fn next_key(&self, after: &str) -> Result<Option<String>> {
+        self.lock().next_key(after)
     }
 
-    Ok(uncompacted)
-}
+    /// Iterates the live key/value pairs in the order they were last written.
+    ///
+    /// Commands already compacted in key order keep that order. Like `scan`, the keys are
+    /// collected when the iterator is created and the values are read lazily.
+    ///
+    /// # Errors
+    ///
+    /// Each item propagates I/O or deserialization errors while reading its value from the log.
+    pub fn iter_by_seq(&self) -> impl Iterator<Item = Result<(String, String)>> + '_ {
+        let keys = self.lock().keys_by_seq();
 
-/// Create a new log file with given log file id and add the reader to the readers map.
-///
-/// Returns the writer to the log.
-fn create_new_log_file(
-    path: &PathBuf,
-    log_file_id: u64, 
-    readers: &mut HashMap<u64, BufReaderWithPos<File>>
-) -> Result<BufWriterWithPos<File>> {
-    // Filepath for new log file
-    let filepath = path.join(format!("{}.log", log_file_id));
+        self.read_keys(keys)
+    }
 
-    // Create writer for new log file
-    let writer = BufWriterWithPos::new(
-    OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&filepath)?
-    )?;
+    /// Reads the values of the given keys one at a time, skipping keys that no longer exist.
+    ///
+    /// The store is only locked while each value is read, so other threads can use it
+    /// between items.
+    fn read_keys(&self, keys: Vec<String>) -> impl Iterator<Item = Result<(String, String)>> + '_ {
+        keys
+            .into_iter()
+            .filter_map(move |key| {
+                let value = self.lock().get(key.clone());
+                value.map(|value| value.map(|value| (key, value))).transpose()
+            })
+    }
 
-    // Create reader for new log file and add it to readers hash map
-    // Reader is created after the writer because the writer creates the file at the given path
-    // if it does not exist
-    let reader = BufReaderWithPos::new(File::open(&filepath)?);
-    readers.insert(log_file_id, reader);
+    /// Locks the state shared by all clones of the store.
+    fn lock(&self) -> MutexGuard<'_, KvStoreInner> {
+        self.inner.lock().expect("KvStore lock poisoned")
+    }
 
-    Ok(writer)
-}
\ No newline at end of file
+    /// Opens the store in the given directory, or in memory if there is none, and starts
+    /// the expiry sweeper if the options configure one.
+    fn open_inner(path: Option<PathBuf>, config: KvStoreConfig, progress: &mut dyn FnMut(usize, usize, u64)) -> Result<KvStore> {
+        let expiry_sweep_interval = config.expiry_sweep_interval;
+        let store = KvStore::from_inner(KvStoreInner::open(path, config, progress)?);
+
+        if let Some(interval) = expiry_sweep_interval {
+            // Dropping the sender with the store wakes the sweeper up, which then stops
+            let (sender, receiver) = mpsc::channel();
+            let this = Arc::downgrade(&store.inner);
+            thread::Builder::new()
+                .name("kvs-expiry-sweeper".to_owned())
+                .spawn(move || sweep_expired_in_background(this, receiver, interval))?;
+            store.lock().expiry_sweeper = Some(sender);
+        }
+
+        Ok(store)
+    }
+
+    /// Shares the state of a newly opened store, which keeps a weak reference to itself
+    /// for background compactions.
+    fn from_inner(mut inner: KvStoreInner) -> KvStore {
+        KvStore {
+            inner: Arc::new_cyclic(|this| {
+                inner.this = this.clone();
+                Mutex::new(inner)
+            })
+        }
+    }
+}
+
+impl KvsEngine for KvStore {
+    fn name(&self) -> &'static str {
+        "kvs"
+    }
+
+    /// Gets the string value of a given string key.
+    ///
+    /// Returns `None` if the given key does not exist.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::InvalidKey` if the key is empty, `KvsError::UnexpectedCommand`
+    /// if the given command is not a Set command and `KvsError::Utf8Error` if the value was
+    /// set as bytes that are not valid UTF-8.
+    fn get(&self, key: String) -> Result<Option<String>> {
+        self.lock().get(key)
+    }
+
+    /// Gets the string values of the given keys, in the same order as the keys.
+    ///
+    /// Missing keys are `None` in place. The log pointers of all keys are looked up first
+    /// and their values read sorted by log file and position, so the log files are
+    /// read front to back instead of seeking back and forth.
+    ///
+    /// # Errors
+    ///
+    /// It returns the same errors as `get` for the first key that fails.
+    fn get_many(&self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        self.lock().get_many(keys)
+    }
+
+    /// Checks whether a given string key exists using only the in-memory index,
+    /// so the log files are never read.
+    fn contains_key(&self, key: String) -> Result<bool> {
+        self.lock().contains_key(key)
+    }
+
+    /// Returns every live key in key order using only the in-memory index,
+    /// so the log files are never read.
+    fn all_keys(&self) -> Result<Vec<String>> {
+        Ok(self.lock().keys())
+    }
+
+    /// Returns the number of live keys using only the in-memory index,
+    /// so the log files are never read.
+    fn len(&self) -> Result<usize> {
+        self.lock().len()
+    }
+
+    /// Returns the number of live keys and the sizes of their values using only the
+    /// in-memory index, which keeps the length of every value, so the log files are never read.
+    fn size_stats(&self) -> Result<SizeStats> {
+        Ok(self.lock().size_stats())
+    }
+
+    /// Returns the log file, value length and expiry of a key using only the in-memory index,
+    /// so the log files are never read.
+    fn stat_key(&self, key: String) -> Result<Option<KeyStat>> {
+        Ok(self.lock().stat_key(&key))
+    }
+
+    /// Returns at most `limit` live keys starting with the prefix in key order, after `after`
+    /// if it is given, walking only the matching range of the in-memory index.
+    fn keys_page(&self, prefix: String, after: Option<String>, limit: usize) -> Result<Vec<String>> {
+        Ok(self.lock().keys_page(&prefix, after.as_deref(), limit))
+    }
+
+    /// Sets the value of a string key to a string.
+    ///
+    /// If the key already exists, the previous value will be overwritten.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::InvalidKey` if the key is empty and `KvsError::ValueTooLarge`
+    /// if the value is bigger than the configured maximum value size.
+    ///
+    /// It propagates I/O or serialization errors while writing to the log
+    fn set(&self, key: String, value: String) -> Result<()> {
+        self.lock().set(key, value)
+    }
+
+    /// Gets the value of a given string key as bytes, without UTF-8 validation.
+    ///
+    /// Returns `None` if the given key does not exist.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::UnexpectedCommand` if the given command is not a Set command.
+    fn get_bytes(&self, key: String) -> Result<Option<Vec<u8>>> {
+        self.lock().get_bytes(key)
+    }
+
+    /// Sets the value of a string key to arbitrary bytes.
+    ///
+    /// If the key already exists, the previous value will be overwritten.
+    ///
+    /// # Errors
+    ///
+    /// It propagates I/O or serialization errors while writing to the log
+    fn set_bytes(&self, key: String, value: Vec<u8>) -> Result<()> {
+        self.lock().set_bytes(key, value)
+    }
+
+    /// Removes a given key.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::InvalidKey` if the key is empty and `KvsError::KeyNotFound`
+    /// if the given key is not found.
+    ///
+    /// It propagates I/O or serialization errors while writing to the log.
+    fn remove(&self, key: String) -> Result<()> {
+        self.lock().remove(key)
+    }
+
+    /// Removes every key starting with the given prefix and returns how many were removed.
+    ///
+    /// The keys are found in the ordered index without looking at the other keys, and their
+    /// Remove commands are written as a single transaction. A prefix matching no key removes
+    /// nothing and returns 0.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::InvalidKey` if the prefix is empty.
+    ///
+    /// It propagates I/O or serialization errors while writing to the log.
+    fn remove_prefix(&self, prefix: String) -> Result<usize> {
+        self.lock().remove_prefix(prefix)
+    }
+
+    /// Sets the value of a string key to a string and returns the value it replaced.
+    ///
+    /// Returns `None` if the key did not exist.
+    ///
+    /// # Errors
+    ///
+    /// It propagates the errors of `get` while reading the previous value, in which case
+    /// nothing is written, and the errors of `set`.
+    fn set_and_get_previous(&self, key: String, value: String) -> Result<Option<String>> {
+        self.lock().set_and_get_previous(key, value)
+    }
+
+    /// Removes a given key and returns its value.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::KeyNotFound` if the given key is not found.
+    ///
+    /// It propagates the errors of `get` while reading the value, in which case nothing
+    /// is written, and the errors of `remove`.
+    fn take(&self, key: String) -> Result<String> {
+        self.lock().take(key)
+    }
+
+    /// Sets the key to `new`, or removes it if `new` is `None`, only if its current value
+    /// is `expected`, where `None` means the key does not exist.
+    ///
+    /// Returns whether the swap happened. The store stays locked between the comparison and
+    /// the write, so no other clone of the store can change the key in between.
+    ///
+    /// # Errors
+    ///
+    /// It propagates I/O or serialization errors while reading or writing the log.
+    fn compare_and_swap(&self, key: String, expected: Option<String>, new: Option<String>) -> Result<bool> {
+        self.lock().compare_and_swap(key, expected, new)
+    }
+
+    /// Removes every key by starting a new empty log file and deleting all previous ones.
+    ///
+    /// The previous log files are deleted one at a time, so a crash in between can bring back
+    /// the keys of the log files that were not deleted yet.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::LogIdExhausted` if there are no log file ids left for the new
+    /// log file.
+    ///
+    /// It propagates I/O errors while creating or deleting log files.
+    fn clear(&self) -> Result<()> {
+        self.lock().clear()
+    }
+
+    /// Adds `delta` to the integer value of a given string key and returns the new value.
+    ///
+    /// A key that does not exist counts as 0. The key keeps its expiry, and the store stays
+    /// locked between the read and the write.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::NotAnInteger` if the current value is not an integer and
+    /// `KvsError::IntegerOverflow` if the new value does not fit in an `i64`.
+    ///
+    /// It propagates I/O or serialization errors while reading or writing the log.
+    fn increment(&self, key: String, delta: i64) -> Result<i64> {
+        self.lock().increment(key, delta)
+    }
+
+    /// Appends `suffix` to the value of a given string key and returns the length of the
+    /// new value in bytes.
+    ///
+    /// A key that does not exist counts as an empty string. The key keeps its expiry, and the
+    /// store stays locked between the read and the write. The previous value counts as stale
+    /// bytes to compact, like an overwrite with `set`.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::ValueTooLarge` if the new value is bigger than the configured
+    /// maximum size.
+    ///
+    /// It propagates I/O or serialization errors while reading or writing the log.
+    fn append(&self, key: String, suffix: String) -> Result<usize> {
+        self.lock().append(key, suffix)
+    }
+
+    /// Flushes buffered writes to the current log file.
+    ///
+    /// Writes are flushed right away unless the store was opened with `sync_on_write`
+    /// set to false, in which case this controls when they reach the log file.
+    ///
+    /// # Errors
+    ///
+    /// It propagates I/O errors while writing to the log.
+    fn flush(&self) -> Result<()> {
+        self.lock().flush()
+    }
+
+    /// Moves the value of the `from` key to the `to` key, overwriting any value of `to`.
+    ///
+    /// The Set command for `to` and the Remove command for `from` are applied as a single
+    /// transaction, so a reader never sees both keys or neither key holding the value.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::KeyNotFound` if the `from` key is not found.
+    ///
+    /// It propagates I/O or serialization errors while reading or writing the log.
+    fn rename(&self, from: String, to: String) -> Result<()> {
+        self.lock().rename(from, to)
+    }
+
+    /// Applies the given Set and Remove commands atomically.
+    ///
+    /// All commands are written to the log first and the in-memory index is only
+    /// updated once every command was written and flushed, so either all of them
+    /// take effect or none of them do.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::KeyNotFound` if a Remove command targets a key that does not
+    /// exist at that point of the transaction, and `KvsError::UnexpectedCommand` if a
+    /// command other than Set or Remove is given. Nothing is written in both cases.
+    ///
+    /// It propagates I/O or serialization errors while writing to the log.
+    fn transaction(&self, commands: Vec<Command>) -> Result<()> {
+        self.lock().transaction(commands)
+    }
+}
+
+/// State of a `KvStore`, shared by all its clones behind a mutex.
+#[derive(Debug)]
+struct KvStoreInner {
+    /// Directory for saving log files, or `None` if the log files are kept in memory.
+    path: Option<PathBuf>,
+    /// Map with log files' ids as keys and file readers as values.
+    readers: HashMap<u64, BufReaderWithPos<LogFile>>,
+    /// File writer of the current log file.
+    writer: BufWriterWithPos<LogFile>,
+    /// Current log file id.
+    current_log_id: u64,
+    /// In-memory index map with keys coming as the <KEY> value from the command line argument and 
+    /// values which are pointers to the location of the corresponding commands saved in the log files.
+    index: Index,
+    /// Bloom filter of the keys of the index, if enabled.
+    bloom: Option<BloomFilter>,
+    /// Cache of the most recently read values, if enabled.
+    cache: Option<ValueCache>,
+    /// Number of bytes representing "stale" commands that could be
+    /// deleted during compaction. It saturates instead of overflowing,
+    /// which still triggers compaction, and is reset by it.
+    uncompacted: u64,
+    /// Number of stale bytes above which compaction runs automatically.
+    compaction_threshold: u64,
+    /// Whether every write is flushed to its log file right away.
+    sync_on_write: bool,
+    /// Size in bytes above which writes move on to a new log file.
+    max_log_size: Option<u64>,
+    /// Number of log files above which compaction runs automatically.
+    max_log_files: Option<usize>,
+    /// How commands are written to the log files.
+    log_format: LogFormat,
+    /// How commands are serialized in framed log files.
+    codec: Codec,
+    /// Size in bytes above which values are rejected.
+    max_value_size: Option<u64>,
+    /// Capacity in bytes of the buffer of every log file reader and writer.
+    buf_capacity: usize,
+    /// Names of the log files.
+    log_file_names: LogFileNames,
+    /// Number of bytes that must remain free on disk after compaction copied all live commands.
+    min_free_space: u64,
+    /// Order in which compaction writes the live commands.
+    compaction_order: CompactionOrder,
+    /// When compaction runs.
+    compaction_mode: CompactionMode,
+    /// Compaction started by `compact_step` which has not copied every live command yet.
+    compaction: Option<CompactionProgress>,
+    /// Whether a background compaction thread is running.
+    background_compaction: bool,
+    /// Reference to the shared state of the store, given to background compaction threads.
+    this: Weak<Mutex<KvStoreInner>>,
+    /// Senders of the change events of the subscribed keys.
+    subscriptions: Subscriptions,
+    /// Channel to the expiry sweeper thread, if any, which stops once it is dropped.
+    expiry_sweeper: Option<Sender<()>>,
+    /// Lock file of the directory, held so that no other store writes to the same log files.
+    /// Closing it when the store is dropped releases the lock. `None` for a store in memory.
+    _lock_file: Option<File>,
+}
+
+/// State of a compaction which copies the live commands to the compaction file a chunk at a time.
+#[derive(Debug)]
+struct CompactionProgress {
+    /// Id of the compaction file. Live commands in log files with smaller ids are copied to it.
+    log_file_id: u64,
+    /// Writer of the compaction file.
+    writer: BufWriterWithPos<LogFile>,
+    /// Keys left to copy, in reverse compaction order.
+    pending: Vec<String>,
+    /// Number of stale bytes in the log files that are deleted once the compaction is
+    /// finished, counted when it started and as keys that were not copied yet change.
+    uncompacted: u64,
+}
+
+impl KvStoreInner {
+    /// Opens the store in the given directory, or in memory if there is none, reporting
+    /// the progress of the log load like `KvStore::open_with_progress`.
+    fn open(path: Option<PathBuf>, config: KvStoreConfig, progress: &mut dyn FnMut(usize, usize, u64)) -> Result<KvStoreInner> {
+        if config.compaction_threshold == 0 {
+            return Err(KvsError::InvalidCompactionThreshold);
+        }
+
+        if config.max_log_files.is_some_and(|max_log_files| max_log_files < 2) {
+            return Err(KvsError::InvalidMaxLogFiles);
+        }
+
+        if config.expiry_sweep_interval == Some(Duration::ZERO) {
+            return Err(KvsError::InvalidSweepInterval);
+        }
+
+        let (path, lock_file, file_ids, mut file_readers) = match path {
+            Some(mut path) => {
+                // Create directory if it does not exist
+                if let Some(log_dir) = &config.log_dir {
+                    path.push(log_dir);
+                }
+
+                create_dir_all(&path)?;
+                let lock_file = lock_dir(&path)?;
+
+                // Get sorted vector of log file ids inside the directory
+                let file_ids = sort_log_files(&path, &config.log_file_names)?;
+
+                // Create a reader for each log file
+                let file_readers = file_ids
+                    .iter()
+                    .map(|&id| {
+                        let filepath = path.join(config.log_file_names.file_name(id));
+                        Ok((id, BufReaderWithPos::with_capacity(config.buf_capacity, LogFile::Disk(File::open(filepath)?))))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                (Some(path), Some(lock_file), file_ids, file_readers)
+            },
+            // A store kept in memory starts without log files
+            None => (None, None, Vec::new(), Vec::new())
+        };
+
+        // Load the log files in parallel, splitting them in contiguous chunks of ids between threads
+        let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let chunk_size = file_readers.len().div_ceil(threads).max(1);
+        let total_files = file_readers.len();
+        progress(0, total_files, 0);
+
+        // Loading threads send the size of every log file they loaded
+        let (sender, receiver) = mpsc::channel();
+
+        let loaded_files = thread::scope(|scope| {
+            let handles: Vec<_> = file_readers
+                .chunks_mut(chunk_size)
+                .map(|chunk| {
+                    let sender = sender.clone();
+
+                    scope.spawn(move || {
+                        chunk
+                            .iter_mut()
+                            .map(|(id, reader)| {
+                                let loaded = load_log_file(*id, reader);
+                                // The size is only reported, so failing to read it does not fail the load
+                                let _ = sender.send(reader.reader.get_ref().len().unwrap_or(0));
+                                loaded
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+
+            // The callback runs on this thread until every loading thread dropped its sender
+            drop(sender);
+            let mut bytes_processed: u64 = 0;
+
+            for (files_loaded, len) in receiver.into_iter().enumerate() {
+                bytes_processed = bytes_processed.saturating_add(len);
+                progress(files_loaded + 1, total_files, bytes_processed);
+            }
+
+            // Joining the handles in order keeps the results sorted by log file id
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().expect("Log loading thread panicked"))
+                .collect::<Result<Vec<_>>>()
+        })?;
+
+        // Instantiate in-memory index map and merge the log files into it from oldest to newest,
+        // so that commands from newer log files override the ones from older log files
+        let mut index = Index::new(config.index);
+        let mut uncompacted: u64 = 0; // Number of bytes that can be saved after compaction
+
+        for (entries, file_uncompacted) in loaded_files {
+            uncompacted = uncompacted.saturating_add(file_uncompacted);
+
+            for (key, entry) in entries {
+                let old_cmd = match entry {
+                    Some(log_pointer) => index.insert(key, log_pointer),
+                    None => index.remove(&key)
+                };
+
+                // Add overridden command's bytes to uncompacted counter
+                if let Some(old_cmd) = old_cmd {
+                    uncompacted = uncompacted.saturating_add(old_cmd.record_len());
+                }
+            }
+        }
+
+        // The bloom filter is built from the keys of the merged index
+        let bloom = config.bloom_filter.then(|| BloomFilter::with_keys(index.keys()));
+
+        // Instantiate file readers hash map
+        let mut readers: HashMap<u64, BufReaderWithPos<LogFile>> = file_readers.into_iter().collect();
+
+        // Get file id of last log file and add 1 to it for the new log file
+        let current_log_id = file_ids
+            .last()
+            .unwrap_or(&0)
+            .checked_add(1)
+            .ok_or(KvsError::LogIdExhausted)?;
+
+        // Create writer for new log file (it also creates a reader and adds it to readers hash map)
+        let writer = create_new_log_file(
+            path.as_deref(),
+            &config.log_file_names,
+            current_log_id,
+            config.buf_capacity,
+            &mut readers
+        )?;
+        
+        Ok(KvStoreInner {
+            path,
+            readers,
+            writer,
+            current_log_id,
+            index,
+            bloom,
+            cache: config.value_cache.map(ValueCache::new),
+            uncompacted,
+            compaction_threshold: config.compaction_threshold,
+            sync_on_write: config.sync_on_write,
+            max_log_size: config.max_log_size,
+            max_log_files: config.max_log_files,
+            log_format: config.log_format,
+            codec: config.codec,
+            max_value_size: config.max_value_size,
+            buf_capacity: config.buf_capacity,
+            log_file_names: config.log_file_names,
+            min_free_space: MIN_FREE_SPACE,
+            compaction_order: CompactionOrder::KeySorted,
+            compaction_mode: CompactionMode::Automatic,
+            compaction: None,
+            background_compaction: false,
+            this: Weak::new(),
+            subscriptions: Subscriptions::default(),
+            expiry_sweeper: None,
+            _lock_file: lock_file,
+        })
+    }
+
+    fn set_min_free_space(&mut self, bytes: u64) {
+        self.min_free_space = bytes;
+    }
+
+    fn set_compaction_order(&mut self, order: CompactionOrder) {
+        self.compaction_order = order;
+    }
+
+    fn set_compaction_mode(&mut self, mode: CompactionMode) {
+        self.compaction_mode = mode;
+    }
+
+    fn compact(&mut self) -> Result<()> {
+        // A compaction in progress only covers the log files written before it started,
+        // so it is finished before a new one copies everything written since
+        if self.compaction.is_some() {
+            self.compact_step(u64::MAX)?;
+        }
+
+        self.compact_step(u64::MAX)?;
+
+        Ok(())
+    }
+
+    fn compact_step(&mut self, max_bytes: u64) -> Result<bool> {
+        let mut progress = match self.compaction.take() {
+            Some(progress) => progress,
+            None => self.start_compaction()?
+        };
+
+        // The compaction is kept on errors, so a later step can continue it
+        let result = self.copy_live_commands(&mut progress, max_bytes);
+
+        if result.is_err() || !progress.pending.is_empty() {
+            self.compaction = Some(progress);
+            return result.map(|()| true);
+        }
+
+        // In manual mode the new log files must be durable before the old ones are deleted
+        if self.compaction_mode == CompactionMode::Manual {
+            progress.writer.writer.get_ref().sync_all()?;
+            self.writer.writer.get_ref().sync_all()?;
+        }
+
+        // Delete the log files which are no longer being used
+        self.remove_log_files_before(progress.log_file_id)?;
+
+        // The stale bytes from before the compaction were deleted with the old log files
+        self.uncompacted = self.uncompacted.saturating_sub(progress.uncompacted);
+
+        Ok(false)
+    }
+
+    /// Creates the compaction file and lists the keys to copy to it.
+    fn start_compaction(&mut self) -> Result<CompactionProgress> {
+        // Expired keys are not copied
+        self.remove_expired();
+
+        // Compaction copies every live command before the old log files can be deleted
+        if let Some(path) = &self.path {
+            let live = self.index
+                .values()
+                .fold(0u64, |live, log_pointer| live.saturating_add(log_pointer.record_len()));
+            let required = live.saturating_add(self.min_free_space);
+            let available = fs2::available_space(path)?;
+
+            if available < required {
+                return Err(KvsError::InsufficientSpace { required, available });
+            }
+        }
+
+        // Buffered writes must reach the current log file before it is copied and replaced
+        self.writer.flush()?;
+
+        // Set log file id for compaction file
+        let compaction_log_file_id = self.current_log_id
+            .checked_add(1)
+            .ok_or(KvsError::LogIdExhausted)?;
+
+        // Set log file id for new writable log file after compaction is finished
+        // The compaction file will be immutable and users will start writing new logs
+        // in a new file
+        self.current_log_id = compaction_log_file_id
+            .checked_add(1)
+            .ok_or(KvsError::LogIdExhausted)?;
+        self.writer = create_new_log_file(
+            self.path.as_deref(),
+            &self.log_file_names,
+            self.current_log_id,
+            self.buf_capacity,
+            &mut self.readers
+        )?;
+
+        // Create writer for compaction file
+        let compaction_writer = create_new_log_file(
+            self.path.as_deref(),
+            &self.log_file_names,
+            compaction_log_file_id,
+            self.buf_capacity,
+            &mut self.readers
+        )?;
+
+        // Values in the in-memory index map are the latest values stored in the database
+        let mut entries: Vec<(&String, &LogPointer)> = match self.compaction_order {
+            CompactionOrder::KeySorted => self.index.sorted_range(..).collect(),
+            CompactionOrder::WriteOrder => self.index.iter().collect()
+        };
+
+        // Log file ids and positions inside a log file only grow, so together they give the write order
+        if self.compaction_order == CompactionOrder::WriteOrder {
+            entries.sort_unstable_by_key(|(_, log_pointer)| (log_pointer.log_file_id, log_pointer.start_position));
+        }
+
+        // Keys are popped from the end, so they are stored in reverse order
+        let pending = entries.into_iter().rev().map(|(key, _)| key.clone()).collect();
+
+        Ok(CompactionProgress {
+            log_file_id: compaction_log_file_id,
+            writer: compaction_writer,
+            pending,
+            uncompacted: self.uncompacted,
+        })
+    }
+
+    /// Copies the live commands of the next pending keys to the compaction file
+    /// until at least `max_bytes` were copied or no key is left.
+    fn copy_live_commands(&mut self, progress: &mut CompactionProgress, max_bytes: u64) -> Result<()> {
+        let mut copied: u64 = 0;
+
+        // A key is only popped once copied, so a failed copy is retried by the next step
+        while let Some(key) = progress.pending.last() {
+            copied = copied.saturating_add(self.copy_live_command(progress.log_file_id, &mut progress.writer, key)?);
+            progress.pending.pop();
+
+            if copied >= max_bytes {
+                break;
+            }
+        }
+
+        // Copied commands must reach the compaction file before they can be read
+        progress.writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Copies the live command of the given key to the compaction file
+    /// and returns the number of copied bytes.
+    fn copy_live_command(
+        &mut self,
+        compaction_log_file_id: u64,
+        compaction_writer: &mut BufWriterWithPos<LogFile>,
+        key: &str
+    ) -> Result<u64> {
+        // Keys removed or written again since the compaction started are not copied,
+        // since their current command is not in a log file that will be deleted
+        let log_pointer = match self.index.get_mut(key) {
+            Some(log_pointer) if log_pointer.log_file_id < compaction_log_file_id => log_pointer,
+            _ => return Ok(0)
+        };
+
+        // Keys which expired since the compaction started are dropped instead
+        if log_pointer.is_expired(now_millis()) {
+            self.index.remove(key);
+            return Ok(0);
+        }
+
+        // Read the bytes of the serialized command that pertain to the log pointer
+        let mut payload = read_payload(&mut self.readers, key, log_pointer)?;
+
+        // Commands serialized with another codec are serialized again with the current one
+        let codec = write_codec(self.log_format, self.codec);
+        if Codec::detect(&payload) != codec {
+            payload = codec.encode(&Codec::decode(&payload)?)?;
+        }
+
+        // Copy the command to the compaction file inside a new record
+        let (range, crc) = write_record_payload(compaction_writer, self.log_format, &payload)?;
+
+        // Update log pointer in the in-memory index map to refer to the compaction file
+        // instead of the original log file
+        let (expires_at, value_len) = (log_pointer.expires_at, log_pointer.value_len);
+        let old_location = cache::location(log_pointer);
+        *log_pointer = (compaction_log_file_id, range, crc).into();
+        log_pointer.expires_at = expires_at;
+        log_pointer.value_len = value_len;
+        log_pointer.framing = record_framing(self.log_format);
+
+        // The value did not change, so a cached copy of it stays valid at its new location
+        if let Some(cache) = &mut self.cache {
+            cache.relocate(key, old_location, log_pointer);
+        }
+
+        Ok(log_pointer.len)
+    }
+
+    /// Deletes the log files with an id smaller than the given one, together with their readers.
+    fn remove_log_files_before(&mut self, log_file_id: u64) -> Result<()> {
+        // Get all log file ids which are no longer being used
+        let old_logs: Vec<u64> = self.readers
+            .keys()
+            .filter(|&&id| id < log_file_id)
+            .copied()
+            .collect();
+
+        // Delete unused log files
+        for old_log in old_logs.iter() {
+            // Delete log file reader
+            self.readers.remove(old_log);
+
+            // Delete log file from directory
+            if let Some(path) = &self.path {
+                fs::remove_file(path.join(self.log_file_names.file_name(*old_log)))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs compaction if the uncompacted bytes exceed the threshold,
+    /// unless compaction is in manual mode or already in progress.
+    ///
+    /// Running out of disk space is not an error here because the write that triggered
+    /// the compaction already succeeded. Compaction is attempted again on the next write.
+    fn compact_if_needed(&mut self) -> Result<()> {
+        // A background compaction which stopped on an error is also resumed here
+        if self.compaction_mode == CompactionMode::Background && !self.background_compaction
+            && (self.compaction.is_some() || self.needs_compaction())
+        {
+            let this = self.this.clone();
+            thread::Builder::new()
+                .name("kvs-compaction".to_owned())
+                .spawn(move || compact_in_background(this))?;
+            self.background_compaction = true;
+
+            return Ok(());
+        }
+
+        // A compaction in progress is advanced by whoever called `compact_step`
+        if self.compaction.is_some() {
+            return Ok(());
+        }
+
+        if self.compaction_mode == CompactionMode::Automatic && self.needs_compaction() {
+            match self.compact() {
+                Err(KvsError::InsufficientSpace { .. }) => {},
+                result => return result
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves writes on to a new log file once the current one exceeds the maximum log size.
+    fn rotate_if_needed(&mut self) -> Result<()> {
+        if self.max_log_size.is_some_and(|max_log_size| self.writer.pos > max_log_size) {
+            // Buffered writes must reach the current log file before its writer is replaced
+            self.writer.flush()?;
+            self.start_new_log_file()?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the writer with one for a new log file, without flushing the current one
+    fn start_new_log_file(&mut self) -> Result<()> {
+        self.current_log_id = self.current_log_id
+            .checked_add(1)
+            .ok_or(KvsError::LogIdExhausted)?;
+        self.writer = create_new_log_file(
+            self.path.as_deref(),
+            &self.log_file_names,
+            self.current_log_id,
+            self.buf_capacity,
+            &mut self.readers
+        )?;
+
+        Ok(())
+    }
+
+    /// Drops the bytes of a failed write, which start at `pos`, from the current log file.
+    ///
+    /// Commands written after a partial record would be lost when the log file is loaded, and
+    /// a buffered record must not reach the log file later. If the log file cannot be truncated,
+    /// later writes go to a new log file instead.
+    fn roll_back_write(&mut self, pos: u64) {
+        // The reader may have buffered bytes of the failed write, which the next write replaces
+        if let Some(reader) = self.readers.get_mut(&self.current_log_id) {
+            let _ = reader.seek(SeekFrom::Start(pos));
+        }
+
+        if self.writer.truncate(pos).is_err() {
+            let _ = self.start_new_log_file();
+        }
+    }
+
+    /// Appends a command to the log, flushing it in sync mode, and returns where it was written.
+    ///
+    /// Nothing is left in the log if it fails.
+    fn append_record(&mut self, cmd: &Command) -> Result<(Range<u64>, u32)> {
+        let pos = self.writer.pos;
+
+        let result = write_record(&mut self.writer, self.log_format, self.codec, cmd)
+            .and_then(|written| self.flush_if_sync().map(|()| written));
+
+        if result.is_err() {
+            self.roll_back_write(pos);
+        }
+
+        result
+    }
+
+    /// Writes the commands to the log between batch markers and returns where each one was written
+    ///
+    /// Commands after a `BatchBegin` marker are only loaded back once the matching
+    /// `BatchCommit` marker is found, so a batch cut short by a crash is never partially applied.
+    fn write_batch(&mut self, commands: &[Command]) -> Result<Vec<(Range<u64>, u32)>> {
+        let count = commands.len() as u64;
+        let mut positions = Vec::with_capacity(commands.len());
+
+        let (begin, _) = write_record(&mut self.writer, self.log_format, self.codec, &Command::BatchBegin { count })?;
+
+        for cmd in commands {
+            positions.push(write_record(&mut self.writer, self.log_format, self.codec, cmd)?);
+        }
+
+        let (commit, _) = write_record(&mut self.writer, self.log_format, self.codec, &Command::BatchCommit { count })?;
+        self.flush_if_sync()?;
+
+        // Batch markers are never live, so they can be deleted in the next compaction
+        let framing = record_framing(self.log_format);
+        self.uncompacted = self.uncompacted
+            .saturating_add(begin.end - begin.start + framing)
+            .saturating_add(commit.end - commit.start + framing);
+
+        Ok(positions)
+    }
+
+    fn generation(&self) -> u64 {
+        self.current_log_id - 1
+    }
+
+    fn log_segments_since(&self, gen: u64) -> Vec<(u64, PathBuf)> {
+        // Log files kept in memory have no path
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Vec::new()
+        };
+
+        let mut segments: Vec<(u64, PathBuf)> = self.readers
+            .keys()
+            .filter(|&&log_file_id| log_file_id > gen && log_file_id < self.current_log_id)
+            // The file of a compaction in progress is still written to
+            .filter(|&&log_file_id| self.compaction.as_ref().is_none_or(|progress| progress.log_file_id != log_file_id))
+            .map(|&log_file_id| (log_file_id, path.join(self.log_file_names.file_name(log_file_id))))
+            .collect();
+
+        segments.sort_unstable_by_key(|&(log_file_id, _)| log_file_id);
+
+        segments
+    }
+
+    fn stats(&mut self) -> CompactionStats {
+        self.remove_expired();
+
+        CompactionStats {
+            uncompacted: self.uncompacted,
+            live_keys: self.index.len(),
+            log_files: self.readers.len(),
+            compaction_threshold: self.compaction_threshold,
+            max_log_files: self.max_log_files,
+        }
+    }
+
+    fn needs_compaction(&self) -> bool {
+        self.uncompacted > self.compaction_threshold
+            || self.max_log_files.is_some_and(|max_log_files| self.readers.len() > max_log_files)
+    }
+
+    fn compaction_preview(&mut self) -> Result<CompactionPreview> {
+        self.remove_expired();
+
+        // The writers know the size of their log files including the bytes they buffer
+        let compaction_writer = self.compaction.as_ref().map(|progress| (progress.log_file_id, progress.writer.pos));
+        let mut total_bytes: u64 = 0;
+
+        for (&log_file_id, reader) in &self.readers {
+            let len = match compaction_writer {
+                _ if log_file_id == self.current_log_id => self.writer.pos,
+                Some((compaction_log_file_id, pos)) if log_file_id == compaction_log_file_id => pos,
+                _ => reader.reader.get_ref().len()?
+            };
+
+            total_bytes = total_bytes.saturating_add(len);
+        }
+
+        let live_bytes = self.index
+            .values()
+            .fold(0u64, |live, log_pointer| live.saturating_add(log_pointer.record_len()));
+
+        Ok(CompactionPreview {
+            total_bytes,
+            live_bytes,
+            reclaimable_bytes: total_bytes.saturating_sub(live_bytes),
+        })
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Flushes the write that was just appended unless writes stay buffered.
+    fn flush_if_sync(&mut self) -> Result<()> {
+        if self.sync_on_write {
+            self.writer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Appends a Set or SetBytes command for the given key to the log and points the index to it.
+    fn append_set(&mut self, key: String, cmd: &Command) -> Result<()> {
+        cmd.validate(self.max_value_size)?;
+
+        // Serialize the command and append it to the file
+        let (range, crc) = self.append_record(cmd)?;
+
+        // Create log pointer for the appended command
+        let mut value: LogPointer = (self.current_log_id, range, crc).into();
+        value.expires_at = expires_at(cmd);
+        value.value_len = value_len(cmd);
+        value.framing = record_framing(self.log_format);
+        
+        // Insert log pointer in the in-memory index map
+        // If the key already existed, add the bytes of the old value to the uncompacted property
+        self.add_to_bloom(&key);
+        self.uncache(&key);
+        if let Some(old_cmd) = self.index.insert(key, value) {
+            self.add_stale(&old_cmd);
+        };
+
+        self.subscriptions.notify(cmd);
+
+        // Start a new log file if the current one grew too big, then compact if needed
+        self.rotate_if_needed()?;
+        self.compact_if_needed()?;
+
+        Ok(())
+    }
+
+    /// Removes the cached value of a key that is written or removed, if there is a cache.
+    fn uncache(&mut self, key: &str) {
+        if let Some(cache) = &mut self.cache {
+            cache.remove(key);
+        }
+    }
+
+    /// Reads the value of a key from the value cache if it holds it, and from the log otherwise,
+    /// caching it. Returns `None` if the key is not in the index.
+    ///
+    /// Buffered writes must be flushed first.
+    fn read_cached(&mut self, key: &str) -> Result<Option<Vec<u8>>> {
+        let log_pointer = match self.index.get(key) {
+            Some(log_pointer) => log_pointer,
+            None => return Ok(None)
+        };
+
+        if let Some(value) = self.cache.as_mut().and_then(|cache| cache.get(key, log_pointer)) {
+            return Ok(Some(value.to_vec()));
+        }
+
+        let value = match read_command(&mut self.readers, key, log_pointer)? {
+            Command::Set { value, .. } => value.into_bytes(),
+            Command::SetBytes { value, .. } => value,
+            _ => return Err(KvsError::UnexpectedCommand)
+        };
+
+        if let Some(cache) = &mut self.cache {
+            cache.insert(key.to_owned(), log_pointer, value.clone());
+        }
+
+        Ok(Some(value))
+    }
+
+    /// Whether the key can be in the index, which is only ruled out by the bloom filter.
+    fn might_contain(&self, key: &str) -> bool {
+        self.bloom.as_ref().is_none_or(|bloom| bloom.might_contain(key))
+    }
+
+    /// Adds a key about to be inserted in the index to the bloom filter, if there is one.
+    ///
+    /// A full filter is first rebuilt from the index with room for as many keys again,
+    /// which also drops the removed keys.
+    fn add_to_bloom(&mut self, key: &str) {
+        if let Some(bloom) = &mut self.bloom {
+            if bloom.is_full() {
+                *bloom = BloomFilter::with_keys(self.index.keys());
+            }
+
+            bloom.insert(key);
+        }
+    }
+
+    /// Removes the key from the index if its value expired.
+    ///
+    /// Expired keys are removed lazily when they are accessed, or all at once by compaction.
+    fn expire(&mut self, key: &str) {
+        let now = now_millis();
+
+        if self.index.get(key).is_some_and(|log_pointer| log_pointer.is_expired(now)) {
+            if let Some(old_cmd) = self.index.remove(key) {
+                self.add_stale(&old_cmd);
+            }
+        }
+    }
+
+    /// Returns the keys whose value expired, in no particular order.
+    fn expired_keys(&self) -> Vec<String> {
+        let now = now_millis();
+
+        self.index
+            .iter()
+            .filter(|(_, log_pointer)| log_pointer.is_expired(now))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Appends a Remove command for each of the given keys that is still expired and removes
+    /// it from the index. Returns the number of removed keys.
+    fn sweep_expired(&mut self, keys: &[String]) -> Result<usize> {
+        let now = now_millis();
+        let mut removed = 0;
+
+        for key in keys {
+            // The key may have been set again or removed since it was found expired
+            if !self.index.get(key).is_some_and(|log_pointer| log_pointer.is_expired(now)) {
+                continue;
+            }
+
+            let cmd = Command::Remove { key: key.clone() };
+            let (range, _) = self.append_record(&cmd)?;
+
+            self.uncache(key);
+            if let Some(old_cmd) = self.index.remove(key) {
+                self.add_stale(&old_cmd);
+            }
+
+            // The Remove command itself is stale as soon as it is written
+            self.uncompacted = self.uncompacted.saturating_add(range.end - range.start + record_framing(self.log_format));
+
+            self.subscriptions.notify(&cmd);
+            removed += 1;
+        }
+
+        // Start a new log file if the current one grew too big, then compact if needed
+        self.rotate_if_needed()?;
+        self.compact_if_needed()?;
+
+        Ok(removed)
+    }
+
+    /// Removes every expired key from the index.
+    fn remove_expired(&mut self) {
+        let now = now_millis();
+        let mut expired = Vec::new();
+
+        self.index.retain(|_, log_pointer| {
+            if log_pointer.is_expired(now) {
+                expired.push(log_pointer.clone());
+                false
+            } else {
+                true
+            }
+        });
+
+        for log_pointer in &expired {
+            self.add_stale(log_pointer);
+        }
+    }
+
+    /// Counts the bytes of a command that is no longer live as uncompacted.
+    ///
+    /// A command in a log file that the compaction in progress will delete is also counted
+    /// with the stale bytes of that compaction, so that it is no longer counted once the log
+    /// file is gone. Otherwise overwriting or removing a key that was not copied yet would
+    /// leave bytes counted that no compaction can reclaim.
+    fn add_stale(&mut self, log_pointer: &LogPointer) {
+        self.uncompacted = self.uncompacted.saturating_add(log_pointer.record_len());
+
+        if let Some(progress) = &mut self.compaction {
+            if log_pointer.log_file_id < progress.log_file_id {
+                progress.uncompacted = progress.uncompacted.saturating_add(log_pointer.record_len());
+            }
+        }
+    }
+
+    fn set_with_ttl(&mut self, key: String, value: String, ttl: Duration) -> Result<()> {
+        let ttl = u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX);
+        let cmd = Command::Set {
+            key: key.clone(),
+            value,
+            expires_at: Some(now_millis().saturating_add(ttl))
+        };
+
+        self.append_set(key, &cmd)
+    }
+
+    fn update(&mut self, key: String, f: impl FnOnce(Option<String>) -> Option<String>) -> Result<()> {
+        let current = self.get(key.clone())?;
+        let existed = current.is_some();
+
+        match f(current) {
+            Some(value) => {
+                // The new value keeps the expiry of the current one
+                let expires_at = self.index.get(&key).and_then(|log_pointer| log_pointer.expires_at);
+                let cmd = Command::Set { key: key.clone(), value, expires_at };
+
+                self.append_set(key, &cmd)
+            },
+            None if existed => self.remove(key),
+            None => Ok(())
+        }
+    }
+
+    /// Returns the live keys in key order, after removing the expired ones from the index.
+    fn keys(&mut self) -> Vec<String> {
+        self.remove_expired();
+
+        self.index.sorted_range(..).map(|(key, _)| key.clone()).collect()
+    }
+
+    fn first_key(&mut self) -> Result<Option<String>> {
+        self.remove_expired();
+
+        Ok(self.index.range::<str, _>(..)?.next().map(|(key, _)| key.clone()))
+    }
+
+    fn last_key(&mut self) -> Result<Option<String>> {
+        self.remove_expired();
+
+        Ok(self.index.range::<str, _>(..)?.next_back().map(|(key, _)| key.clone()))
+    }
+
+    fn next_key(&mut self, after: &str) -> Result<Option<String>> {
+        self.remove_expired();
+
+        let next = self.index
+            .range::<str, _>((Bound::Excluded(after), Bound::Unbounded))?
+            .next()
+            .map(|(key, _)| key.clone());
+
+        Ok(next)
+    }
+
+    /// Reads the commands of all live keys in key order.
+    fn live_commands(&mut self) -> Result<Vec<Command>> {
+        // Buffered writes must reach the log file before they can be read
+        self.writer.flush()?;
+        self.remove_expired();
+
+        let readers = &mut self.readers;
+
+        self.index
+            .sorted_range(..)
+            .map(|(key, log_pointer)| read_command(readers, key, log_pointer))
+            .collect()
+    }
+
+    /// Returns the live keys sorted by the order in which they were last written.
+    ///
+    /// Log file ids and positions inside a log file only grow, so sorting the log pointers
+    /// by them gives the write order.
+    fn keys_by_seq(&mut self) -> Vec<String> {
+        self.remove_expired();
+
+        let mut entries: Vec<(&String, &LogPointer)> = self.index.iter().collect();
+
+        entries.sort_unstable_by_key(|(_, log_pointer)| (log_pointer.log_file_id, log_pointer.start_position));
+
+        entries.into_iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    fn keys_page(&mut self, prefix: &str, after: Option<&str>, limit: usize) -> Vec<String> {
+        self.remove_expired();
+
+        // Keys starting with the prefix are next to each other in the index
+        let start = match after {
+            Some(after) if after >= prefix => Bound::Excluded(after),
+            _ => Bound::Included(prefix)
+        };
+
+        self.index
+            .sorted_range((start, Bound::Unbounded))
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    fn size_stats(&mut self) -> SizeStats {
+        self.remove_expired();
+
+        let value_lens = self.index.values().map(|log_pointer| log_pointer.value_len);
+
+        SizeStats::from_value_lens(value_lens)
+    }
+
+    fn stat_key(&mut self, key: &str) -> Option<KeyStat> {
+        self.expire(key);
+
+        self.index.get(key).map(|log_pointer| KeyStat {
+            log_file_id: Some(log_pointer.log_file_id),
+            value_bytes: log_pointer.value_len,
+            expires_at: log_pointer.expires_at
+        })
+    }
+}
+
+impl Drop for KvStoreInner {
+    /// Flushes buffered writes so that they are not lost when the last clone of the store
+    /// is dropped, including the commands copied by a compaction in progress.
+    ///
+    /// Errors cannot be returned from here, so they are only reported on stderr, or as tracing
+    /// events with the `tracing` feature. `flush` should be called to handle them.
+    fn drop(&mut self) {
+        let compaction_writer = self.compaction.as_mut().map(|progress| &mut progress.writer);
+
+        for writer in std::iter::once(&mut self.writer).chain(compaction_writer) {
+            if let Err(e) = writer.flush() {
+                #[cfg(feature = "tracing")]
+                tracing::error!(error = %e, "failed to flush a log file while closing the store");
+                #[cfg(not(feature = "tracing"))]
+                eprintln!("Failed to flush a log file while closing the store: {}", e);
+            }
+        }
+    }
+}
+
+// The `KvsEngine` operations, run by `KvStore` while it holds the lock
+impl KvStoreInner {
+    fn get(&mut self, key: String) -> Result<Option<String>> {
+        validate_key(&key)?;
+
+        if !self.might_contain(&key) {
+            return Ok(None);
+        }
+
+        // Buffered writes must reach the log file before they can be read
+        self.writer.flush()?;
+        self.expire(&key);
+
+        match self.read_cached(&key)? {
+            Some(value) => Ok(Some(String::from_utf8(value)?)),
+            None => Ok(None)
+        }
+    }
+
+    fn get_many(&mut self, keys: Vec<String>) -> Result<Vec<Option<String>>> {
+        // Buffered writes must reach the log file before they can be read
+        self.writer.flush()?;
+
+        // Look up the log pointers of all keys before reading any value
+        let mut log_pointers = Vec::new();
+
+        for (position, key) in keys.iter().enumerate() {
+            validate_key(key)?;
+
+            if !self.might_contain(key) {
+                continue;
+            }
+
+            self.expire(key);
+
+            if let Some(log_pointer) = self.index.get(key) {
+                log_pointers.push((position, log_pointer.clone()));
+            }
+        }
+
+        log_pointers.sort_unstable_by_key(|(_, log_pointer)| (log_pointer.log_file_id, log_pointer.start_position));
+
+        let mut values = vec![None; keys.len()];
+
+        for (position, log_pointer) in log_pointers {
+            values[position] = Some(read_value(&mut self.readers, &keys[position], &log_pointer)?);
+        }
+
+        Ok(values)
+    }
+
+    fn contains_key(&mut self, key: String) -> Result<bool> {
+        validate_key(&key)?;
+
+        if !self.might_contain(&key) {
+            return Ok(false);
+        }
+        self.expire(&key);
+
+        Ok(self.index.contains_key(&key))
+    }
+
+    fn len(&mut self) -> Result<usize> {
+        self.remove_expired();
+
+        Ok(self.index.len())
+    }
+
+    fn set(&mut self, key: String, value: String) -> Result<()> {
+        let cmd = Command::Set {
+            key: key.clone(),
+            value,
+            expires_at: None
+        };
+
+        self.append_set(key, &cmd)
+    }
+
+    fn get_bytes(&mut self, key: String) -> Result<Option<Vec<u8>>> {
+        validate_key(&key)?;
+
+        if !self.might_contain(&key) {
+            return Ok(None);
+        }
+
+        // Buffered writes must reach the log file before they can be read
+        self.writer.flush()?;
+        self.expire(&key);
+
+        self.read_cached(&key)
+    }
+
+    fn set_bytes(&mut self, key: String, value: Vec<u8>) -> Result<()> {
+        let cmd = Command::SetBytes {
+            key: key.clone(),
+            value
+        };
+
+        self.append_set(key, &cmd)
+    }
+
+    fn remove(&mut self, key: String) -> Result<()> {
+        validate_key(&key)?;
+        self.expire(&key);
+
+        if !self.index.contains_key(&key) {
+            return Err(KvsError::KeyNotFound);
+        }
+
+        // Remove command to be added to the log file
+        let cmd = Command::Remove { key: key.clone() };
+
+        // Serialize the command and append it to the file, keeping the key if it fails
+        let (range, _) = self.append_record(&cmd)?;
+
+        // Add removed command's length to the uncompacted property
+        self.uncache(&key);
+        if let Some(old_cmd) = self.index.remove(&key) {
+            self.add_stale(&old_cmd);
+        }
+
+        // Add appended command's length to the uncompacted property
+        self.uncompacted = self.uncompacted.saturating_add(range.end - range.start + record_framing(self.log_format));
+
+        self.subscriptions.notify(&cmd);
+
+        // Start a new log file if the current one grew too big, then compact if needed
+        self.rotate_if_needed()?;
+        self.compact_if_needed()?;
+
+        Ok(())
+    }
+
+    fn remove_prefix(&mut self, prefix: String) -> Result<usize> {
+        validate_key(&prefix)?;
+
+        // Keys starting with the prefix are next to each other in the index
+        let keys: Vec<String> = self.index
+            .sorted_range((Bound::Included(prefix.as_str()), Bound::Unbounded))
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(&prefix))
+            .cloned()
+            .collect();
+
+        // Expired keys count as already removed
+        for key in &keys {
+            self.expire(key);
+        }
+
+        let commands: Vec<Command> = keys
+            .into_iter()
+            .filter(|key| self.index.contains_key(key))
+            .map(|key| Command::Remove { key })
+            .collect();
+
+        if commands.is_empty() {
+            return Ok(0);
+        }
+
+        let count = commands.len();
+        self.transaction(commands)?;
+
+        Ok(count)
+    }
+
+    fn set_and_get_previous(&mut self, key: String, value: String) -> Result<Option<String>> {
+        // The previous value must be read before the index points to the new command
+        let previous = self.get(key.clone())?;
+        self.set(key, value)?;
+
+        Ok(previous)
+    }
+
+    fn take(&mut self, key: String) -> Result<String> {
+        let value = self.get(key.clone())?.ok_or(KvsError::KeyNotFound)?;
+        self.remove(key)?;
+
+        Ok(value)
+    }
+
+    fn compare_and_swap(&mut self, key: String, expected: Option<String>, new: Option<String>) -> Result<bool> {
+        let current = self.get(key.clone())?;
+
+        if current != expected {
+            return Ok(false);
+        }
+
+        match new {
+            Some(value) => self.set(key, value)?,
+            // A key that does not exist is already in the requested state
+            None if current.is_some() => self.remove(key)?,
+            None => {}
+        }
+
+        Ok(true)
+    }
+
+    fn increment(&mut self, key: String, delta: i64) -> Result<i64> {
+        let value = match self.get(key.clone())? {
+            Some(current) => current
+                .parse::<i64>()
+                .map_err(|_| KvsError::NotAnInteger)?
+                .checked_add(delta)
+                .ok_or(KvsError::IntegerOverflow)?,
+            None => delta
+        };
+
+        // The new value keeps the expiry of the current one
+        let expires_at = self.index.get(&key).and_then(|log_pointer| log_pointer.expires_at);
+        let cmd = Command::Set {
+            key: key.clone(),
+            value: value.to_string(),
+            expires_at
+        };
+
+        self.append_set(key, &cmd)?;
+
+        Ok(value)
+    }
+
+    fn append(&mut self, key: String, suffix: String) -> Result<usize> {
+        let mut value = self.get(key.clone())?.unwrap_or_default();
+        value.push_str(&suffix);
+        let len = value.len();
+
+        // The new value keeps the expiry of the current one
+        let expires_at = self.index.get(&key).and_then(|log_pointer| log_pointer.expires_at);
+        let cmd = Command::Set { key: key.clone(), value, expires_at };
+
+        self.append_set(key, &cmd)?;
+
+        Ok(len)
+    }
+
+    fn clear(&mut self) -> Result<()> {
+        self.subscriptions.notify_clear(&self.index);
+        self.index.clear();
+
+        if let Some(cache) = &mut self.cache {
+            cache.clear();
+        }
+
+        if let Some(bloom) = &mut self.bloom {
+            *bloom = BloomFilter::with_keys(self.index.keys());
+        }
+
+        // Nothing is left to copy, and the compaction file is deleted with the other log files
+        self.compaction = None;
+
+        // Writes continue in a new empty log file, so every previous log file can be deleted
+        self.current_log_id = self.current_log_id
+            .checked_add(1)
+            .ok_or(KvsError::LogIdExhausted)?;
+        self.writer = create_new_log_file(
+            self.path.as_deref(),
+            &self.log_file_names,
+            self.current_log_id,
+            self.buf_capacity,
+            &mut self.readers
+        )?;
+        self.remove_log_files_before(self.current_log_id)?;
+
+        // Nothing stale is left to compact
+        self.uncompacted = 0;
+
+        Ok(())
+    }
+
+    fn rename(&mut self, from: String, to: String) -> Result<()> {
+        let value = self.get(from.clone())?.ok_or(KvsError::KeyNotFound)?;
+
+        // The moved value keeps its expiry
+        let expires_at = self.index.get(&from).and_then(|log_pointer| log_pointer.expires_at);
+
+        // Renaming a key to itself leaves the store unchanged
+        if from == to {
+            return Ok(());
+        }
+
+        self.transaction(vec![
+            Command::Set { key: to, value, expires_at },
+            Command::Remove { key: from },
+        ])
+    }
+
+    fn transaction(&mut self, commands: Vec<Command>) -> Result<()> {
+        // Validate every command against the index and the keys touched earlier in the transaction
+        let mut pending: HashMap<&str, bool> = HashMap::new();
+
+        for cmd in &commands {
+            cmd.validate(self.max_value_size)?;
+
+            match cmd {
+                Command::Set { key, .. } => {
+                    pending.insert(key, true);
+                },
+                Command::Remove { key } => {
+                    self.expire(key);
+
+                    let exists = pending
+                        .get(key.as_str())
+                        .copied()
+                        .unwrap_or_else(|| self.index.contains_key(key));
+
+                    if !exists {
+                        return Err(KvsError::KeyNotFound);
+                    }
+
+                    pending.insert(key, false);
+                },
+                _ => return Err(KvsError::UnexpectedCommand)
+            }
+        }
+
+        // Append all commands to the log file and keep track of where each one was written
+        let pos = self.writer.pos;
+        let positions = match self.write_batch(&commands) {
+            Ok(positions) => positions,
+            Err(e) => {
+                // The unfinished batch is dropped, so that it cannot be completed by a later flush
+                self.roll_back_write(pos);
+
+                return Err(e);
+            }
+        };
+
+        // Every command is in the log, so the index can now be updated
+        for (cmd, (range, crc)) in commands.into_iter().zip(positions) {
+            self.subscriptions.notify(&cmd);
+
+            match cmd {
+                Command::Set { key, value, expires_at } => {
+                    let mut log_pointer: LogPointer = (self.current_log_id, range, crc).into();
+                    log_pointer.expires_at = expires_at;
+                    log_pointer.value_len = value.len() as u64;
+                    log_pointer.framing = record_framing(self.log_format);
+
+                    self.add_to_bloom(&key);
+                    self.uncache(&key);
+                    if let Some(old_cmd) = self.index.insert(key, log_pointer) {
+                        self.add_stale(&old_cmd);
+                    }
+                },
+                Command::Remove { key } => {
+                    self.uncache(&key);
+                    if let Some(old_cmd) = self.index.remove(&key) {
+                        self.add_stale(&old_cmd);
+                    }
+
+                    // The "remove" command itself can be deleted in the next compaction
+                    self.uncompacted = self.uncompacted.saturating_add(range.end - range.start + record_framing(self.log_format));
+                },
+                _ => {}
+            }
+        }
+
+        // Start a new log file if the current one grew too big, then compact if needed
+        self.rotate_if_needed()?;
+        self.compact_if_needed()?;
+
+        Ok(())
+    }
+}
+
+/// Current time in milliseconds since the Unix epoch
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| u64::try_from(now.as_millis()).unwrap_or(u64::MAX))
+}
+
+/// Expiry of a Set command, in milliseconds since the Unix epoch
+fn expires_at(cmd: &Command) -> Option<u64> {
+    match cmd {
+        Command::Set { expires_at, .. } => *expires_at,
+        _ => None
+    }
+}
+
+/// Number of bytes of the value set by a Set or SetBytes command
+fn value_len(cmd: &Command) -> u64 {
+    match cmd {
+        Command::Set { value, .. } => value.len() as u64,
+        Command::SetBytes { value, .. } => value.len() as u64,
+        _ => 0
+    }
+}
+
+/// Read the serialized command to which the log pointer refers to
+///
+/// It returns `KvsError::MissingLogFile` if the store has no reader for the log file and
+/// `KvsError::CorruptLog` for the given key if the log file ends before the command does or
+/// the command does not match its checksum, so a bad log pointer only fails the operation
+/// that uses it.
+fn read_payload(readers: &mut HashMap<u64, BufReaderWithPos<LogFile>>, key: &str, cmd: &LogPointer) -> Result<Vec<u8>> {
+    // Retrieve reader for log file to which the log pointer refers to 
+    let reader = readers
+        .get_mut(&cmd.log_file_id)
+        .ok_or(KvsError::MissingLogFile(cmd.log_file_id))?;
+
+    // Set the starting position to start reading the command from the log file,
+    // reusing the bytes of the reader's buffer if it holds it
+    reader.seek_to(cmd.start_position)?;
+
+    // Read only the bytes of the command
+    let mut payload = Vec::with_capacity(cmd.len as usize);
+    reader.take(cmd.len).read_to_end(&mut payload)?;
+
+    // The log file may have been modified since the command was indexed
+    if (payload.len() as u64) < cmd.len || crc32fast::hash(&payload) != cmd.crc {
+        return Err(KvsError::CorruptLog { position: cmd.start_position, key: Some(key.to_owned()) });
+    }
+
+    Ok(payload)
+}
+
+/// Read the command to which the log pointer refers to
+fn read_command(readers: &mut HashMap<u64, BufReaderWithPos<LogFile>>, key: &str, cmd: &LogPointer) -> Result<Command> {
+    Codec::decode(&read_payload(readers, key, cmd)?)
+}
+
+/// Read the value of the Set command to which the log pointer refers to
+///
+/// It returns `KvsError::UnexpectedCommand` if the command is not a Set command
+/// and `KvsError::Utf8Error` if a value set as bytes is not valid UTF-8.
+fn read_value(readers: &mut HashMap<u64, BufReaderWithPos<LogFile>>, key: &str, cmd: &LogPointer) -> Result<String> {
+    // If retrieved command is a Set command, return the value associated with it
+    match read_command(readers, key, cmd)? {
+        Command::Set { value, .. } => Ok(value),
+        Command::SetBytes { value, .. } => Ok(String::from_utf8(value)?),
+        _ => Err(KvsError::UnexpectedCommand)
+    }
+}
+
+/// Name of the lock file held in the directory of an open store
+const LOCK_FILE: &str = "kvs.lock";
+
+/// Runs compaction steps on the store until no compaction is needed, releasing its lock
+/// between steps so that writes are not blocked for the whole compaction.
+///
+/// The thread stops once every clone of the store is dropped, and on errors, which the next
+/// write that needs compaction retries.
+fn compact_in_background(this: Weak<Mutex<KvStoreInner>>) {
+    while let Some(store) = this.upgrade() {
+        let mut inner = store.lock().expect("KvStore lock poisoned");
+
+        // Writes made during the compaction may have crossed the threshold again
+        let needed = inner.compaction.is_some()
+            || (inner.compaction_mode == CompactionMode::Background && inner.needs_compaction());
+
+        if !needed {
+            inner.background_compaction = false;
+            return;
+        }
+
+        match inner.compact_step(BACKGROUND_COMPACTION_STEP) {
+            Ok(_) => {},
+            // Not enough free space yet, like for automatic compaction
+            Err(KvsError::InsufficientSpace { .. }) => {
+                inner.background_compaction = false;
+                return;
+            },
+            Err(e) => {
+                inner.background_compaction = false;
+
+                #[cfg(feature = "tracing")]
+                tracing::error!(error = %e, "background compaction failed");
+                #[cfg(not(feature = "tracing"))]
+                eprintln!("Background compaction failed: {}", e);
+
+                return;
+            }
+        }
+
+        // Let waiting writes take the lock before the next step
+        drop(inner);
+        drop(store);
+        thread::yield_now();
+    }
+}
+
+/// Runs `KvStore::sweep_expired` on the store every `interval`.
+///
+/// The store is only held during a sweep, and the thread stops as soon as the store drops
+/// the sender of the given channel, once every clone of the store is dropped. A sweep running
+/// at that time finishes first, so the directory can stay locked for a moment after the drop.
+/// Errors are reported and the next sweep tries again.
+fn sweep_expired_in_background(this: Weak<Mutex<KvStoreInner>>, stop: Receiver<()>, interval: Duration) {
+    loop {
+        match stop.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => {},
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => return
+        }
+
+        let store = match this.upgrade() {
+            Some(inner) => KvStore { inner },
+            None => return
+        };
+
+        if let Err(e) = store.sweep_expired() {
+            #[cfg(feature = "tracing")]
+            tracing::error!(error = %e, "expiry sweep failed");
+            #[cfg(not(feature = "tracing"))]
+            eprintln!("Expiry sweep failed: {}", e);
+        }
+    }
+}
+
+/// Take the lock of the store in the given directory, which is released once the returned
+/// file is closed
+///
+/// It returns `KvsError::AlreadyLocked` if another store holds it, whether it was opened
+/// by this process or another one.
+fn lock_dir(path: &Path) -> Result<File> {
+    let lock_file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path.join(LOCK_FILE))?;
+
+    match lock_file.try_lock_exclusive() {
+        Ok(()) => Ok(lock_file),
+        Err(e) if e.raw_os_error() == fs2::lock_contended_error().raw_os_error() => {
+            Err(KvsError::AlreadyLocked(path.to_owned()))
+        },
+        Err(e) => Err(e.into())
+    }
+}
+
+/// Get sorted vector of the ids of the log files with the given names inside the given directory
+fn sort_log_files(path: &Path, names: &LogFileNames) -> Result<Vec<u64>> {
+    let mut file_ids: Vec<u64> = read_dir(path)?
+        .flat_map(|entry| -> Result<_> { Ok(entry?.path()) }) // Get path for each entry in the directory, ignoring errors by using flat_map
+        .filter(|path| path.is_file()) // Filter entries which are files
+        .flat_map(|file| { // flat_map ignores None values, keeping only Some(value)
+            file.file_name()
+            .and_then(OsStr::to_str)
+            .and_then(|s| names.parse_id(s)) // Strip the prefix and extension and keep only log file id number
+        })
+        .collect();
+
+    // sort_unstable is faster than stable sort in some cases
+    file_ids.sort_unstable();
+
+    Ok(file_ids)
+}
+
+/// Load log file and collect the latest command of each key found in it
+///
+/// Returns a map with a log pointer for keys whose latest command is a Set command
+/// and `None` for keys whose latest command is a Remove command or an expired Set command,
+/// together with the number of bytes in the file that can be saved in compaction regardless
+/// of other log files
+///
+/// A truncated or corrupt record ends the log file, so only the commands before it are loaded.
+fn load_log_file(
+    id: u64,
+    reader: &mut BufReaderWithPos<LogFile>
+) -> Result<(HashMap<String, Option<LogPointer>>, u64)> {
+    let mut entries: HashMap<String, Option<LogPointer>> = HashMap::new();
+    let mut uncompacted: u64 = 0;
+    let now = now_millis();
+
+    // Commands of a batch are only applied once its commit marker is read
+    let mut batch: Option<Vec<(Command, CommandPos)>> = None;
+
+    let result = for_each_command(reader, |cmd, pos| {
+        match cmd {
+            Command::BatchBegin { .. } => {
+                // A batch without commit marker is followed by the end of its log file,
+                // so any commands still buffered here are discarded
+                discard_batch(batch.replace(Vec::new()), &mut uncompacted);
+                uncompacted = uncompacted.saturating_add(pos.record_len());
+            },
+            Command::BatchCommit { count } => {
+                match batch.take() {
+                    Some(commands) if commands.len() as u64 == count => {
+                        for (cmd, pos) in commands {
+                            load_command(&mut entries, &mut uncompacted, id, now, cmd, pos);
+                        }
+                    },
+                    commands => discard_batch(commands, &mut uncompacted)
+                }
+
+                uncompacted = uncompacted.saturating_add(pos.record_len());
+            },
+            cmd => match batch.as_mut() {
+                Some(commands) => commands.push((cmd, pos)),
+                None => load_command(&mut entries, &mut uncompacted, id, now, cmd, pos)
+            }
+        }
+    });
+
+    // A batch cut short by a crash or a corrupt record is never applied
+    discard_batch(batch, &mut uncompacted);
+
+    match result {
+        Ok(()) => {},
+        // Records after a corrupt one cannot be located, so loading stops there
+        Err(KvsError::CorruptLog { position, .. }) => {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(log_file_id = id, position, "corrupt log record, ignoring the rest of the log file");
+            #[cfg(not(feature = "tracing"))]
+            eprintln!("Corrupt log record at position {} of log file {}, ignoring the rest of the log file", position, id);
+        },
+        Err(e) => return Err(e)
+    }
+
+    Ok((entries, uncompacted))
+}
+
+/// Apply a command loaded from log file `id` to the entries found in it so far
+fn load_command(
+    entries: &mut HashMap<String, Option<LogPointer>>,
+    uncompacted: &mut u64,
+    id: u64,
+    now: u64,
+    cmd: Command,
+    pos: CommandPos
+) {
+    let record_len = pos.record_len();
+    let mut log_pointer: LogPointer = (id, pos.range, pos.crc).into();
+    log_pointer.framing = pos.framing;
+
+    match cmd {
+        Command::Set { key, value, expires_at } => {
+            log_pointer.expires_at = expires_at;
+            log_pointer.value_len = value.len() as u64;
+
+            // An expired Set command removes the key and can itself be deleted in the next compaction
+            let entry = if log_pointer.is_expired(now) {
+                *uncompacted = uncompacted.saturating_add(record_len);
+                None
+            } else {
+                Some(log_pointer)
+            };
+
+            // Insert returns the previous entry if the key was already found in this file
+            if let Some(Some(old_cmd)) = entries.insert(key, entry) {
+                // Add old command's bytes to uncompacted counter
+                *uncompacted = uncompacted.saturating_add(old_cmd.record_len());
+            }
+        },
+        Command::SetBytes { key, value } => {
+            log_pointer.value_len = value.len() as u64;
+
+            // Insert returns the previous entry if the key was already found in this file
+            if let Some(Some(old_cmd)) = entries.insert(key, Some(log_pointer)) {
+                // Add old command's bytes to uncompacted counter
+                *uncompacted = uncompacted.saturating_add(old_cmd.record_len());
+            }
+        },
+        Command::Remove { key } => {
+            if let Some(Some(old_cmd)) = entries.insert(key, None) {
+                // Add old command's bytes to uncompacted counter
+                *uncompacted = uncompacted.saturating_add(old_cmd.record_len());
+            };
+
+            // The "remove" command itself can be deleted in the next compaction
+            // so we add its length to the uncompacted counter
+            *uncompacted = uncompacted.saturating_add(record_len);
+        },
+        _ => {}
+    }
+}
+
+/// Count the bytes of the commands of an uncommitted batch as uncompacted
+fn discard_batch(batch: Option<Vec<(Command, CommandPos)>>, uncompacted: &mut u64) {
+    for (_, pos) in batch.unwrap_or_default() {
+        *uncompacted = uncompacted.saturating_add(pos.record_len());
+    }
+}
+
+/// Location of a command read from a log file
+struct CommandPos {
+    /// Range of the serialized command in the log file
+    range: Range<u64>,
+    /// CRC32 of the serialized command
+    crc: u32,
+    /// Bytes of the record around the serialized command, such as its header or newline
+    framing: u64,
+}
+
+impl CommandPos {
+    /// Number of bytes of the whole record holding the command
+    fn record_len(&self) -> u64 {
+        self.range.end - self.range.start + self.framing
+    }
+}
+
+/// Call `f` with every command in the log file and its location
+///
+/// Log files in the `LogFormat::JsonLines` format, and the ones written before records were
+/// framed, hold plain JSON commands. They are told apart from framed log files by their first
+/// byte, since no record length starts with `{`.
+fn for_each_command<F>(reader: &mut BufReaderWithPos<LogFile>, mut f: F) -> Result<()>
+where
+    F: FnMut(Command, CommandPos)
+{
+    // Make sure file starts being read from first byte
+    let mut pos: u64 = reader.seek(SeekFrom::Start(0))?;
+
+    if reader.fill_buf()?.first() == Some(&b'{') {
+        let mut line = Vec::new();
+
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+
+            if read == 0 {
+                return Ok(());
+            }
+
+            // The newline is not part of the command's range
+            let complete = line.last() == Some(&b'\n');
+            if complete {
+                line.pop();
+            }
+
+            // Log files written before records were framed hold all their commands back to back
+            // on a single line
+            let mut stream = Deserializer::from_slice(&line).into_iter::<Command>();
+            let mut start = 0;
+
+            while let Some(cmd) = stream.next() {
+                let end = stream.byte_offset();
+
+                // Only the last command of a line is followed by the newline
+                let framing = u64::from(complete && end == line.len());
+
+                match cmd {
+                    Ok(cmd) => f(cmd, CommandPos {
+                        range: pos + start as u64..pos + end as u64,
+                        crc: crc32fast::hash(&line[start..end]),
+                        framing
+                    }),
+                    // The last command can be cut short by a crash
+                    Err(e) if e.is_eof() && !complete => {
+                        return Err(KvsError::CorruptLog { position: pos + start as u64, key: None });
+                    },
+                    Err(e) => return Err(e.into())
+                }
+
+                start = end;
+            }
+
+            pos += read as u64;
+        }
+    }
+
+    while let Some(Record { range, payload, crc }) = read_record(reader, pos)? {
+        // The reader is now positioned at the start of the next record
+        pos = range.end;
+        f(Codec::decode(&payload)?, CommandPos { range, crc, framing: RECORD_HEADER_SIZE });
+    }
+
+    Ok(())
+}
+
+/// Size of the header written before every command: its length and CRC32 as big-endian `u32`s
+const RECORD_HEADER_SIZE: u64 = 8;
+
+/// Bytes written around every command in the given format, which are part of its record
+fn record_framing(format: LogFormat) -> u64 {
+    match format {
+        LogFormat::Framed => RECORD_HEADER_SIZE,
+        LogFormat::JsonLines => 1
+    }
+}
+
+/// Largest serialized command that fits in a record
+///
+/// Keeping lengths below `0x7B000000` means no record starts with `{`,
+/// the first byte of every command in logs written before records were framed.
+const MAX_RECORD_SIZE: u64 = 0x7AFF_FFFF;
+
+/// Codec used to write commands in the given format
+fn write_codec(format: LogFormat, codec: Codec) -> Codec {
+    match format {
+        LogFormat::Framed => codec,
+        LogFormat::JsonLines => Codec::Json
+    }
+}
+
+/// Serialize the command and append it to the log in the given format
+///
+/// Returns the range of the serialized command in the log file, without the record header
+/// or the newline, and the CRC32 of the serialized command.
+fn write_record(
+    writer: &mut BufWriterWithPos<LogFile>,
+    format: LogFormat,
+    codec: Codec,
+    cmd: &Command
+) -> Result<(Range<u64>, u32)> {
+    let payload = write_codec(format, codec).encode(cmd)?;
+
+    write_record_payload(writer, format, &payload)
+}
+
+/// Append a serialized command to the log in the given format
+fn write_record_payload(
+    writer: &mut BufWriterWithPos<LogFile>,
+    format: LogFormat,
+    payload: &[u8]
+) -> Result<(Range<u64>, u32)> {
+    let crc = crc32fast::hash(payload);
+
+    // Compact JSON escapes newlines inside strings, so a command always fits on one line
+    if format == LogFormat::JsonLines {
+        let start = writer.pos;
+        writer.write_all(payload)?;
+        let end = writer.pos;
+        writer.write_all(b"\n")?;
+
+        return Ok((start..end, crc));
+    }
+
+    let len = payload.len() as u64;
+
+    if len > MAX_RECORD_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Command is too large for a log record").into());
+    }
+
+    writer.write_all(&(len as u32).to_be_bytes())?;
+    writer.write_all(&crc.to_be_bytes())?;
+
+    let start = writer.pos;
+    writer.write_all(payload)?;
+
+    Ok((start..writer.pos, crc))
+}
+
+/// Serialized command read from a framed log file
+struct Record {
+    /// Range of the serialized command in the log file, without the record header
+    range: Range<u64>,
+    payload: Vec<u8>,
+    /// CRC32 of the serialized command, as verified against the record header
+    crc: u32,
+}
+
+/// Read the record starting at `pos` and verify its checksum
+///
+/// Returns `None` at the end of the file and `KvsError::CorruptLog` if the record is truncated
+/// or does not match its checksum.
+fn read_record(reader: &mut BufReaderWithPos<LogFile>, pos: u64) -> Result<Option<Record>> {
+    let corrupt = KvsError::CorruptLog { position: pos, key: None };
+
+    // Read the header, telling the end of the file apart from a truncated header
+    let mut header = [0; RECORD_HEADER_SIZE as usize];
+    let mut read = 0;
+
+    while read < header.len() {
+        match reader.read(&mut header[read..])? {
+            0 if read == 0 => return Ok(None),
+            0 => return Err(corrupt),
+            n => read += n
+        }
+    }
+
+    let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+    let crc = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+
+    if len > MAX_RECORD_SIZE {
+        return Err(corrupt);
+    }
+
+    let mut payload = Vec::with_capacity(len as usize);
+    reader.take(len).read_to_end(&mut payload)?;
+
+    if payload.len() as u64 != len || crc32fast::hash(&payload) != crc {
+        return Err(corrupt);
+    }
+
+    let start = pos + RECORD_HEADER_SIZE;
+
+    Ok(Some(Record { range: start..start + len, payload, crc }))
+}
+
+/// Create a new log file with given log file id and add the reader to the readers map.
+/// The log file is kept in memory if there is no directory.
+///
+/// Returns the writer to the log.
+fn create_new_log_file(
+    path: Option<&Path>,
+    names: &LogFileNames,
+    log_file_id: u64,
+    buf_capacity: usize,
+    readers: &mut HashMap<u64, BufReaderWithPos<LogFile>>
+) -> Result<BufWriterWithPos<LogFile>> {
+    let (writer_file, reader_file) = match path {
+        Some(path) => {
+            // Filepath for new log file
+            let filepath = path.join(names.file_name(log_file_id));
+
+            // Create writer's file for new log file
+            let writer_file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&filepath)?;
+
+            // Reader is created after the writer because the writer creates the file at the given path
+            // if it does not exist
+            (LogFile::Disk(writer_file), LogFile::Disk(File::open(&filepath)?))
+        },
+        None => {
+            // Both handles share the same bytes
+            let log = MemoryLog::default();
+            (LogFile::Memory(log.clone()), LogFile::Memory(log))
+        }
+    };
+
+    // Create writer for new log file
+    let writer = BufWriterWithPos::with_capacity(buf_capacity, writer_file)?;
+
+    // Create reader for new log file and add it to readers hash map
+    readers.insert(log_file_id, BufReaderWithPos::with_capacity(buf_capacity, reader_file));
+
+    Ok(writer)
+}
@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, RwLock};
+
+/// Storage behind a log file, either a file on disk or a buffer in memory
+#[derive(Debug)]
+pub enum LogFile {
+    Disk(File),
+    Memory(MemoryLog),
+}
+
+impl LogFile {
+    /// Makes sure all written bytes are durable, which only applies to files on disk
+    pub fn sync_all(&self) -> io::Result<()> {
+        match self {
+            LogFile::Disk(file) => file.sync_all(),
+            LogFile::Memory(_) => Ok(()),
+        }
+    }
+
+    /// Truncates the log file to the given number of bytes
+    pub fn set_len(&self, len: u64) -> io::Result<()> {
+        match self {
+            LogFile::Disk(file) => file.set_len(len),
+            LogFile::Memory(log) => {
+                log.bytes.write().expect("MemoryLog lock poisoned").truncate(len as usize);
+                Ok(())
+            },
+        }
+    }
+
+    /// Number of bytes in the log file, without the writes still buffered by its writer
+    pub fn len(&self) -> io::Result<u64> {
+        match self {
+            LogFile::Disk(file) => Ok(file.metadata()?.len()),
+            LogFile::Memory(log) => Ok(log.bytes.read().expect("MemoryLog lock poisoned").len() as u64),
+        }
+    }
+}
+
+impl Read for LogFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            LogFile::Disk(file) => file.read(buf),
+            LogFile::Memory(log) => log.read(buf),
+        }
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            LogFile::Disk(file) => file.write(buf),
+            LogFile::Memory(log) => log.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            LogFile::Disk(file) => file.flush(),
+            LogFile::Memory(log) => log.flush(),
+        }
+    }
+}
+
+impl Seek for LogFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            LogFile::Disk(file) => file.seek(pos),
+            LogFile::Memory(log) => log.seek(pos),
+        }
+    }
+}
+
+/// Log file kept in memory
+///
+/// Clones share the same bytes but keep their own position, like several handles
+/// to the same file, so the writer and the reader of a log file can both hold one.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryLog {
+    bytes: Arc<RwLock<Vec<u8>>>,
+    pos: u64,
+}
+
+impl MemoryLog {
+    /// Runs `f` on a cursor over the shared bytes, starting at this handle's position
+    fn with_cursor<T>(&mut self, f: impl FnOnce(&mut Cursor<&mut Vec<u8>>) -> io::Result<T>) -> io::Result<T> {
+        let mut bytes = self.bytes.write().expect("MemoryLog lock poisoned");
+        let mut cursor = Cursor::new(&mut *bytes);
+        cursor.set_position(self.pos);
+
+        let result = f(&mut cursor);
+        self.pos = cursor.position();
+
+        result
+    }
+}
+
+impl Read for MemoryLog {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.with_cursor(|cursor| cursor.read(buf))
+    }
+}
+
+impl Write for MemoryLog {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.with_cursor(|cursor| cursor.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryLog {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.with_cursor(|cursor| cursor.seek(pos))
+    }
+}
@@ -1,20 +1,45 @@
 use std::convert::From;
 use std::ops::Range;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// Pointer to a command's location in a log file
 pub struct LogPointer {
     pub log_file_id: u64,
     pub start_position: u64,
-    pub len: u64
+    pub len: u64,
+    /// CRC32 of the pointed command, verified every time it is read
+    pub crc: u32,
+    /// Expiry of the pointed Set command, in milliseconds since the Unix epoch
+    pub expires_at: Option<u64>,
+    /// Bytes of the record around the pointed command, such as its header,
+    /// which are deleted together with it
+    pub framing: u64,
+    /// Number of bytes of the value set by the pointed command, without the record around it
+    pub value_len: u64
 }
 
-impl From<(u64, Range<u64>)> for LogPointer {
-    fn from((id, range): (u64, Range<u64>)) -> Self {
+impl LogPointer {
+    /// Whether the pointed command expired at the given time, in milliseconds since the Unix epoch
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Number of bytes of the whole record holding the pointed command
+    pub fn record_len(&self) -> u64 {
+        self.len + self.framing
+    }
+}
+
+impl From<(u64, Range<u64>, u32)> for LogPointer {
+    fn from((id, range, crc): (u64, Range<u64>, u32)) -> Self {
         Self {
             log_file_id: id,
             start_position: range.start,
-            len: range.end - range.start
+            len: range.end - range.start,
+            crc,
+            expires_at: None,
+            framing: 0,
+            value_len: 0
         }
     }
 }
\ No newline at end of file
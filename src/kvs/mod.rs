@@ -1,9 +1,25 @@
-pub use kvs_engine::KvStore;
+pub use batch::WriteBatch;
+pub use cache::{CacheSize, CacheStats};
+pub use codec::Codec;
+pub use index::IndexKind;
+pub use kvs_engine::{CompactionMode, CompactionOrder, CompactionPreview, CompactionStats, KvStore, KvStoreConfig, LogFileNames, LogFormat};
 pub use reader::BufReaderWithPos;
 pub use writer::BufWriterWithPos;
 pub use log_pointer::LogPointer;
+pub use watch::ChangeEvent;
 
+mod backup;
+mod batch;
+mod bloom;
+mod cache;
+pub mod codec;
+mod index;
 pub mod kvs_engine;
 pub mod reader;
 pub mod writer;
-pub mod log_pointer;
\ No newline at end of file
+pub mod log_pointer;
+mod log_file;
+mod watch;
+
+/// Capacity in bytes of the buffers of log file readers and writers unless configured otherwise
+pub const DEFAULT_BUF_CAPACITY: usize = 8 * 1024;
\ No newline at end of file
@@ -1,4 +1,6 @@
-use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+
+use super::DEFAULT_BUF_CAPACITY;
 
 /// BufReader from std::io with byte's position tracking
 #[derive(Debug)]
@@ -8,12 +10,29 @@ pub struct BufReaderWithPos<R: Read + Seek> {
 }
 
 impl<R: Read + Seek> BufReaderWithPos<R> {
-  pub fn new(file: R) -> Self {    
+  pub fn new(file: R) -> Self {
+    Self::with_capacity(DEFAULT_BUF_CAPACITY, file)
+  }
+
+  // Buffer up to the given number of bytes read from the file
+  pub fn with_capacity(capacity: usize, file: R) -> Self {
     Self {
-      reader: BufReader::new(file),
+      reader: BufReader::with_capacity(capacity, file),
       pos: 0
     }
   }
+
+  // Move to the given position without seeking the file when it is already buffered,
+  // as when commands are read one after the other, which keeps the buffered bytes
+  pub fn seek_to(&mut self, pos: u64) -> io::Result<()> {
+    if pos != self.pos {
+      // Offsets wrap around like two's complement, so a position behind gives a negative offset
+      self.reader.seek_relative(pos.wrapping_sub(self.pos) as i64)?;
+      self.pos = pos;
+    }
+
+    Ok(())
+  }
 }
 
 impl<R: Read + Seek> Read for BufReaderWithPos<R> {
@@ -28,6 +47,19 @@ impl<R: Read + Seek> Read for BufReaderWithPos<R> {
   }
 }
 
+impl<R: Read + Seek> BufRead for BufReaderWithPos<R> {
+  // Return the buffered bytes, reading more from the file if the buffer is empty
+  fn fill_buf(&mut self) -> io::Result<&[u8]> {
+    self.reader.fill_buf()
+  }
+
+  // Mark the given number of buffered bytes as read and advance the current position
+  fn consume(&mut self, amt: usize) {
+    self.reader.consume(amt);
+    self.pos += amt as u64;
+  }
+}
+
 impl<R: Read + Seek> Seek for BufReaderWithPos<R> {
   // Find the given position (file offset) in the file and
   // set it as current position
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::Command;
+use super::index::Index;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Change to a key, delivered to the receivers returned by `KvStore::subscribe`
+pub enum ChangeEvent {
+    /// The key was set to a string
+    Set { key: String, value: String },
+    /// The key was set to arbitrary bytes
+    SetBytes { key: String, value: Vec<u8> },
+    /// The key was removed
+    Remove { key: String }
+}
+
+/// Senders of the change events of every subscribed key
+#[derive(Debug, Default)]
+pub(crate) struct Subscriptions {
+    senders: HashMap<String, Vec<Sender<ChangeEvent>>>
+}
+
+impl Subscriptions {
+    /// Returns a receiver of the change events of the given key
+    pub(crate) fn subscribe(&mut self, key: String) -> Receiver<ChangeEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.senders.entry(key).or_default().push(sender);
+
+        receiver
+    }
+
+    /// Sends the change made by a command written to the log to the subscribers of its key
+    pub(crate) fn notify(&mut self, cmd: &Command) {
+        // Nothing needs to be cloned for the keys nobody subscribed to
+        if self.senders.is_empty() {
+            return;
+        }
+
+        let event = match cmd {
+            Command::Set { key, value, .. } => ChangeEvent::Set { key: key.clone(), value: value.clone() },
+            Command::SetBytes { key, value } => ChangeEvent::SetBytes { key: key.clone(), value: value.clone() },
+            Command::Remove { key } => ChangeEvent::Remove { key: key.clone() },
+            _ => return
+        };
+
+        if let Some(key) = cmd.key() {
+            self.send(key, event);
+        }
+    }
+
+    /// Sends a Remove event to the subscribers of every key of the index, which is being cleared
+    pub(crate) fn notify_clear(&mut self, index: &Index) {
+        let keys: Vec<String> = self.senders
+            .keys()
+            .filter(|key| index.contains_key(key))
+            .cloned()
+            .collect();
+
+        for key in keys {
+            let event = ChangeEvent::Remove { key: key.clone() };
+            self.send(&key, event);
+        }
+    }
+
+    fn send(&mut self, key: &str, event: ChangeEvent) {
+        if let Some(senders) = self.senders.get_mut(key) {
+            // Sending only fails once the receiver was dropped, so its sender is pruned
+            senders.retain(|sender| sender.send(event.clone()).is_ok());
+
+            if senders.is_empty() {
+                self.senders.remove(key);
+            }
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use std::io::{self, BufWriter, Write, Seek, SeekFrom};
+use std::mem;
 
 use crate::Result;
+use super::DEFAULT_BUF_CAPACITY;
+use super::log_file::{LogFile, MemoryLog};
 
 /// BufWriter from std::io with byte's position tracking
 #[derive(Debug)]
@@ -11,16 +14,49 @@ pub struct BufWriterWithPos<W: Write + Seek> {
 
 impl<W: Write + Seek> BufWriterWithPos<W> {
   // Set the position (file offset) to the size of the file (last byte's position)
-  pub fn new(mut file: W) -> Result<Self> {  
+  pub fn new(file: W) -> Result<Self> {
+    Self::with_capacity(DEFAULT_BUF_CAPACITY, file)
+  }
+
+  // Buffer up to the given number of bytes before writing them to the file
+  pub fn with_capacity(capacity: usize, mut file: W) -> Result<Self> {
     let pos =  file.seek(SeekFrom::End(0))?;
 
     Ok(Self {
-      writer: BufWriter::new(file),
+      writer: BufWriter::with_capacity(capacity, file),
       pos
     })
   }
 }
 
+impl BufWriterWithPos<LogFile> {
+  // Drop the bytes written from the given position on, whether they are still buffered
+  // or already reached the file, so that the next write starts there.
+  // Buffered bytes before the position are kept and written later.
+  pub fn truncate(&mut self, pos: u64) -> io::Result<()> {
+    let capacity = self.writer.capacity();
+    let placeholder = BufWriter::with_capacity(0, LogFile::Memory(MemoryLog::default()));
+
+    // Taking the file out of the BufWriter drops its buffer instead of flushing it
+    let (mut file, buffered) = mem::replace(&mut self.writer, placeholder).into_parts();
+    let buffered = buffered.unwrap_or_else(|panicked| panicked.into_inner());
+
+    // The BufWriter only drains the bytes that reached the file, so the rest are buffered
+    let flushed = self.pos - buffered.len() as u64;
+
+    let result = if pos < flushed {
+      file.set_len(pos).and_then(|()| file.seek(SeekFrom::Start(pos))).map(|_| Vec::new())
+    } else {
+      Ok(buffered[..(pos - flushed) as usize].to_vec())
+    };
+
+    self.writer = BufWriter::with_capacity(capacity, file);
+    self.pos = flushed.min(pos);
+
+    self.write_all(&result?)
+  }
+}
+
 impl<W: Write + Seek> Write for BufWriterWithPos<W> {
   // Write the given buffer into the file, returning how many bytes were written
   fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
@@ -1,15 +1,20 @@
 //! A simple key/value store.
+#![allow(clippy::module_inception)]
 
 pub use errors::{KvsError, Result};
-pub use crate::kvs::{BufReaderWithPos, BufWriterWithPos, LogPointer, KvStore};
-pub use client::{ClientOpt, Command, KvsClient};
-pub use server::{CommandResponse, Engine, KvsServer, ServerOpt};
-pub use engine::KvsEngine;
-pub use crate::sled::SledKvsEngine;
+pub use crate::kvs::{BufReaderWithPos, BufWriterWithPos, CacheSize, CacheStats, ChangeEvent, Codec, CompactionMode, CompactionOrder, CompactionPreview, CompactionStats, IndexKind, LogFileNames, LogFormat, LogPointer, KvStore, KvStoreConfig, WriteBatch};
+pub use client::{ClientOpt, Command, ExitStatus, KvsClient, OutputFormat};
+pub use server::{CommandResponse, Engine, ErrorKind, KvsServer, MetricsSnapshot, MAX_CHUNK_SIZE, MAX_KEYS_PER_RESPONSE, Protocol, RespRequest, RespValue, ServerCommand, ServerInfo, ServerMetrics, ServerOpt};
+pub use engine::{export, import, KeyStat, KvsEngine, SizeStats};
+pub use crate::sled::{SledConfig, SledKvsEngine};
+pub use util::{read_frame, write_frame, Backoff, Listener, LoggerFormat, LOG_LEVELS, ServerAddr, Stream, MAX_FRAME_SIZE};
+pub use thread_pool::{SharedQueueThreadPool, ThreadPool};
 
 pub mod server;
 pub mod errors;
 pub mod kvs;
 pub mod client;
 pub mod engine;
-pub mod sled;
\ No newline at end of file
+pub mod sled;
+pub mod util;
+pub mod thread_pool;
\ No newline at end of file
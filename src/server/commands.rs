@@ -1,9 +1,10 @@
-use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::fmt::{self, Display};
 use structopt::StructOpt;
 
-use crate::KvsError;
+use crate::{Command, KvsError, LoggerFormat, ServerAddr, LOG_LEVELS};
+use crate::util::logging::parse_log_level;
 
 #[derive(StructOpt)]
 /// Struct which represents the server's parsed command line arguments
@@ -11,11 +12,12 @@ pub struct ServerOpt {
     #[structopt(
         default_value = "127.0.0.1:4000", 
         long, 
-        value_name = "IP:PORT",
+        value_name = "IP:PORT|unix:PATH",
+        number_of_values = 1,
         parse(try_from_str)
     )]
-    /// Listening IP address
-    pub addr: SocketAddr,
+    /// Listening IP address, or path of a Unix domain socket after `unix:`, repeat to listen on several
+    pub addr: Vec<ServerAddr>,
     
     #[structopt(
         default_value = "kvs",
@@ -24,7 +26,111 @@ pub struct ServerOpt {
         possible_values = &Engine::variants()
     )]
     /// Storage Engine
-    pub engine: Engine
+    pub engine: Engine,
+
+    #[structopt(
+        default_value = "json",
+        long,
+        value_name = "PROTOCOL",
+        possible_values = &Protocol::variants()
+    )]
+    /// Protocol spoken with clients, resp lets Redis clients send GET, SET, DEL and PING
+    pub protocol: Protocol,
+
+    #[structopt(
+        default_value = "text",
+        long,
+        value_name = "FORMAT",
+        possible_values = &LoggerFormat::variants()
+    )]
+    /// Format of the logs written to stderr
+    pub log_format: LoggerFormat,
+
+    #[structopt(
+        default_value = "info",
+        short = "v",
+        long,
+        value_name = "LEVEL",
+        possible_values = &LOG_LEVELS,
+        parse(try_from_str = parse_log_level)
+    )]
+    /// Least severe level of the logs written to stderr
+    pub log_level: slog::Level,
+
+    #[structopt(default_value = ".", long, value_name = "PATH", parse(from_os_str))]
+    /// Directory holding the engine config file and the engine's data in a logs subdirectory
+    pub data_dir: PathBuf,
+
+    #[structopt(long)]
+    /// Start even if the engine config file cannot be read, which overwrites it
+    pub force_engine: bool,
+
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    /// File with one key per line to read before accepting connections
+    pub warmup_file: Option<PathBuf>,
+
+    #[structopt(default_value = "3", long, value_name = "N")]
+    /// Number of times a failed sled flush is retried
+    pub flush_retries: u32,
+
+    #[structopt(default_value = "10", long, value_name = "MILLISECONDS")]
+    /// Delay before the first sled flush retry, doubled after every attempt
+    pub flush_retry_delay_ms: u64,
+
+    #[structopt(long)]
+    /// Randomize the delays between sled flush retries
+    pub flush_retry_jitter: bool,
+
+    #[structopt(long, value_name = "N")]
+    /// Number of threads serving connections, defaults to the number of CPUs
+    pub threads: Option<u32>,
+
+    #[structopt(long, value_name = "BYTES")]
+    /// Reject values bigger than this size, defaults to no limit
+    pub max_value_size: Option<u64>,
+
+    #[structopt(long)]
+    /// Reject every command that can change the store, such as set and rm
+    pub read_only: bool,
+
+    #[structopt(long)]
+    /// Log one line per served command with its name, key, status and latency
+    pub access_log: bool,
+
+    #[structopt(long)]
+    /// Let Nagle's algorithm delay small responses on TCP connections instead of sending them right away
+    pub nagle: bool,
+
+    #[structopt(long, value_name = "N")]
+    /// Maximum number of connections waiting to be accepted, defaults to 128. Only supported on Linux
+    pub backlog: Option<u32>,
+
+    #[structopt(long, value_name = "MS")]
+    /// Close connections on which the client sends nothing for this many milliseconds,
+    /// defaults to keeping them open
+    pub idle_timeout_ms: Option<u64>,
+
+    #[structopt(long, value_name = "COMMAND", number_of_values = 1, possible_values = Command::names())]
+    /// Only run the given command, repeat to allow several. All commands are allowed by default
+    pub allow_command: Vec<String>,
+
+    #[structopt(subcommand)]
+    /// Command to run instead of starting the server
+    pub command: Option<ServerCommand>
+}
+
+#[derive(Debug, StructOpt, PartialEq, Eq)]
+/// Commands that run instead of starting the server
+pub enum ServerCommand {
+    /// Run a set/get/remove/compact cycle on a reserved key and exit without listening
+    SelfTest {
+        #[structopt(long, value_name = "PATH", parse(from_os_str))]
+        /// Directory with the engine's data
+        data_dir: PathBuf
+    },
+    /// Print how many bytes a compaction of the kvs engine's log files would free and exit
+    /// without compacting or listening. The server must not be running on the data directory
+    CompactionPreview
 }
 
 #[derive(Debug, StructOpt, PartialEq, Eq)]
@@ -60,4 +166,42 @@ impl Display for Engine {
         };
         write!(f, "{}", printable)
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// Protocol spoken by the server with its clients
+pub enum Protocol {
+    /// JSON commands in length-prefixed frames, as sent by `KvsClient`
+    Json,
+    /// RESP2, the protocol of Redis
+    Resp
+}
+
+impl Protocol {
+    /// Possible values of this enum
+    fn variants() -> [&'static str; 2] {
+        ["json", "resp"]
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = KvsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Protocol::Json),
+            "resp" => Ok(Protocol::Resp),
+            _ => Err(KvsError::UnknownProtocol)
+        }
+    }
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = match *self {
+            Protocol::Json => "json",
+            Protocol::Resp => "resp",
+        };
+        write!(f, "{}", printable)
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::{Deserialize, Serialize};
+
+use crate::{Command, CommandResponse};
+
+/// Counters of the commands served by a `KvsServer`
+///
+/// Counters are atomics updated without locking, so connections served on different
+/// threads never wait on each other to record their commands.
+#[derive(Debug)]
+pub struct ServerMetrics {
+    /// Number of served commands by command name, with a counter for each of `Command::names`.
+    /// The map is never modified after its creation, only the counters inside it.
+    commands: HashMap<&'static str, AtomicU64>,
+    /// Number of responses which were errors
+    errors: AtomicU64,
+    /// Number of bytes sent back in responses
+    bytes_served: AtomicU64,
+}
+
+impl ServerMetrics {
+    pub fn new() -> Self {
+        Self {
+            commands: Command::names().iter().map(|&name| (name, AtomicU64::new(0))).collect(),
+            errors: AtomicU64::new(0),
+            bytes_served: AtomicU64::new(0),
+        }
+    }
+
+    /// Records a served command with its response
+    pub fn record(&self, command: &str, response: &CommandResponse) {
+        if let Some(count) = self.commands.get(command) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if let CommandResponse::Error(..) = response {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records the size in bytes of a response sent back to a client
+    pub fn record_bytes(&self, bytes: usize) {
+        self.bytes_served.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records a request which could not be read as a command
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Reads the current value of every counter
+    ///
+    /// Counters are read one at a time, so commands served meanwhile may only
+    /// be reflected in some of them.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            commands: self.commands
+                .iter()
+                .map(|(&name, count)| (name.to_owned(), count.load(Ordering::Relaxed)))
+                .collect(),
+            errors: self.errors.load(Ordering::Relaxed),
+            bytes_served: self.bytes_served.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for ServerMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Values of the `ServerMetrics` counters at one point in time
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Number of served commands by command name
+    pub commands: BTreeMap<String, u64>,
+    /// Number of responses which were errors
+    pub errors: u64,
+    /// Number of bytes sent back in responses
+    pub bytes_served: u64,
+}
+
+impl MetricsSnapshot {
+    /// Total number of served commands
+    pub fn total_commands(&self) -> u64 {
+        self.commands.values().sum()
+    }
+}
@@ -1,7 +1,11 @@
-pub use server::KvsServer;
-pub use commands::{ServerOpt, Engine};
-pub use response::{CommandResponse};
+pub use server::{KvsServer, MAX_CHUNK_SIZE, MAX_KEYS_PER_RESPONSE};
+pub use metrics::{MetricsSnapshot, ServerMetrics};
+pub use commands::{ServerOpt, ServerCommand, Engine, Protocol};
+pub use resp::{RespRequest, RespValue};
+pub use response::{CommandResponse, ErrorKind, ServerInfo};
 
 pub mod server;
+pub mod metrics;
 pub mod commands;
-pub mod response;
\ No newline at end of file
+pub mod response;
+pub mod resp;
\ No newline at end of file
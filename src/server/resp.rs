@@ -0,0 +1,160 @@
+use std::convert::TryFrom;
+use std::io::{BufRead, Read, Write};
+
+use crate::{KvsError, Result, MAX_FRAME_SIZE};
+
+/// Reply sent back to a client speaking RESP2, the protocol of Redis
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RespValue {
+    /// `+OK\r\n`
+    SimpleString(String),
+    /// `-ERR message\r\n`
+    Error(String),
+    /// `:1\r\n`
+    Integer(i64),
+    /// `$5\r\nvalue\r\n`, or the nil bulk string `$-1\r\n` for `None`
+    BulkString(Option<String>),
+}
+
+/// Request of a client speaking RESP2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RespRequest {
+    Ping(Option<String>),
+    Get(String),
+    Set(String, String),
+    Del(Vec<String>),
+}
+
+impl RespRequest {
+    /// Parse the arguments of a request, the first one being the command name in any case
+    ///
+    /// Returns the error message sent back to the client for unknown commands,
+    /// wrong numbers of arguments or arguments which are not UTF-8.
+    pub fn parse(args: Vec<Vec<u8>>) -> std::result::Result<RespRequest, String> {
+        let mut args = args
+            .into_iter()
+            .map(String::from_utf8)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| "ERR arguments must be valid UTF-8".to_owned())?;
+
+        if args.is_empty() {
+            return Err("ERR empty command".to_owned());
+        }
+
+        let name = args.remove(0).to_ascii_lowercase();
+        let wrong_arity = || format!("ERR wrong number of arguments for '{}' command", name);
+
+        match (name.as_str(), args.len()) {
+            ("ping", 0) => Ok(RespRequest::Ping(None)),
+            ("ping", 1) => Ok(RespRequest::Ping(args.pop())),
+            ("get", 1) => Ok(RespRequest::Get(args.remove(0))),
+            ("set", 2) => {
+                let value = args.remove(1);
+                Ok(RespRequest::Set(args.remove(0), value))
+            },
+            ("del", len) if len > 0 => Ok(RespRequest::Del(args)),
+            ("ping", _) | ("get", _) | ("set", _) | ("del", _) => Err(wrong_arity()),
+            _ => Err(format!("ERR unknown command '{}'", name))
+        }
+    }
+}
+
+/// Read one request, which is an array of bulk strings
+///
+/// Returns `None` if the stream ended before a new request started. It returns
+/// `KvsError::RequestError` for anything else than an array of bulk strings, or for a
+/// request bigger than `MAX_FRAME_SIZE` in total, in which case the stream cannot be read
+/// further.
+pub fn read_resp_request<R: BufRead>(reader: &mut R) -> Result<Option<Vec<Vec<u8>>>> {
+    let count = match read_line(reader)? {
+        Some(line) => parse_length(&line, b'*')?,
+        None => return Ok(None)
+    };
+
+    let mut args = Vec::with_capacity(count.min(64));
+
+    // Bytes of the request so far, counted like they were sent so that many empty
+    // arguments add up too
+    let mut total = 0;
+
+    for _ in 0..count {
+        let line = read_line(reader)?.ok_or_else(|| protocol_error("request ended early"))?;
+        let len = parse_length(&line, b'$')?;
+
+        // The length line and the bulk string are both followed by \r\n
+        total += line.len() + len + 4;
+        if total > MAX_FRAME_SIZE as usize {
+            return Err(protocol_error(&format!("request bigger than the maximum of {} bytes", MAX_FRAME_SIZE)));
+        }
+
+        // The argument grows as its bytes arrive instead of trusting the announced length
+        let mut arg = Vec::new();
+        reader.take(len as u64 + 2).read_to_end(&mut arg)?;
+
+        if arg.len() != len + 2 || !arg.ends_with(b"\r\n") {
+            return Err(protocol_error("bulk string does not match its length"));
+        }
+
+        arg.truncate(len);
+        args.push(arg);
+    }
+
+    Ok(Some(args))
+}
+
+/// Write the reply and flush the writer
+///
+/// Returns the number of bytes written.
+pub fn write_resp<W: Write>(writer: &mut W, value: &RespValue) -> Result<usize> {
+    let bytes = match value {
+        RespValue::SimpleString(s) => format!("+{}\r\n", s),
+        // Line breaks would end the error early
+        RespValue::Error(message) => format!("-{}\r\n", message.replace(['\r', '\n'], " ")),
+        RespValue::Integer(n) => format!(":{}\r\n", n),
+        RespValue::BulkString(Some(s)) => format!("${}\r\n{}\r\n", s.len(), s),
+        RespValue::BulkString(None) => "$-1\r\n".to_owned(),
+    };
+
+    writer.write_all(bytes.as_bytes())?;
+    writer.flush()?;
+
+    Ok(bytes.len())
+}
+
+/// Read a line ending with \r\n, without the line ending
+fn read_line<R: BufRead>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    let mut line = Vec::new();
+
+    // A length line is short, so a client never sending a line break cannot use up memory
+    reader.take(32).read_until(b'\n', &mut line)?;
+
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    if !line.ends_with(b"\r\n") {
+        return Err(protocol_error("line does not end with \\r\\n"));
+    }
+
+    line.truncate(line.len() - 2);
+
+    Ok(Some(line))
+}
+
+/// Parse a line made of the given type byte followed by a length
+fn parse_length(line: &[u8], prefix: u8) -> Result<usize> {
+    if line.first() != Some(&prefix) {
+        return Err(protocol_error(&format!("expected '{}'", prefix as char)));
+    }
+
+    std::str::from_utf8(&line[1..])
+        .ok()
+        .and_then(|len| len.parse::<u32>().ok())
+        .filter(|&len| len <= MAX_FRAME_SIZE)
+        .and_then(|len| usize::try_from(len).ok())
+        .ok_or_else(|| protocol_error("invalid length"))
+}
+
+fn protocol_error(reason: &str) -> KvsError {
+    KvsError::RequestError(format!("Protocol error: {}", reason))
+}
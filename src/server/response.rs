@@ -1,10 +1,140 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::{KeyStat, KvsError, SizeStats};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 /// Response to Get command
 pub enum CommandResponse {
-  Error(String),
+  /// Kind of the error and a message describing it
+  Error(ErrorKind, String),
   Value(String),
+  /// Length in bytes of a value sent in the following `Chunk` responses, answering GetChunked
+  ValueStart(u64),
+  /// Part of the value announced by `ValueStart`
+  Chunk(String),
+  /// Values of several keys in the requested order, `None` for missing keys
+  Values(Vec<Option<String>>),
+  Exists(bool),
+  Swapped(bool),
+  /// Number of keys in the store, of keys removed by RemovePrefix or of bytes of a value after Append
+  Count(u64),
+  /// Page of keys in key order and the key to list the next page after, `None` on the last page
+  Keys(Vec<String>, Option<String>),
+  Integer(i64),
+  /// Version of the server
+  Pong(String),
+  ServerInfo(ServerInfo),
+  SizeStats(SizeStats),
+  KeyStat(KeyStat),
   Success,
-  KeyNotFound
-}
\ No newline at end of file
+  KeyNotFound,
+  Queued,
+  Committed(usize)
+}
+
+impl CommandResponse {
+  /// Name of the response type
+  pub fn name(&self) -> &'static str {
+    match self {
+      CommandResponse::Error(..) => "error",
+      CommandResponse::Value(_) => "value",
+      CommandResponse::ValueStart(_) => "value_start",
+      CommandResponse::Chunk(_) => "chunk",
+      CommandResponse::Values(_) => "values",
+      CommandResponse::Exists(_) => "exists",
+      CommandResponse::Swapped(_) => "swapped",
+      CommandResponse::Count(_) => "count",
+      CommandResponse::Keys(..) => "keys",
+      CommandResponse::Integer(_) => "integer",
+      CommandResponse::Pong(_) => "pong",
+      CommandResponse::ServerInfo(_) => "server_info",
+      CommandResponse::SizeStats(_) => "size_stats",
+      CommandResponse::KeyStat(_) => "key_stat",
+      CommandResponse::Success => "success",
+      CommandResponse::KeyNotFound => "key_not_found",
+      CommandResponse::Queued => "queued",
+      CommandResponse::Committed(_) => "committed"
+    }
+  }
+
+  /// Outcome of the command for access logs: "hit" when a value was found,
+  /// "miss" when the key was not found, "error" on errors and "ok" otherwise
+  pub fn status(&self) -> &'static str {
+    match self {
+      CommandResponse::Error(..) => "error",
+      CommandResponse::KeyNotFound => "miss",
+      CommandResponse::Value(_) | CommandResponse::Values(_) | CommandResponse::KeyStat(_) => "hit",
+      CommandResponse::ValueStart(_) => "hit",
+      _ => "ok"
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+/// Kind of an error returned by the server, so clients can tell errors apart
+pub enum ErrorKind {
+  /// The key was not found in the store
+  KeyNotFound,
+  /// The value to increment is not an integer
+  NotAnInteger,
+  /// An I/O error happened in the server
+  Io,
+  /// A log record or a value in the store could not be read back
+  Corrupt,
+  /// Any other error, such as a command that is not allowed at that point
+  Other
+}
+
+impl ErrorKind {
+  /// Kind of the given engine error
+  pub fn of(err: &KvsError) -> Self {
+    match err {
+      KvsError::KeyNotFound => ErrorKind::KeyNotFound,
+      KvsError::NotAnInteger => ErrorKind::NotAnInteger,
+      KvsError::IOError(_) | KvsError::SledError(sled::Error::Io(_)) => ErrorKind::Io,
+      KvsError::CorruptLog { .. } | KvsError::MissingLogFile(_) | KvsError::UnexpectedCommand | KvsError::SerializationError(_)
+        | KvsError::BincodeError(_) | KvsError::Utf8Error(_) => ErrorKind::Corrupt,
+      _ => ErrorKind::Other
+    }
+  }
+
+  /// Rebuild the error on the client side from its kind and the message sent by the server
+  pub fn into_error(self, message: String) -> KvsError {
+    match self {
+      ErrorKind::KeyNotFound => KvsError::KeyNotFound,
+      ErrorKind::NotAnInteger => KvsError::NotAnInteger,
+      ErrorKind::Io => KvsError::IOError(io::Error::other(message)),
+      ErrorKind::Corrupt | ErrorKind::Other => KvsError::RequestError(message)
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+/// Description of a running server
+pub struct ServerInfo {
+  /// Name of the storage engine, `kvs` or `sled`
+  pub engine: String,
+  /// Version of the server
+  pub version: String,
+  /// Directory holding the engine's data, if the server was told about it
+  pub data_dir: Option<PathBuf>,
+  /// Seconds since the server was created
+  pub uptime_secs: u64
+}
+
+impl fmt::Display for ServerInfo {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "engine: {}", self.engine)?;
+    writeln!(f, "version: {}", self.version)?;
+
+    match &self.data_dir {
+      Some(data_dir) => writeln!(f, "data directory: {}", data_dir.display())?,
+      None => writeln!(f, "data directory: unknown")?
+    }
+
+    write!(f, "uptime: {}s", self.uptime_secs)
+  }
+}
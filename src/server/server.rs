@@ -1,79 +1,500 @@
-use std::io::BufReader;
-use std::io::BufWriter;
-use std::io::Write;
-use std::net::SocketAddr;
-use std::net::TcpListener;
-use std::net::TcpStream;
-use serde_json::Deserializer;
-use slog::{info, error, debug};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use slog::{info, error, debug, warn};
 
-use crate::{Command, KvsEngine , CommandResponse, Result};
+use crate::{read_frame, write_frame, Command, MAX_FRAME_SIZE, KvsEngine , CommandResponse, ErrorKind, KvsError, Listener, MetricsSnapshot, Protocol, Result, ServerAddr, ServerInfo, ServerMetrics, SharedQueueThreadPool, Stream, ThreadPool};
+use super::resp::{read_resp_request, write_resp, RespRequest, RespValue};
 
+/// Maximum number of keys in the response to a Keys command, which bounds its size
+/// however many keys the store holds
+pub const MAX_KEYS_PER_RESPONSE: usize = 1000;
+
+/// Maximum number of bytes in a chunk of the response to a GetChunked command, which keeps
+/// the chunk below `MAX_FRAME_SIZE` even if JSON escapes each of its bytes
+pub const MAX_CHUNK_SIZE: u32 = MAX_FRAME_SIZE / 8;
+
+#[derive(Clone)]
 pub struct KvsServer {
-  addr: SocketAddr,
-  engine: Box<dyn KvsEngine>,
-  logger: slog::Logger
+  addrs: Vec<ServerAddr>,
+  engine: Arc<dyn KvsEngine>,
+  logger: slog::Logger,
+  threads: u32,
+  max_value_size: Option<u64>,
+  read_only: bool,
+  access_log: bool,
+  allowed_commands: Option<HashSet<String>>,
+  protocol: Protocol,
+  nodelay: bool,
+  backlog: Option<u32>,
+  idle_timeout: Option<Duration>,
+  metrics: Arc<ServerMetrics>,
+  shutdown: Arc<AtomicBool>,
+  data_dir: Option<PathBuf>,
+  started: Instant
 }
 
 impl KvsServer {
-    pub fn new(addr: SocketAddr, engine: Box<dyn KvsEngine>, logger: slog::Logger) -> Self {
-        Self { addr, engine, logger }
+    /// Creates a server listening on a TCP address or a Unix domain socket
+    pub fn new(addr: impl Into<ServerAddr>, engine: Box<dyn KvsEngine>, logger: slog::Logger) -> Self {
+        Self::with_addrs(vec![addr.into()], engine, logger)
+    }
+
+    /// Creates a server listening on every given address, all served by the same engine
+    pub fn with_addrs(addrs: Vec<ServerAddr>, engine: Box<dyn KvsEngine>, logger: slog::Logger) -> Self {
+        // Connections are served by one thread per CPU unless configured otherwise
+        let threads = thread::available_parallelism().map(|n| n.get() as u32).unwrap_or(1);
+
+        Self { addrs, engine: Arc::from(engine), logger, threads, max_value_size: None, read_only: false, access_log: false, allowed_commands: None, protocol: Protocol::Json, nodelay: true, backlog: None, idle_timeout: None, metrics: Arc::new(ServerMetrics::new()), shutdown: Arc::new(AtomicBool::new(false)), data_dir: None, started: Instant::now() }
+    }
+
+    /// Returns the current values of the counters of served commands.
+    /// They are shared by all clones of the server.
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Sets the number of threads serving connections concurrently.
+    /// It defaults to the number of CPUs.
+    pub fn set_threads(&mut self, threads: u32) {
+        self.threads = threads;
+    }
+
+    /// Sets the size in bytes above which values sent by clients are rejected, whatever the engine.
+    /// Values of any size are accepted by default.
+    pub fn set_max_value_size(&mut self, max_value_size: Option<u64>) {
+        self.max_value_size = max_value_size;
+    }
+
+    /// Makes the server reject every command that can change the store, such as Set and Remove.
+    /// Commands are all allowed by default.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Logs one line per served command with its name, key, status and latency in microseconds.
+    /// Access logging is disabled by default.
+    pub fn set_access_log(&mut self, access_log: bool) {
+        self.access_log = access_log;
+    }
+
+    /// Restricts the commands run by the server to the given names, as returned by `Command::name`.
+    /// Every command is allowed by default, which `None` restores.
+    pub fn set_allowed_commands(&mut self, names: Option<HashSet<String>>) {
+        self.allowed_commands = names;
+    }
+
+    /// Sets the directory holding the engine's data, reported to clients by the ServerInfo command.
+    /// It is reported as unknown by default.
+    pub fn set_data_dir(&mut self, data_dir: PathBuf) {
+        self.data_dir = Some(data_dir);
+    }
+
+    /// Sets the protocol spoken with clients. It defaults to `Protocol::Json`.
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// Sets whether TCP_NODELAY is set on accepted TCP connections, which sends responses
+    /// right away instead of letting Nagle's algorithm delay them. It is set by default.
+    pub fn set_nodelay(&mut self, nodelay: bool) {
+        self.nodelay = nodelay;
+    }
+
+    /// Sets the maximum number of connections waiting to be accepted on every address,
+    /// which defaults to the one of the standard library. Only Linux supports it, so `run`
+    /// fails with `KvsError::BindFailed` on other platforms if it is set.
+    pub fn set_backlog(&mut self, backlog: Option<u32>) {
+        self.backlog = backlog;
+    }
+
+    /// Sets the time after which a connection on which the client sends nothing is closed,
+    /// which frees its thread for other connections. Idle connections are kept open by default.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.idle_timeout = idle_timeout;
+    }
+
+    /// Read every key listed in the given file, one per line, so that their values
+    /// are cached by the operating system before the server starts accepting connections
+    ///
+    /// Returns the number of keys found in the engine.
+    pub fn warm_up(&self, path: &Path) -> Result<usize> {
+        let keys = fs::read_to_string(path)?;
+        let mut found = 0;
+
+        for key in keys.lines().map(str::trim).filter(|key| !key.is_empty()) {
+            match self.engine.get(key.to_owned())? {
+                Some(_) => found += 1,
+                None => debug!(self.logger, "Warm-up key not found: {}", key)
+            }
+        }
+
+        info!(self.logger, "Warmed up {} keys from {}", found, path.display());
+
+        Ok(found)
+    }
+
+    /// Stop the server running on this server or any of its clones
+    ///
+    /// `run` returns once it has flushed the engine. Connections that are already open
+    /// keep being served by the thread pool until their clients close them.
+    pub fn shutdown(&self) -> Result<()> {
+        self.shutdown.store(true, Ordering::SeqCst);
+
+        // The listeners only check the flag between connections, so each is woken up with one
+        for addr in &self.addrs {
+            Stream::connect(addr)?;
+        }
+
+        Ok(())
     }
 
-    /// Run server
-    pub fn run(&mut self) -> Result<()> {
-        info!(self.logger, "Listening on {}", &self.addr);
+    /// Run server until `shutdown` is called
+    ///
+    /// Every connection is handed to a thread pool, so a slow client only occupies one thread.
+    /// Each address is listened on by its own thread, and all of them share the thread pool.
+    /// The engine is flushed before returning, so every acknowledged write is on disk.
+    ///
+    /// It returns `KvsError::BindFailed` with the address if any address cannot be listened on,
+    /// before accepting any connection.
+    pub fn run(&self) -> Result<()> {
         info!(self.logger, "Version {}", env!("CARGO_PKG_VERSION"));
 
-        // Bind listener to the address
-        let listener = TcpListener::bind(&self.addr)?;
+        let pool = SharedQueueThreadPool::new(self.threads)?;
+        info!(self.logger, "Serving connections with {} threads", self.threads);
+        info!(self.logger, "Speaking the {} protocol", self.protocol);
+
+        // Bind a listener to every address
+        let listeners = self.addrs
+            .iter()
+            .map(|addr| {
+                let listener = Listener::bind(addr).map_err(|e| KvsError::BindFailed(addr.to_string(), e))?;
+
+                if let Some(backlog) = self.backlog {
+                    listener.set_backlog(backlog).map_err(|e| KvsError::BindFailed(addr.to_string(), e))?;
+                }
+                info!(self.logger, "Listening on {}", addr);
+
+                Ok(listener)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let pool = &pool;
+
+        thread::scope(|scope| {
+            for listener in &listeners {
+                scope.spawn(move || self.accept(listener, pool));
+            }
+        });
+
+        info!(self.logger, "Shutting down");
+        self.engine.flush()
+    }
 
+    /// Hand every connection received by the listener to the thread pool until `shutdown` is called
+    fn accept(&self, listener: &Listener, pool: &SharedQueueThreadPool) {
         // Get stream from incoming connections
         for connection in listener.incoming() {
+            if self.shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+
             match connection {
                 Ok(stream) => {
                     info!(self.logger, "Connection received: {:?}", &stream);
 
-                    // Create reader for stream
-                    let reader = BufReader::new(&stream);
-
-                    // Create deserializer for commands sent through the stream
-                    let commands = Deserializer::from_reader(reader).into_iter::<Command>();
+                    if let Err(e) = stream.set_nodelay(self.nodelay) {
+                        warn!(self.logger, "Failed to set TCP_NODELAY: {}", e);
+                    }
 
-                    // Loop through the received commmands until we get None
-                    for cmd in commands {
-                        debug!(self.logger, "Received command: {:?}", &cmd);
+                    if let Err(e) = stream.set_read_timeout(self.idle_timeout) {
+                        warn!(self.logger, "Failed to set the idle timeout: {}", e);
+                    }
 
-                        // Read command and send response
-                        if let Err(e) = self.serve(&stream, cmd?) {
-                            error!(self.logger, "Error processing command: {}", e)
+                    let server = self.clone();
+                    pool.spawn(move || {
+                        if let Err(e) = server.handle_connection(&stream) {
+                            error!(server.logger, "Error reading commands: {}", e)
                         }
-                    }
+                    });
                 },
                 Err(e) => error!(self.logger, "Failed to establish a connection: {}", e)
             }
         }
+    }
+
+    /// Serve every command received through the stream until the client closes it
+    fn handle_connection(&self, stream: &Stream) -> Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!("connection", peer = ?stream.peer_addr().ok()).entered();
+
+        if self.protocol == Protocol::Resp {
+            return self.handle_resp_connection(stream);
+        }
+
+        // Create reader for stream
+        let mut reader = BufReader::new(stream);
+
+        // Commands buffered by an open transaction on this connection
+        // They are discarded if the connection closes before a Commit
+        let mut transaction = None;
+
+        // Loop through the received commmands until the client closes the stream
+        loop {
+            let cmd = match read_frame::<_, Command>(&mut reader) {
+                Ok(Some(cmd)) => cmd,
+                Ok(None) => return Ok(()),
+                Err(KvsError::RequestError(e)) => {
+                    // The rest of an oversized frame is never read, so the connection cannot continue
+                    let res = CommandResponse::Error(ErrorKind::Other, format!("Request error: {}", e));
+                    self.metrics.record_error();
+                    let bytes = write_frame(&mut BufWriter::new(stream), &res)?;
+                    self.metrics.record_bytes(bytes);
+
+                    return Err(KvsError::RequestError(e));
+                },
+                Err(KvsError::SerializationError(e)) => {
+                    // The whole frame was read, so the connection can go on with the next one
+                    warn!(self.logger, "Invalid command received: {}", e);
+                    let res = CommandResponse::Error(ErrorKind::Other, format!("Request error: {}", e));
+                    self.metrics.record_error();
+                    let bytes = write_frame(&mut BufWriter::new(stream), &res)?;
+                    self.metrics.record_bytes(bytes);
+
+                    continue;
+                },
+                Err(e) if is_disconnect(&e) => {
+                    debug!(self.logger, "Client disconnected: {}", e);
+                    return Ok(());
+                },
+                Err(e) if is_idle_timeout(&e) => {
+                    debug!(self.logger, "Closing idle connection");
+                    return Ok(());
+                },
+                Err(e) => return Err(e)
+            };
+
+            debug!(self.logger, "Received command: {:?}", &cmd);
+
+            // Read command and send response
+            if let Err(e) = self.serve(stream, cmd, &mut transaction) {
+                error!(self.logger, "Error processing command: {}", e)
+            }
+        }
+    }
 
-        Ok(())
+    /// Serve every RESP request received through the stream until the client closes it
+    fn handle_resp_connection(&self, stream: &Stream) -> Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut writer = BufWriter::new(stream);
+
+        loop {
+            let reply = match read_resp_request(&mut reader) {
+                Ok(Some(args)) => match RespRequest::parse(args) {
+                    Ok(request) => {
+                        debug!(self.logger, "Received RESP request: {:?}", &request);
+                        self.serve_resp(request)?
+                    },
+                    Err(message) => {
+                        self.metrics.record_error();
+                        RespValue::Error(message)
+                    }
+                },
+                Ok(None) => return Ok(()),
+                Err(KvsError::RequestError(e)) => {
+                    // The stream cannot be parsed any further, so the connection cannot continue
+                    self.metrics.record_error();
+                    let bytes = write_resp(&mut writer, &RespValue::Error(format!("ERR {}", e)))?;
+                    self.metrics.record_bytes(bytes);
+
+                    return Err(KvsError::RequestError(e));
+                },
+                Err(e) if is_disconnect(&e) => {
+                    debug!(self.logger, "Client disconnected: {}", e);
+                    return Ok(());
+                },
+                Err(e) if is_idle_timeout(&e) => {
+                    debug!(self.logger, "Closing idle connection");
+                    return Ok(());
+                },
+                Err(e) => return Err(e)
+            };
+
+            let bytes = write_resp(&mut writer, &reply)?;
+            self.metrics.record_bytes(bytes);
+        }
+    }
+
+    /// Run the commands of a RESP request and turn their responses into a RESP reply
+    fn serve_resp(&self, request: RespRequest) -> Result<RespValue> {
+        let error = |message: String| RespValue::Error(format!("ERR {}", message));
+
+        let reply = match request {
+            RespRequest::Ping(message) => match self.execute(Command::Ping)? {
+                CommandResponse::Error(_, e) => error(e),
+                _ => match message {
+                    Some(message) => RespValue::BulkString(Some(message)),
+                    None => RespValue::SimpleString("PONG".to_owned())
+                }
+            },
+            RespRequest::Get(key) => match self.execute(Command::Get { key, out: None })? {
+                CommandResponse::Value(value) => RespValue::BulkString(Some(value)),
+                CommandResponse::KeyNotFound => RespValue::BulkString(None),
+                CommandResponse::Error(_, e) => error(e),
+                response => error(format!("Unexpected response: {:?}", response))
+            },
+            RespRequest::Set(key, value) => match self.execute(Command::Set { key, value, expires_at: None })? {
+                CommandResponse::Success => RespValue::SimpleString("OK".to_owned()),
+                CommandResponse::Error(_, e) => error(e),
+                response => error(format!("Unexpected response: {:?}", response))
+            },
+            RespRequest::Del(keys) => {
+                // Redis replies with the number of removed keys, missing keys count as 0
+                let mut removed = 0;
+
+                for key in keys {
+                    match self.execute(Command::Remove { key })? {
+                        CommandResponse::Success => removed += 1,
+                        CommandResponse::Error(ErrorKind::KeyNotFound, _) => {},
+                        CommandResponse::Error(_, e) => return Ok(error(e)),
+                        response => return Ok(error(format!("Unexpected response: {:?}", response)))
+                    }
+                }
+
+                RespValue::Integer(removed)
+            }
+        };
+
+        Ok(reply)
+    }
+
+    /// Run the command outside of any transaction and return its response instead of sending it
+    fn execute(&self, command: Command) -> Result<CommandResponse> {
+        let mut response = None;
+
+        self.serve_with(command, &mut None, |res| {
+            response = Some(res.clone());
+            Ok(0)
+        })?;
+
+        Ok(response.expect("Command served without a response"))
     }
 
     /// Check which command was received and send back appropriate response
-    pub fn serve (&mut self, stream: &TcpStream, command: Command) -> Result<()> {
+    ///
+    /// While a transaction is open, Set and Remove commands are buffered in `transaction`
+    /// and only applied to the engine on Commit, so other connections never see them
+    /// before that. Get commands are still served from the committed state of the engine.
+    pub fn serve (
+        &self,
+        stream: &Stream,
+        command: Command,
+        transaction: &mut Option<Vec<Command>>
+    ) -> Result<()> {
         // Create writer for stream
         let mut writer = BufWriter::new(stream);
 
+        self.serve_with(command, transaction, |res| write_frame(&mut writer, res))
+    }
+
+    /// Serve the command like `serve`, handing its response to `send`,
+    /// which returns the number of bytes sent
+    fn serve_with<F>(&self, command: Command, transaction: &mut Option<Vec<Command>>, mut send: F) -> Result<()>
+    where
+        F: FnMut(&CommandResponse) -> Result<usize>
+    {
+        let name = command.name();
+
+        // Span around the command with its outcome and latency recorded once the response is sent
+        #[cfg(feature = "tracing")]
+        let span = tracing::info_span!(
+            "command",
+            command = command.name(),
+            key = command.key().unwrap_or_default(),
+            outcome = tracing::field::Empty,
+            latency_us = tracing::field::Empty
+        ).entered();
+        #[cfg(feature = "tracing")]
+        let start = std::time::Instant::now();
+
+        // Key and start time of the command for the access log line written once the response is sent
+        let access = self.access_log.then(|| (command.key().unwrap_or_default().to_owned(), Instant::now()));
+
         // Macro to send back response
         macro_rules! send_res {
             ($res: expr) => {
                 let res = $res;
                 debug!(self.logger, "Command response: {:?}", &res);
 
+                if let Some((key, start)) = &access {
+                    info!(self.logger, "Command served";
+                        "command" => name,
+                        "key" => key,
+                        "status" => res.status(),
+                        "latency_us" => start.elapsed().as_micros() as u64
+                    );
+                }
+
+                #[cfg(feature = "tracing")]
+                {
+                    span.record("outcome", res.name());
+                    span.record("latency_us", start.elapsed().as_micros() as u64);
+                    tracing::info!("command served");
+                }
+
+                // Count the command before the client can see its response
+                self.metrics.record(name, res);
+
                 // Send response back to the stream
-                serde_json::to_writer(&mut writer, &res)?;
-                writer.flush()?;
+                let bytes = send(res)?;
+                self.metrics.record_bytes(bytes);
             };
         }
 
+        // Commands with an empty key or a value that is too big never reach the engine
+        if let Err(e) = command.validate(self.max_value_size) {
+            send_res!(&CommandResponse::Error(ErrorKind::of(&e), format!("Invalid command: {}", e)));
+
+            return Ok(());
+        }
+
+        // `KvsEngine` cannot set an expiry, so a Set carrying one is rejected instead of
+        // being stored without it, whether it is run right away or queued in a transaction
+        if let Command::Set { expires_at: Some(_), .. } = command {
+            let e = KvsError::RequestError("Set commands with an expiry are not supported by the server".to_owned());
+            send_res!(&CommandResponse::Error(ErrorKind::of(&e), format!("Invalid command: {}", e)));
+
+            return Ok(());
+        }
+
+        // Commands which are not allowed are neither run nor queued in a transaction
+        if self.read_only && command.is_mutating() {
+            send_res!(&CommandResponse::Error(ErrorKind::Other, format!("Command not allowed on a read-only server: {}", name)));
+
+            return Ok(());
+        }
+
+        if self.allowed_commands.as_ref().is_some_and(|allowed| !allowed.contains(name)) {
+            send_res!(&CommandResponse::Error(ErrorKind::Other, format!("Command not allowed: {}", name)));
+
+            return Ok(());
+        }
+
+        // Buffer mutations until the transaction is committed or rolled back
+        if let Some(queued) = transaction.as_mut() {
+            if let Command::Set { .. } | Command::Remove { .. } = command {
+                queued.push(command);
+                send_res!(&CommandResponse::Queued);
+
+                return Ok(());
+            }
+        }
+
         match command {
             Command::Get { key, .. } => match self.engine.get(key) {
                 Ok(Some(value)) => {
@@ -92,12 +513,47 @@ impl KvsServer {
                 },
                 Err(e) => {
                     // Set response
-                    let res = CommandResponse::Error(format!("Get command error: {}", e));
+                    let res = CommandResponse::Error(ErrorKind::of(&e), format!("Get command error: {}", e));
 
                     // Send response back to the stream
                     send_res!(&res);
                 }
             },
+            Command::GetChunked { key, chunk_size } => match self.engine.get(key) {
+                Ok(Some(value)) => {
+                    send_res!(&CommandResponse::ValueStart(value.len() as u64));
+
+                    // The chunks belong to the response above, so they are not counted as responses
+                    for chunk in str_chunks(&value, chunk_size.clamp(1, MAX_CHUNK_SIZE) as usize) {
+                        let bytes = send(&CommandResponse::Chunk(chunk.to_owned()))?;
+                        self.metrics.record_bytes(bytes);
+                    }
+                },
+                Ok(None) => {
+                    send_res!(&CommandResponse::KeyNotFound);
+                },
+                Err(e) => {
+                    send_res!(&CommandResponse::Error(ErrorKind::of(&e), format!("GetChunked command error: {}", e)));
+                }
+            },
+            Command::GetMany { keys } => {
+                let res = match self.engine.get_many(keys) {
+                    Ok(values) => CommandResponse::Values(values),
+                    Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("GetMany command error: {}", e))
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Exists { key } => {
+                let res = match self.engine.contains_key(key) {
+                    Ok(exists) => CommandResponse::Exists(exists),
+                    Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("Exists command error: {}", e))
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
             Command::Set { key, value, .. } => {
                 match self.engine.set(key, value) {
                     Ok(()) => {
@@ -108,7 +564,7 @@ impl KvsServer {
                         send_res!(&res);
                     },
                     Err(e) => {
-                         let res = CommandResponse::Error(format!("Set command error: {}", e));
+                         let res = CommandResponse::Error(ErrorKind::of(&e), format!("Set command error: {}", e));
 
                         // Send response back to the stream
                         send_res!(&res);
@@ -125,14 +581,264 @@ impl KvsServer {
                 },
                 Err(e) => {
                     // Set response
-                    let res = CommandResponse::Error(format!("Remove command error: {}", e));
+                    let res = CommandResponse::Error(ErrorKind::of(&e), format!("Remove command error: {}", e));
 
                     // Send response back to the stream
                     send_res!(&res);
                 }
             },
+            Command::RemovePrefix { prefix } => {
+                let res = if transaction.is_some() {
+                    CommandResponse::Error(ErrorKind::Other, "RemovePrefix command error: Not supported inside a transaction".to_owned())
+                } else {
+                    match self.engine.remove_prefix(prefix) {
+                        Ok(count) => CommandResponse::Count(count as u64),
+                        Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("RemovePrefix command error: {}", e))
+                    }
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Len => {
+                let res = match self.engine.len() {
+                    Ok(len) => CommandResponse::Count(len as u64),
+                    Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("Len command error: {}", e))
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Keys { prefix, after, limit } => {
+                let limit = limit.unwrap_or(MAX_KEYS_PER_RESPONSE).min(MAX_KEYS_PER_RESPONSE);
+
+                let res = if limit == 0 {
+                    CommandResponse::Error(ErrorKind::Other, "Keys command error: The limit must be at least 1".to_owned())
+                } else {
+                    // One key more than the page tells whether there is a next page
+                    match self.engine.keys_page(prefix.unwrap_or_default(), after, limit + 1) {
+                        Ok(mut keys) => {
+                            let next = if keys.len() > limit {
+                                keys.truncate(limit);
+                                keys.last().cloned()
+                            } else {
+                                None
+                            };
+
+                            CommandResponse::Keys(keys, next)
+                        },
+                        Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("Keys command error: {}", e))
+                    }
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Ping => {
+                // Answered without touching the engine
+                let res = CommandResponse::Pong(env!("CARGO_PKG_VERSION").to_owned());
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::ServerInfo => {
+                let res = CommandResponse::ServerInfo(ServerInfo {
+                    engine: self.engine.name().to_owned(),
+                    version: env!("CARGO_PKG_VERSION").to_owned(),
+                    data_dir: self.data_dir.clone(),
+                    uptime_secs: self.started.elapsed().as_secs()
+                });
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Stats => {
+                let res = match self.engine.size_stats() {
+                    Ok(stats) => CommandResponse::SizeStats(stats),
+                    Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("Stats command error: {}", e))
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Stat { key } => {
+                let res = match self.engine.stat_key(key) {
+                    Ok(Some(stat)) => CommandResponse::KeyStat(stat),
+                    Ok(None) => CommandResponse::KeyNotFound,
+                    Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("Stat command error: {}", e))
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Rename { from, to } => {
+                let res = if transaction.is_some() {
+                    CommandResponse::Error(ErrorKind::Other, "Rename command error: Not supported inside a transaction".to_owned())
+                } else {
+                    match self.engine.rename(from, to) {
+                        Ok(()) => CommandResponse::Success,
+                        Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("Rename command error: {}", e))
+                    }
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::SetBytes { key, value } => {
+                let res = if transaction.is_some() {
+                    CommandResponse::Error(ErrorKind::Other, "SetBytes command error: Not supported inside a transaction".to_owned())
+                } else {
+                    match self.engine.set_bytes(key, value) {
+                        Ok(()) => CommandResponse::Success,
+                        Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("SetBytes command error: {}", e))
+                    }
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Cas { key, expected, new } => {
+                let res = if transaction.is_some() {
+                    CommandResponse::Error(ErrorKind::Other, "Cas command error: Not supported inside a transaction".to_owned())
+                } else {
+                    match self.engine.compare_and_swap(key, expected, new) {
+                        Ok(swapped) => CommandResponse::Swapped(swapped),
+                        Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("Cas command error: {}", e))
+                    }
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Clear => {
+                let res = if transaction.is_some() {
+                    CommandResponse::Error(ErrorKind::Other, "Clear command error: Not supported inside a transaction".to_owned())
+                } else {
+                    match self.engine.clear() {
+                        Ok(()) => CommandResponse::Success,
+                        Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("Clear command error: {}", e))
+                    }
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Incr { key, delta } => {
+                let res = if transaction.is_some() {
+                    CommandResponse::Error(ErrorKind::Other, "Incr command error: Not supported inside a transaction".to_owned())
+                } else {
+                    match self.engine.increment(key, delta) {
+                        Ok(value) => CommandResponse::Integer(value),
+                        Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("Incr command error: {}", e))
+                    }
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Append { key, value } => {
+                let res = if transaction.is_some() {
+                    CommandResponse::Error(ErrorKind::Other, "Append command error: Not supported inside a transaction".to_owned())
+                } else {
+                    match self.engine.append(key, value) {
+                        Ok(len) => CommandResponse::Count(len as u64),
+                        Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("Append command error: {}", e))
+                    }
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Begin => {
+                let res = if transaction.is_some() {
+                    CommandResponse::Error(ErrorKind::Other, "Begin command error: Transaction already in progress".to_owned())
+                } else {
+                    *transaction = Some(Vec::new());
+                    CommandResponse::Success
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Commit => {
+                let res = match transaction.take() {
+                    Some(commands) => {
+                        let count = commands.len();
+
+                        match self.engine.transaction(commands) {
+                            Ok(()) => CommandResponse::Committed(count),
+                            Err(e) => CommandResponse::Error(ErrorKind::of(&e), format!("Commit command error: {}", e))
+                        }
+                    },
+                    None => CommandResponse::Error(ErrorKind::Other, "Commit command error: No transaction in progress".to_owned())
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            Command::Rollback => {
+                let res = match transaction.take() {
+                    Some(_) => CommandResponse::Success,
+                    None => CommandResponse::Error(ErrorKind::Other, "Rollback command error: No transaction in progress".to_owned())
+                };
+
+                // Send response back to the stream
+                send_res!(&res);
+            },
+            // Batch markers are only ever written to the log by the store itself
+            Command::BatchBegin { .. } | Command::BatchCommit { .. } => {
+                let res = CommandResponse::Error(ErrorKind::Other, format!("Unexpected command: {}", name));
+                send_res!(&res);
+            },
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Whether the error is a read which timed out because the client sent nothing for
+/// the idle timeout, reported as `WouldBlock` on Unix and `TimedOut` on Windows
+fn is_idle_timeout(err: &KvsError) -> bool {
+    match err {
+        KvsError::IOError(e) => matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut),
+        _ => false
+    }
+}
+
+/// Splits the value in chunks of at most `size` bytes, cut between characters, so that a chunk
+/// is only bigger when a single character is
+fn str_chunks(value: &str, size: usize) -> impl Iterator<Item = &str> {
+    let mut rest = value;
+
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+
+        let mut end = size.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+
+        Some(chunk)
+    })
+}
+
+/// Whether the error means that the client went away, in the middle of a request or not,
+/// which ends its connection without being a failure of the server
+fn is_disconnect(err: &KvsError) -> bool {
+    match err {
+        KvsError::IOError(e) => matches!(
+            e.kind(),
+            io::ErrorKind::UnexpectedEof | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionAborted | io::ErrorKind::BrokenPipe
+        ),
+        _ => false
+    }
+}
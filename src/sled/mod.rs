@@ -1,3 +1,3 @@
-pub use sled_engine::SledKvsEngine;
+pub use sled_engine::{SledConfig, SledKvsEngine};
 
 pub mod sled_engine;
\ No newline at end of file
@@ -1,13 +1,48 @@
+use std::io;
+use std::ops::Bound;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use crate::{KvsEngine, KvsError, Result};
+use sled::Batch;
+use sled::transaction::{abort, TransactionError};
 
-#[derive(Debug)]
+use crate::{Backoff, Command, KeyStat, KvsEngine, KvsError, Result, SizeStats};
+use crate::client::commands::validate_key;
+
+#[derive(Debug, Clone)]
 /// Using the "sled" crate, we create a new database engine
+///
+/// Clones share the same database, so they can be used from different threads.
 pub struct SledKvsEngine {
-    db: sled::Db
+    db: sled::Db,
+    /// Number of times a failed flush is retried before giving up.
+    flush_retries: u32,
+    /// Delays between flush retries.
+    flush_backoff: Backoff
 }
 
+/// Options used when opening a `SledKvsEngine`, which are passed on to `sled::Config`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SledConfig {
+    /// Maximum size in bytes of the page cache. It defaults to 1 GiB.
+    pub cache_capacity: u64,
+    /// Interval in milliseconds between automatic flushes to disk. It defaults to 500 ms.
+    /// With none, writes are only flushed when `flush` is called.
+    pub flush_every_ms: Option<u64>,
+    /// Whether the data is compressed with zstd. It defaults to false.
+    /// Enabling it requires the `compression` feature of sled, without which opening fails.
+    pub use_compression: bool,
+}
+
+impl Default for SledConfig {
+    fn default() -> Self {
+        Self {
+            cache_capacity: 1024 * 1024 * 1024,
+            flush_every_ms: Some(500),
+            use_compression: false,
+        }
+    }
+}
 
 impl SledKvsEngine {
     /// Opens a sled store at the given path.
@@ -16,15 +51,65 @@ impl SledKvsEngine {
     ///
     /// # Errors
     ///
-    /// It propagates sled errors during the log load.
+    /// It propagates the errors of `open_with_config`.
     pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
-        let db = sled::open(path.into())?;
+        Self::open_with_config(path, SledConfig::default())
+    }
+
+    /// Opens a sled store at the given path with the given options.
+    ///
+    /// This will create a new directory if the given one does not exist.
+    ///
+    /// # Errors
+    ///
+    /// It propagates sled errors during the log load, including unsupported options.
+    pub fn open_with_config(path: impl Into<PathBuf>, config: SledConfig) -> Result<Self> {
+        let db = sled::Config::new()
+            .path(path.into())
+            .cache_capacity(config.cache_capacity)
+            .flush_every_ms(config.flush_every_ms)
+            .use_compression(config.use_compression)
+            .open()?;
     
-        Ok(Self { db })
+        Ok(Self {
+            db,
+            flush_retries: 3,
+            flush_backoff: Backoff::new(Duration::from_millis(10), Duration::from_secs(1))
+        })
+    }
+
+    /// Sets how many times a transient flush failure is retried and the delays between retries.
+    ///
+    /// It defaults to 3 retries starting with a 10 ms delay which doubles after every attempt.
+    pub fn set_flush_retry(&mut self, retries: u32, backoff: Backoff) {
+        self.flush_retries = retries;
+        self.flush_backoff = backoff;
+    }
+
+    /// Flushes the database, retrying transient I/O failures with exponential backoff.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::SledError` right away for a fatal error or once all
+    /// retries of a transient error are exhausted.
+    fn flush_with_retry(&self) -> Result<()> {
+        self.retry_flush(|| self.db.flush())
+    }
+
+    /// Runs the flush, retrying its transient failures as configured
+    fn retry_flush(&self, flush: impl FnMut() -> sled::Result<usize>) -> Result<()> {
+        self.flush_backoff.retry(self.flush_retries, is_transient, flush)?;
+
+        Ok(())
     }
 }
 
+
 impl KvsEngine for SledKvsEngine {
+    fn name(&self) -> &'static str {
+        "sled"
+    }
+
     /// Gets the string value of a given string key.
     ///
     /// Returns `None` if the given key does not exist.
@@ -32,7 +117,7 @@ impl KvsEngine for SledKvsEngine {
     /// # Errors
     ///
     /// It propagates sled errors while reading from the log.
-    fn get(&mut self, key: String) -> Result<Option<String>> {
+    fn get(&self, key: String) -> Result<Option<String>> {
         let value = self.db
             .get(key.as_bytes())?
             .map(|i_vec| AsRef::<[u8]>::as_ref(&i_vec).to_vec())
@@ -42,6 +127,80 @@ impl KvsEngine for SledKvsEngine {
         Ok(value)
     }
 
+    /// Checks whether a given string key exists without copying its value.
+    ///
+    /// # Errors
+    ///
+    /// It propagates sled errors while reading from the log.
+    fn contains_key(&self, key: String) -> Result<bool> {
+        Ok(self.db.contains_key(key.as_bytes())?)
+    }
+
+    /// Returns the number of keys in the database.
+    ///
+    /// Sled counts the keys by iterating over all of them.
+    fn len(&self) -> Result<usize> {
+        Ok(self.db.len())
+    }
+
+    /// Returns every key in key order.
+    ///
+    /// # Errors
+    ///
+    /// It propagates sled errors while reading from the log and returns `KvsError::Utf8Error`
+    /// if a key is not valid UTF-8.
+    fn all_keys(&self) -> Result<Vec<String>> {
+        self.db
+            .iter()
+            .keys()
+            .map(|key| Ok(String::from_utf8(key?.to_vec())?))
+            .collect()
+    }
+
+    /// Returns at most `limit` keys starting with the prefix in key order, after `after` if it is given.
+    ///
+    /// # Errors
+    ///
+    /// It propagates sled errors while reading from the log and returns `KvsError::Utf8Error`
+    /// if a key is not valid UTF-8.
+    fn keys_page(&self, prefix: String, after: Option<String>, limit: usize) -> Result<Vec<String>> {
+        let start = match after {
+            Some(after) if after >= prefix => Bound::Excluded(after.into_bytes()),
+            _ => Bound::Included(prefix.clone().into_bytes())
+        };
+
+        self.db
+            .range((start, Bound::Unbounded))
+            .keys()
+            .take_while(|key| key.as_ref().map_or(true, |key| key.starts_with(prefix.as_bytes())))
+            .take(limit)
+            .map(|key| Ok(String::from_utf8(key?.to_vec())?))
+            .collect()
+    }
+
+    /// Returns the number of keys and the sizes of their values.
+    ///
+    /// # Errors
+    ///
+    /// It propagates sled errors while reading from the log.
+    fn size_stats(&self) -> Result<SizeStats> {
+        let value_lens = self.db
+            .iter()
+            .values()
+            .map(|value| Ok(value?.len() as u64))
+            .collect::<Result<Vec<u64>>>()?;
+
+        Ok(SizeStats::from_value_lens(value_lens))
+    }
+
+    /// Returns the length of the value of a key, which sled reads from its tree.
+    /// Keys never expire in sled and have no log file.
+    fn stat_key(&self, key: String) -> Result<Option<KeyStat>> {
+        let value = self.db.get(key)?;
+
+        Ok(value.map(|value| KeyStat { log_file_id: None, value_bytes: value.len() as u64, expires_at: None }))
+    }
+
     /// Sets the value of a string key to a string.
     ///
     /// If the key already exists, the previous value will be overwritten.
@@ -49,12 +208,44 @@ impl KvsEngine for SledKvsEngine {
     /// # Errors
     ///
     /// It propagates sled errors while writing to the log.
-    fn set(&mut self, key: String, value: String) -> Result<()> {
+    fn set(&self, key: String, value: String) -> Result<()> {
         // Set key-value pair in database
         self.db.insert(key, value.as_bytes())?;
 
         // Make sure the write operation is completed or throws an error
-        self.db.flush()?;
+        self.flush_with_retry()?;
+
+        Ok(())
+    }
+
+    /// Gets the value of a given string key as bytes, without UTF-8 validation.
+    ///
+    /// Returns `None` if the given key does not exist.
+    ///
+    /// # Errors
+    ///
+    /// It propagates sled errors while reading from the log.
+    fn get_bytes(&self, key: String) -> Result<Option<Vec<u8>>> {
+        let value = self.db
+            .get(key.as_bytes())?
+            .map(|i_vec| AsRef::<[u8]>::as_ref(&i_vec).to_vec());
+
+        Ok(value)
+    }
+
+    /// Sets the value of a string key to arbitrary bytes.
+    ///
+    /// If the key already exists, the previous value will be overwritten.
+    ///
+    /// # Errors
+    ///
+    /// It propagates sled errors while writing to the log.
+    fn set_bytes(&self, key: String, value: Vec<u8>) -> Result<()> {
+        // Set key-value pair in database
+        self.db.insert(key, value)?;
+
+        // Make sure the write operation is completed or throws an error
+        self.flush_with_retry()?;
 
         Ok(())
     }
@@ -66,13 +257,339 @@ impl KvsEngine for SledKvsEngine {
     /// It returns `KvsError::KeyNotFound` if the given key is not found.
     ///
     /// It propagates sled errors while writing to the log.
-    fn remove(&mut self, key: String) -> Result<()> {
+    fn remove(&self, key: String) -> Result<()> {
         // Remove key-value pair from database
         self.db.remove(key)?.ok_or(KvsError::KeyNotFound)?;
 
         // Make sure the write operation is completed or throws an error
-        self.db.flush()?;
+        self.flush_with_retry()?;
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Removes every key starting with the given prefix atomically using a sled batch
+    /// and returns how many were removed.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::InvalidKey` if the prefix is empty.
+    ///
+    /// It propagates sled errors while writing to the log.
+    fn remove_prefix(&self, prefix: String) -> Result<usize> {
+        validate_key(&prefix)?;
+
+        let mut batch = Batch::default();
+        let mut count = 0;
+
+        for key in self.db.scan_prefix(prefix.as_bytes()).keys() {
+            batch.remove(key?);
+            count += 1;
+        }
+
+        self.db.apply_batch(batch)?;
+
+        // Make sure the write operation is completed or throws an error
+        self.flush_with_retry()?;
+
+        Ok(count)
+    }
+
+    /// Sets the value of a string key to a string and returns the value it replaced.
+    ///
+    /// Returns `None` if the key did not exist.
+    ///
+    /// # Errors
+    ///
+    /// It propagates sled errors while writing to the log, and returns `KvsError::Utf8Error`
+    /// after the write if the previous value is not valid UTF-8.
+    fn set_and_get_previous(&self, key: String, value: String) -> Result<Option<String>> {
+        // Sled returns the replaced value from the insert itself
+        let previous = self.db.insert(key, value.as_bytes())?;
+
+        // Make sure the write operation is completed or throws an error
+        self.flush_with_retry()?;
+
+        let previous = previous
+            .map(|i_vec| String::from_utf8(AsRef::<[u8]>::as_ref(&i_vec).to_vec()))
+            .transpose()?;
+
+        Ok(previous)
+    }
+
+    /// Removes a given key and returns its value.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::KeyNotFound` if the given key is not found.
+    ///
+    /// It propagates sled errors while writing to the log, and returns `KvsError::Utf8Error`
+    /// after the write if the removed value is not valid UTF-8.
+    fn take(&self, key: String) -> Result<String> {
+        // Sled returns the removed value from the remove itself
+        let value = self.db.remove(key)?.ok_or(KvsError::KeyNotFound)?;
+
+        // Make sure the write operation is completed or throws an error
+        self.flush_with_retry()?;
+
+        Ok(String::from_utf8(AsRef::<[u8]>::as_ref(&value).to_vec())?)
+    }
+
+    /// Sets the key to `new`, or removes it if `new` is `None`, only if its current value
+    /// is `expected`, where `None` means the key does not exist.
+    ///
+    /// Returns whether the swap happened, using sled's atomic compare and swap.
+    ///
+    /// # Errors
+    ///
+    /// It propagates sled errors while writing to the log.
+    fn compare_and_swap(&self, key: String, expected: Option<String>, new: Option<String>) -> Result<bool> {
+        let swapped = self.db
+            .compare_and_swap(key, expected.as_deref().map(str::as_bytes), new.as_deref().map(str::as_bytes))?
+            .is_ok();
+
+        if swapped {
+            // Make sure the write operation is completed or throws an error
+            self.flush_with_retry()?;
+        }
+
+        Ok(swapped)
+    }
+
+    /// Removes every key from the database.
+    ///
+    /// # Errors
+    ///
+    /// It propagates sled errors while writing to the log.
+    fn clear(&self) -> Result<()> {
+        self.db.clear()?;
+
+        // Make sure the write operation is completed or throws an error
+        self.flush_with_retry()?;
+
+        Ok(())
+    }
+
+    /// Adds `delta` to the integer value of a given string key atomically using a sled
+    /// transaction and returns the new value. A key that does not exist counts as 0.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::NotAnInteger` if the current value is not an integer and
+    /// `KvsError::IntegerOverflow` if the new value does not fit in an `i64`.
+    ///
+    /// It propagates sled errors while writing to the log.
+    fn increment(&self, key: String, delta: i64) -> Result<i64> {
+        let value = self.db
+            .transaction(|tx_db| {
+                let value = match tx_db.get(key.as_bytes())? {
+                    Some(current) => {
+                        let current = std::str::from_utf8(&current)
+                            .ok()
+                            .and_then(|current| current.parse::<i64>().ok());
+
+                        match current.map(|current| current.checked_add(delta)) {
+                            Some(Some(value)) => value,
+                            Some(None) => return abort(KvsError::IntegerOverflow),
+                            None => return abort(KvsError::NotAnInteger)
+                        }
+                    },
+                    None => delta
+                };
+
+                tx_db.insert(key.as_bytes(), value.to_string().as_bytes())?;
+
+                Ok(value)
+            })
+            .map_err(|err| match err {
+                TransactionError::Abort(err) => err,
+                TransactionError::Storage(err) => KvsError::SledError(err)
+            })?;
+
+        // Make sure the write operation is completed or throws an error
+        self.flush_with_retry()?;
+
+        Ok(value)
+    }
+
+    /// Appends `suffix` to the value of a given string key atomically using a sled transaction
+    /// and returns the length of the new value in bytes. A key that does not exist counts as
+    /// an empty string.
+    ///
+    /// # Errors
+    ///
+    /// It propagates sled errors while writing to the log.
+    fn append(&self, key: String, suffix: String) -> Result<usize> {
+        let len = self.db
+            .transaction(|tx_db| {
+                let mut value = tx_db
+                    .get(key.as_bytes())?
+                    .map(|i_vec| AsRef::<[u8]>::as_ref(&i_vec).to_vec())
+                    .unwrap_or_default();
+                value.extend_from_slice(suffix.as_bytes());
+
+                tx_db.insert(key.as_bytes(), value.as_slice())?;
+
+                Ok(value.len())
+            })
+            .map_err(|err: TransactionError<KvsError>| match err {
+                TransactionError::Abort(err) => err,
+                TransactionError::Storage(err) => KvsError::SledError(err)
+            })?;
+
+        // Make sure the write operation is completed or throws an error
+        self.flush_with_retry()?;
+
+        Ok(len)
+    }
+
+    /// Moves the value of the `from` key to the `to` key atomically using a sled transaction,
+    /// overwriting any value of `to`.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::KeyNotFound` if the `from` key is not found.
+    ///
+    /// It propagates sled errors while writing to the log.
+    fn rename(&self, from: String, to: String) -> Result<()> {
+        self.db
+            .transaction(|tx_db| {
+                match tx_db.remove(from.as_bytes())? {
+                    Some(value) => {
+                        tx_db.insert(to.as_bytes(), value)?;
+                        Ok(())
+                    },
+                    None => abort(KvsError::KeyNotFound)
+                }
+            })
+            .map_err(|err| match err {
+                TransactionError::Abort(err) => err,
+                TransactionError::Storage(err) => KvsError::SledError(err)
+            })?;
+
+        // Make sure the write operation is completed or throws an error
+        self.flush_with_retry()?;
+
+        Ok(())
+    }
+
+    /// Applies the given Set and Remove commands atomically using a sled transaction.
+    ///
+    /// # Errors
+    ///
+    /// It returns `KvsError::KeyNotFound` if a Remove command targets a key that does not
+    /// exist at that point of the transaction, `KvsError::Unsupported` if a Set command has
+    /// an expiry, which sled cannot store, and `KvsError::UnexpectedCommand` if a command
+    /// other than Set or Remove is given. Nothing is written in these cases.
+    ///
+    /// It propagates sled errors while writing to the log.
+    fn transaction(&self, commands: Vec<Command>) -> Result<()> {
+        self.db
+            .transaction(|tx_db| {
+                for cmd in &commands {
+                    match cmd {
+                        Command::Set { expires_at: Some(_), .. } => {
+                            return abort(KvsError::Unsupported("sled keys cannot expire".to_owned()));
+                        },
+                        Command::Set { key, value, .. } => {
+                            tx_db.insert(key.as_bytes(), value.as_bytes())?;
+                        },
+                        Command::Remove { key } => {
+                            if tx_db.remove(key.as_bytes())?.is_none() {
+                                return abort(KvsError::KeyNotFound);
+                            }
+                        },
+                        _ => return abort(KvsError::UnexpectedCommand)
+                    }
+                }
+
+                Ok(())
+            })
+            .map_err(|err| match err {
+                TransactionError::Abort(err) => err,
+                TransactionError::Storage(err) => KvsError::SledError(err)
+            })?;
+
+        // Make sure the write operation is completed or throws an error
+        self.flush_with_retry()?;
+
+        Ok(())
+    }
+
+    /// Flushes the database to disk.
+    ///
+    /// Every write already flushes it, so this only matters to make sure nothing is pending.
+    ///
+    /// # Errors
+    ///
+    /// It propagates sled errors while writing to the log.
+    fn flush(&self) -> Result<()> {
+        self.flush_with_retry()
+    }
+}
+
+/// Whether a sled error is a temporary I/O failure worth retrying
+fn is_transient(err: &sled::Error) -> bool {
+    match err {
+        sled::Error::Io(err) => matches!(
+            err.kind(),
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::io;
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    use super::{is_transient, SledKvsEngine};
+    use crate::{Backoff, KvsError};
+
+    fn io_error(kind: io::ErrorKind) -> sled::Error {
+        sled::Error::Io(io::Error::new(kind, "flush failed"))
+    }
+
+    // Should only retry I/O errors which can go away by themselves
+    #[test]
+    fn transient_errors() {
+        assert!(is_transient(&io_error(io::ErrorKind::Interrupted)));
+        assert!(is_transient(&io_error(io::ErrorKind::WouldBlock)));
+        assert!(is_transient(&io_error(io::ErrorKind::TimedOut)));
+
+        assert!(!is_transient(&io_error(io::ErrorKind::PermissionDenied)));
+        assert!(!is_transient(&io_error(io::ErrorKind::NotFound)));
+        assert!(!is_transient(&io_error(io::ErrorKind::Other)));
+        assert!(!is_transient(&sled::Error::Unsupported("flush".to_owned())));
+    }
+
+    // Should retry transient flush failures as configured, and fatal ones never
+    #[test]
+    fn flush_retries() {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let mut engine = SledKvsEngine::open(temp_dir.path()).expect("unable to open sled");
+        engine.set_flush_retry(2, Backoff::new(Duration::from_millis(1), Duration::from_millis(1)));
+
+        // Count the attempts of a flush failing with the given error until the given attempt
+        let attempts = |engine: &SledKvsEngine, kind: io::ErrorKind, succeed_at: u32| {
+            let count = Cell::new(0);
+            let result = engine.retry_flush(|| {
+                count.set(count.get() + 1);
+                if count.get() == succeed_at { Ok(0) } else { Err(io_error(kind)) }
+            });
+
+            (result, count.get())
+        };
+
+        assert!(matches!(attempts(&engine, io::ErrorKind::Interrupted, 3), (Ok(()), 3)));
+        assert!(matches!(attempts(&engine, io::ErrorKind::Interrupted, 0), (Err(KvsError::SledError(_)), 3)));
+        assert!(matches!(attempts(&engine, io::ErrorKind::PermissionDenied, 0), (Err(KvsError::SledError(_)), 1)));
+
+        // No retries at all
+        engine.set_flush_retry(0, Backoff::new(Duration::from_millis(1), Duration::from_millis(1)));
+        assert!(matches!(attempts(&engine, io::ErrorKind::Interrupted, 2), (Err(KvsError::SledError(_)), 1)));
+    }
+}
@@ -0,0 +1,22 @@
+pub use shared_queue::SharedQueueThreadPool;
+
+pub mod shared_queue;
+
+use crate::Result;
+
+/// Pool of threads running jobs concurrently
+pub trait ThreadPool {
+    /// Create a pool with the given number of threads
+    ///
+    /// It returns an error if a thread cannot be spawned.
+    fn new(threads: u32) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Run the job on one of the threads of the pool
+    ///
+    /// A panicking job does not reduce the number of threads of the pool.
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static;
+}
@@ -0,0 +1,70 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::{Result, ThreadPool};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Thread pool whose threads take jobs from a single shared queue
+///
+/// Dropping the pool closes the queue, and every thread exits once it finished its current job.
+#[derive(Debug)]
+pub struct SharedQueueThreadPool {
+    sender: Sender<Job>
+}
+
+impl ThreadPool for SharedQueueThreadPool {
+    fn new(threads: u32) -> Result<Self> {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0..threads.max(1) {
+            spawn_worker(Worker(Arc::clone(&receiver)))?;
+        }
+
+        Ok(Self { sender })
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static
+    {
+        // Sending only fails once every thread is gone, which the workers prevent by respawning
+        self.sender
+            .send(Box::new(job))
+            .expect("No thread left in the thread pool");
+    }
+}
+
+/// Receiving end of the job queue owned by one thread
+///
+/// If a job panics, the worker is dropped while the thread unwinds and spawns
+/// a new thread in its place.
+struct Worker(Arc<Mutex<Receiver<Job>>>);
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let worker = Worker(Arc::clone(&self.0));
+
+            // Nothing can be reported from here, so the pool runs with one thread less
+            let _ = spawn_worker(worker);
+        }
+    }
+}
+
+/// Spawn a thread running jobs from the queue until the pool is dropped
+fn spawn_worker(worker: Worker) -> Result<()> {
+    thread::Builder::new().spawn(move || loop {
+        // The lock is released before running the job so other threads can take the next one
+        let job = worker.0.lock().expect("Job queue lock poisoned").recv();
+
+        match job {
+            Ok(job) => job(),
+            Err(_) => break // The pool was dropped
+        }
+    })?;
+
+    Ok(())
+}
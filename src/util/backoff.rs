@@ -0,0 +1,85 @@
+use std::thread;
+use std::time::Duration;
+use rand::Rng;
+
+/// Exponential backoff between retries of a failed operation
+///
+/// The delay before retry number `attempt` (starting at 0) is
+/// `base * multiplier ^ attempt`, capped at `max`. With jitter enabled,
+/// a random delay between zero and that value is used instead, so that
+/// clients retrying at the same time spread out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Backoff {
+    /// Delay before the first retry
+    pub base: Duration,
+    /// Upper bound of any delay
+    pub max: Duration,
+    /// Factor applied to the delay after every retry
+    pub multiplier: f64,
+    /// Whether a random delay up to the computed one is used
+    pub jitter: bool
+}
+
+impl Backoff {
+    /// Create a backoff doubling the delay after every retry, without jitter
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base,
+            max,
+            multiplier: 2.0,
+            jitter: false
+        }
+    }
+
+    /// Set the factor applied to the delay after every retry
+    ///
+    /// Factors smaller than 1 are treated as 1, so delays never shrink.
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Enable or disable jitter
+    pub fn with_jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Delay before retry number `attempt`, starting at 0
+    pub fn delay(&self, attempt: u32) -> Duration {
+        // Computed in seconds so that big attempts saturate at the maximum instead of overflowing
+        let exponent = attempt.min(i32::MAX as u32) as i32;
+        let secs = self.base.as_secs_f64() * self.multiplier.powi(exponent);
+        let delay = Duration::from_secs_f64(secs.min(self.max.as_secs_f64()));
+
+        if self.jitter {
+            rand::thread_rng().gen_range(Duration::ZERO..=delay)
+        } else {
+            delay
+        }
+    }
+
+    /// Run the operation, retrying it up to `retries` times while it fails with
+    /// an error for which `is_retryable` returns true, sleeping between attempts
+    ///
+    /// Returns the first success, or the last error once retries are exhausted
+    /// or the error is not retryable.
+    pub fn retry<T, E>(
+        &self,
+        retries: u32,
+        is_retryable: impl Fn(&E) -> bool,
+        mut operation: impl FnMut() -> Result<T, E>
+    ) -> Result<T, E> {
+        let mut attempt = 0;
+
+        loop {
+            match operation() {
+                Err(err) if attempt < retries && is_retryable(&err) => {
+                    thread::sleep(self.delay(attempt));
+                    attempt += 1;
+                },
+                result => return result
+            }
+        }
+    }
+}
\ No newline at end of file
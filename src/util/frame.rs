@@ -0,0 +1,68 @@
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::{KvsError, Result};
+
+/// Biggest payload accepted in a frame, in bytes
+pub const MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+/// Serialize the value to JSON and send it as one frame: a 4-byte big-endian
+/// payload length followed by the payload
+///
+/// The writer is flushed, so the frame is sent right away. It returns the number of
+/// bytes written, or `KvsError::RequestError` without writing anything if the payload
+/// is bigger than `MAX_FRAME_SIZE`.
+pub fn write_frame<W: Write, T: Serialize>(writer: &mut W, value: &T) -> Result<usize> {
+    let payload = serde_json::to_vec(value)?;
+    let len = frame_len(payload.len())?;
+
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(&payload)?;
+    writer.flush()?;
+
+    Ok(len.to_be_bytes().len() + payload.len())
+}
+
+/// Read one frame and deserialize its JSON payload
+///
+/// Returns `None` if the stream ended before a new frame started. It returns
+/// `KvsError::RequestError` without reading the payload if the announced length
+/// is bigger than `MAX_FRAME_SIZE`, in which case the stream cannot be read further.
+pub fn read_frame<R: Read, T: DeserializeOwned>(reader: &mut R) -> Result<Option<T>> {
+    let mut len = [0u8; 4];
+
+    // A stream closing between frames is the normal end of a connection
+    match reader.read_exact(&mut len) {
+        Ok(()) => {},
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(KvsError::IOError(e))
+    }
+
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME_SIZE {
+        return Err(frame_too_big(len as usize));
+    }
+
+    let mut payload = vec![0u8; len as usize];
+    reader.read_exact(&mut payload)?;
+
+    Ok(Some(serde_json::from_slice(&payload)?))
+}
+
+/// Length of a payload as written in the frame header
+fn frame_len(len: usize) -> Result<u32> {
+    match u32::try_from(len) {
+        Ok(len) if len <= MAX_FRAME_SIZE => Ok(len),
+        _ => Err(frame_too_big(len))
+    }
+}
+
+fn frame_too_big(len: usize) -> KvsError {
+    KvsError::RequestError(format!(
+        "Frame of {} bytes exceeds the maximum of {} bytes",
+        len,
+        MAX_FRAME_SIZE
+    ))
+}
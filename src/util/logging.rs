@@ -0,0 +1,84 @@
+use std::fmt::{self, Display};
+use std::io;
+use std::str::FromStr;
+use slog::{Drain, Level, LevelFilter, o};
+
+use crate::KvsError;
+
+/// Output format of the logs written to stderr by the binaries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoggerFormat {
+    /// Compact human-readable lines. This is the default.
+    Text,
+    /// One JSON object per line, for log aggregators
+    Json
+}
+
+impl LoggerFormat {
+    /// Possible values of this enum
+    pub fn variants() -> [&'static str; 2] {
+        ["text", "json"]
+    }
+
+    /// Build a logger writing the records of the given level or more severe to stderr
+    /// in this format
+    ///
+    /// Records are written from a background thread, which flushes them when
+    /// the last clone of the logger is dropped.
+    pub fn build_logger(self, level: Level) -> slog::Logger {
+        match self {
+            LoggerFormat::Text => {
+                let decorator = slog_term::TermDecorator::new().build();
+                let drain = slog_term::CompactFormat::new(decorator).build().fuse();
+                let drain = LevelFilter::new(drain, level).fuse();
+                let drain = slog_async::Async::new(drain).build().fuse();
+
+                slog::Logger::root(drain, o!())
+            },
+            LoggerFormat::Json => {
+                let drain = slog_json::Json::new(io::stderr()).add_default_keys().build().fuse();
+                let drain = LevelFilter::new(drain, level).fuse();
+                let drain = slog_async::Async::new(drain).build().fuse();
+
+                slog::Logger::root(drain, o!())
+            }
+        }
+    }
+}
+
+/// Names of the log levels accepted by `parse_log_level`
+pub const LOG_LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+
+/// Parse the name of a log level, as listed in `LOG_LEVELS`
+pub fn parse_log_level(s: &str) -> Result<Level, KvsError> {
+    match s {
+        "error" => Ok(Level::Error),
+        "warn" => Ok(Level::Warning),
+        "info" => Ok(Level::Info),
+        "debug" => Ok(Level::Debug),
+        "trace" => Ok(Level::Trace),
+        _ => Err(KvsError::UnknownLogLevel)
+    }
+}
+
+impl FromStr for LoggerFormat {
+    type Err = KvsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LoggerFormat::Text),
+            "json" => Ok(LoggerFormat::Json),
+            _ => Err(KvsError::UnknownLogFormat)
+        }
+    }
+}
+
+impl Display for LoggerFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = match *self {
+            LoggerFormat::Text => "text",
+            LoggerFormat::Json => "json",
+        };
+        write!(f, "{}", printable)
+    }
+}
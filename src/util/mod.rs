@@ -0,0 +1,9 @@
+pub use backoff::Backoff;
+pub use frame::{read_frame, write_frame, MAX_FRAME_SIZE};
+pub use logging::{parse_log_level, LoggerFormat, LOG_LEVELS};
+pub use transport::{Listener, ServerAddr, Stream};
+
+pub mod backoff;
+pub mod frame;
+pub mod logging;
+pub mod transport;
\ No newline at end of file
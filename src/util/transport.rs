@@ -0,0 +1,273 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+use crate::KvsError;
+
+/// Prefix of addresses which are paths to a Unix domain socket
+const UNIX_PREFIX: &str = "unix:";
+
+/// Address the server listens on and clients connect to
+///
+/// It is parsed from either `IP:PORT` for TCP or `unix:PATH` for a Unix domain socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl From<SocketAddr> for ServerAddr {
+    fn from(addr: SocketAddr) -> Self {
+        ServerAddr::Tcp(addr)
+    }
+}
+
+impl FromStr for ServerAddr {
+    type Err = KvsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix(UNIX_PREFIX) {
+            #[cfg(unix)]
+            Some(path) if !path.is_empty() => Ok(ServerAddr::Unix(PathBuf::from(path))),
+            #[cfg(unix)]
+            Some(_) => Err(KvsError::InvalidAddress(format!("{} is missing the socket path", s))),
+            #[cfg(not(unix))]
+            Some(_) => Err(KvsError::InvalidAddress(format!("{} needs Unix domain sockets, which this platform lacks", s))),
+            None => s.parse().map(ServerAddr::Tcp).map_err(|e| KvsError::InvalidAddress(format!("{}: {}", s, e)))
+        }
+    }
+}
+
+impl fmt::Display for ServerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ServerAddr::Tcp(addr) => addr.fmt(f),
+            #[cfg(unix)]
+            ServerAddr::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// Listener accepting connections on a `ServerAddr`
+#[derive(Debug)]
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Listen on the given address
+    ///
+    /// A socket file left behind by a previous server is replaced, while any other
+    /// existing file makes it fail.
+    pub fn bind(addr: &ServerAddr) -> io::Result<Listener> {
+        match addr {
+            ServerAddr::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr)?)),
+            #[cfg(unix)]
+            ServerAddr::Unix(path) => {
+                use std::os::unix::fs::FileTypeExt;
+
+                if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+                    std::fs::remove_file(path)?;
+                }
+
+                Ok(Listener::Unix(UnixListener::bind(path)?))
+            }
+        }
+    }
+
+    /// Sets the maximum number of connections waiting to be accepted, which `bind` leaves
+    /// to the default of the standard library, 128
+    ///
+    /// The standard library binds and listens in one call, and socket2, which could set the
+    /// backlog before `listen`, is not a dependency. So the backlog is changed by calling
+    /// `listen` again on the listening socket. Linux updates the backlog of a listening socket
+    /// this way, but POSIX does not guarantee it, so other platforms could silently keep the
+    /// previous backlog. There it returns an error of kind `io::ErrorKind::Unsupported`
+    /// instead. The operating system may also cap the backlog.
+    pub fn set_backlog(&self, backlog: u32) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            use std::convert::TryFrom;
+            use std::os::unix::io::AsRawFd;
+
+            let fd = match self {
+                Listener::Tcp(listener) => listener.as_raw_fd(),
+                Listener::Unix(listener) => listener.as_raw_fd(),
+            };
+            let backlog = libc::c_int::try_from(backlog).unwrap_or(libc::c_int::MAX);
+
+            // Listening again on a listening socket only updates its backlog on Linux
+            // SAFETY: `fd` is the open descriptor of the socket owned by `self`. The borrow of
+            // `self` keeps the listener, and so the descriptor, alive and open until the call
+            // returns. `listen` neither takes ownership of the descriptor nor touches memory.
+            if unsafe { libc::listen(fd, backlog) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            Ok(())
+        }
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = backlog;
+
+            Err(io::Error::new(io::ErrorKind::Unsupported, "the listen backlog can only be changed on Linux"))
+        }
+    }
+
+    /// Wait for the next connection
+    pub fn accept(&self) -> io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => listener.accept().map(|(stream, _)| Stream::Tcp(stream)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => listener.accept().map(|(stream, _)| Stream::Unix(stream)),
+        }
+    }
+
+    /// Iterate over the incoming connections, which never ends
+    pub fn incoming(&self) -> impl Iterator<Item = io::Result<Stream>> + '_ {
+        std::iter::repeat_with(move || self.accept())
+    }
+}
+
+/// Connection between a client and the server
+///
+/// Like `TcpStream`, reads and writes go through a shared reference, so a reader
+/// and a writer can be created for the same stream.
+#[derive(Debug)]
+pub enum Stream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Connect to the server listening on the given address
+    pub fn connect(addr: &ServerAddr) -> io::Result<Stream> {
+        match addr {
+            ServerAddr::Tcp(addr) => Ok(Stream::Tcp(TcpStream::connect(addr)?)),
+            #[cfg(unix)]
+            ServerAddr::Unix(path) => Ok(Stream::Unix(UnixStream::connect(path)?)),
+        }
+    }
+
+    /// Connect to the server listening on the given address, giving up after the given timeout
+    /// if there is one, which then also applies to every read and write on the connection
+    ///
+    /// Connecting to a Unix domain socket does not wait for the server, so only its reads
+    /// and writes time out.
+    pub fn connect_timeout(addr: &ServerAddr, timeout: Option<Duration>) -> io::Result<Stream> {
+        let stream = match (addr, timeout) {
+            (ServerAddr::Tcp(addr), Some(timeout)) => Stream::Tcp(TcpStream::connect_timeout(addr, timeout)?),
+            _ => Stream::connect(addr)?
+        };
+
+        stream.set_timeout(timeout)?;
+
+        Ok(stream)
+    }
+
+    /// Sets the time after which reads and writes on the connection fail, or removes it with `None`
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            },
+            #[cfg(unix)]
+            Stream::Unix(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            },
+        }
+    }
+
+    /// Sets the time after which reads on the connection fail, or removes it with `None`
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    /// Sets TCP_NODELAY on a TCP connection, so that small writes are sent right away instead
+    /// of waiting to be merged with the following ones. Unix domain sockets never wait.
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => stream.set_nodelay(nodelay),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok(()),
+        }
+    }
+
+    /// Open another handle to the same connection, so that a reader and a writer can own one each
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Tcp(stream) => Ok(Stream::Tcp(stream.try_clone()?)),
+            #[cfg(unix)]
+            Stream::Unix(stream) => Ok(Stream::Unix(stream.try_clone()?)),
+        }
+    }
+
+    /// Address of the other end of the connection, for logging
+    pub fn peer_addr(&self) -> io::Result<String> {
+        match self {
+            Stream::Tcp(stream) => stream.peer_addr().map(|addr| addr.to_string()),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.peer_addr().map(|addr| format!("{:?}", addr)),
+        }
+    }
+}
+
+impl Read for &Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).read(buf),
+        }
+    }
+}
+
+impl Write for &Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).write(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(stream) => (&*stream).flush(),
+            #[cfg(unix)]
+            Stream::Unix(stream) => (&*stream).flush(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        (&*self).read(buf)
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&*self).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&*self).flush()
+    }
+}
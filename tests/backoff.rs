@@ -0,0 +1,69 @@
+use kvs::Backoff;
+use std::cell::Cell;
+use std::time::Duration;
+
+// Should grow the delay by the multiplier until it reaches the maximum
+#[test]
+fn backoff_sequence() {
+    let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(50));
+
+    let delays: Vec<Duration> = (0..5).map(|attempt| backoff.delay(attempt)).collect();
+    assert_eq!(delays, vec![
+        Duration::from_millis(10),
+        Duration::from_millis(20),
+        Duration::from_millis(40),
+        Duration::from_millis(50),
+        Duration::from_millis(50),
+    ]);
+
+    let backoff = backoff.with_multiplier(3.0);
+    assert_eq!(backoff.delay(1), Duration::from_millis(30));
+
+    // Huge attempts saturate at the maximum
+    assert_eq!(backoff.delay(u32::MAX), Duration::from_millis(50));
+}
+
+// Should keep jittered delays between zero and the computed delay
+#[test]
+fn backoff_jitter_bounds() {
+    let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(50));
+    let jittered = backoff.with_jitter(true);
+
+    for attempt in 0..10 {
+        for _ in 0..100 {
+            assert!(jittered.delay(attempt) <= backoff.delay(attempt));
+        }
+    }
+}
+
+// Should retry retryable errors until success or until retries are exhausted
+#[test]
+fn backoff_retry() {
+    let backoff = Backoff::new(Duration::from_millis(1), Duration::from_millis(1));
+    let calls = Cell::new(0);
+
+    // Succeeds on the third attempt
+    let result: Result<u32, &str> = backoff.retry(5, |_| true, || {
+        calls.set(calls.get() + 1);
+        if calls.get() < 3 { Err("transient") } else { Ok(calls.get()) }
+    });
+    assert_eq!(result, Ok(3));
+
+    // Gives up after the last retry
+    calls.set(0);
+    let result: Result<(), &str> = backoff.retry(2, |_| true, || {
+        calls.set(calls.get() + 1);
+        Err("transient")
+    });
+    assert_eq!(result, Err("transient"));
+    assert_eq!(calls.get(), 3);
+
+    // Never retries an error that is not retryable
+    calls.set(0);
+    let result: Result<(), &str> = backoff.retry(5, |_| false, || {
+        calls.set(calls.get() + 1);
+        Err("fatal")
+    });
+    assert_eq!(result, Err("fatal"));
+    assert_eq!(calls.get(), 1);
+}
@@ -1,4 +1,6 @@
 use assert_cmd::prelude::*;
+use kvs::{ExitStatus, KvStore, KvStoreConfig, KvsEngine};
+use predicates::prelude::*;
 use predicates::str::{contains, is_empty};
 use std::fs::{self, File};
 use std::process::Command;
@@ -20,28 +22,28 @@ fn client_cli_invalid_get() {
     let temp_dir = TempDir::new().unwrap();
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get"])
+        .args(["get"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "extra", "field"])
+        .args(["get", "extra", "field"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key", "--addr", "invalid-addr"])
+        .args(["get", "key", "--addr", "invalid-addr"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key", "--unknown-flag"])
+        .args(["get", "key", "--unknown-flag"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
@@ -52,35 +54,35 @@ fn client_cli_invalid_set() {
     let temp_dir = TempDir::new().unwrap();
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set"])
+        .args(["set"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "missing_field"])
+        .args(["set", "missing_field"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "key", "value", "extra_field"])
+        .args(["set", "key", "value", "extra_field"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["set", "key", "value", "--addr", "invalid-addr"])
+        .args(["set", "key", "value", "--addr", "invalid-addr"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["get", "key", "--unknown-flag"])
+        .args(["get", "key", "--unknown-flag"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
@@ -91,28 +93,28 @@ fn client_cli_invalid_rm() {
     let temp_dir = TempDir::new().unwrap();
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm"])
+        .args(["rm"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm", "extra", "field"])
+        .args(["rm", "extra", "field"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm", "key", "--addr", "invalid-addr"])
+        .args(["rm", "key", "--addr", "invalid-addr"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["rm", "key", "--unknown-flag"])
+        .args(["rm", "key", "--unknown-flag"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
@@ -123,7 +125,7 @@ fn client_cli_invalid_subcommand() {
     let temp_dir = TempDir::new().unwrap();
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["unknown"])
+        .args(["unknown"])
         .current_dir(&temp_dir)
         .assert()
         .failure();
@@ -134,7 +136,7 @@ fn client_cli_invalid_subcommand() {
 fn client_cli_version() {
     let temp_dir = TempDir::new().unwrap();
     let mut cmd = Command::cargo_bin("kvs-client").unwrap();
-    cmd.args(&["-V"])
+    cmd.args(["-V"])
         .current_dir(&temp_dir)
         .assert()
         .stdout(contains(env!("CARGO_PKG_VERSION")));
@@ -145,7 +147,7 @@ fn client_cli_version() {
 fn server_cli_version() {
     let temp_dir = TempDir::new().unwrap();
     let mut cmd = Command::cargo_bin("kvs-server").unwrap();
-    cmd.args(&["-V"])
+    cmd.args(["-V"])
         .current_dir(&temp_dir)
         .assert()
         .stdout(contains(env!("CARGO_PKG_VERSION")));
@@ -157,13 +159,14 @@ fn cli_log_configuration() {
     let stderr_path = temp_dir.path().join("stderr");
     let mut cmd = Command::cargo_bin("kvs-server").unwrap();
     let mut child = cmd
-        .args(&["--engine", "kvs", "--addr", "127.0.0.1:4001"])
+        .args(["--engine", "kvs", "--addr", "127.0.0.1:4001"])
         .current_dir(&temp_dir)
         .stderr(File::create(&stderr_path).unwrap())
         .spawn()
         .unwrap();
     thread::sleep(Duration::from_secs(1));
     child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
 
     let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
     assert!(content.contains(env!("CARGO_PKG_VERSION")));
@@ -171,6 +174,171 @@ fn cli_log_configuration() {
     assert!(content.contains("127.0.0.1:4001"));
 }
 
+#[test]
+fn cli_json_log_format() {
+    let temp_dir = TempDir::new().unwrap();
+    let stderr_path = temp_dir.path().join("stderr");
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(["--engine", "kvs", "--addr", "127.0.0.1:4010", "--log-format", "json"])
+        .current_dir(&temp_dir)
+        .stderr(File::create(&stderr_path).unwrap())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+
+    // Every line is a JSON object, including the version and listening lines
+    let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
+    let messages: Vec<String> = content
+        .lines()
+        .map(|line| {
+            let record: serde_json::Value = serde_json::from_str(line).expect("log line is not JSON");
+            record["msg"].as_str().expect("log line has no message").to_owned()
+        })
+        .collect();
+    assert!(messages.iter().any(|msg| msg.contains(env!("CARGO_PKG_VERSION"))));
+    assert!(messages.iter().any(|msg| msg.contains("127.0.0.1:4010")));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4010", "--log-format", "json", "get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("\"msg\":\"Failed to connect"));
+
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--log-format", "xml"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+#[test]
+fn cli_log_level() {
+    let temp_dir = TempDir::new().unwrap();
+    let stderr_path = temp_dir.path().join("stderr");
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(["--engine", "kvs", "--addr", "127.0.0.1:4011", "--log-level", "warn"])
+        .current_dir(&temp_dir)
+        .stderr(File::create(&stderr_path).unwrap())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+
+    // Info lines are filtered out
+    let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
+    assert!(!content.contains("Listening on"));
+
+    // Retries are logged as warnings, while the final failure is an error
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4011", "--retries", "1", "--retry-delay-ms", "10", "-v", "error", "get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("Failed to connect").and(contains("Connection attempt").not()));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4011", "--log-level", "loud", "get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("loud"));
+}
+
+#[test]
+fn cli_access_log() {
+    let temp_dir = TempDir::new().unwrap();
+    let stderr_path = temp_dir.path().join("stderr");
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(["--engine", "kvs", "--addr", "127.0.0.1:4017", "--log-format", "json", "--access-log"])
+        .current_dir(&temp_dir)
+        .stderr(File::create(&stderr_path).unwrap())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    for args in [&["set", "key1", "value1"][..], &["get", "key1"], &["get", "key2"]] {
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(["--addr", "127.0.0.1:4017"])
+            .args(args)
+            .current_dir(&temp_dir)
+            .assert()
+            .success();
+    }
+    thread::sleep(Duration::from_millis(200));
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+
+    // One line per command with its name, key and status
+    let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
+    let served: Vec<(String, String, String)> = content
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).expect("log line is not JSON"))
+        .filter(|record| record["msg"] == "Command served")
+        .map(|record| {
+            assert!(record["latency_us"].is_u64());
+            let field = |name: &str| record[name].as_str().unwrap().to_owned();
+            (field("command"), field("key"), field("status"))
+        })
+        .collect();
+    let expected = [("set", "key1", "ok"), ("get", "key1", "hit"), ("get", "key2", "miss")];
+    assert_eq!(served, expected.map(|(c, k, s)| (c.to_owned(), k.to_owned(), s.to_owned())));
+}
+
+// Should warn about a truncated log file and still serve the keys before the cut.
+// With the `tracing` feature the warning is a tracing event, which the server does not print.
+#[cfg(not(feature = "tracing"))]
+#[test]
+fn cli_truncated_log_warning() {
+    let temp_dir = TempDir::new().unwrap();
+    {
+        let config = KvStoreConfig { log_dir: Some("logs".into()), ..KvStoreConfig::default() };
+        let store = KvStore::open_with_options(temp_dir.path(), config).unwrap();
+        store.set("key1".to_owned(), "value1".to_owned()).unwrap();
+        store.set("key2".to_owned(), "value2".to_owned()).unwrap();
+    }
+
+    // Cut the last record in the middle of its command
+    let log_path = temp_dir.path().join("logs").join("1.log");
+    let len = fs::metadata(&log_path).unwrap().len();
+    fs::OpenOptions::new().write(true).open(&log_path).unwrap().set_len(len - 5).unwrap();
+
+    let stderr_path = temp_dir.path().join("stderr");
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(["--engine", "kvs", "--addr", "127.0.0.1:4018"])
+        .current_dir(&temp_dir)
+        .stderr(File::create(&stderr_path).unwrap())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4018", "get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+
+    let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
+    assert!(content.contains("of log file 1, ignoring the rest of the log file"));
+}
+
 #[test]
 fn cli_wrong_engine() {
     // sled first, kvs second
@@ -178,15 +346,16 @@ fn cli_wrong_engine() {
         let temp_dir = TempDir::new().unwrap();
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
         let mut child = cmd
-            .args(&["--engine", "sled", "--addr", "127.0.0.1:4002"])
+            .args(["--engine", "sled", "--addr", "127.0.0.1:4002"])
             .current_dir(&temp_dir)
             .spawn()
             .unwrap();
         thread::sleep(Duration::from_secs(1));
         child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait on server");
 
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
-        cmd.args(&["--engine", "kvs", "--addr", "127.0.0.1:4003"])
+        cmd.args(["--engine", "kvs", "--addr", "127.0.0.1:4003"])
             .current_dir(&temp_dir)
             .assert()
             .failure();
@@ -197,39 +366,97 @@ fn cli_wrong_engine() {
         let temp_dir = TempDir::new().unwrap();
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
         let mut child = cmd
-            .args(&["--engine", "kvs", "--addr", "127.0.0.1:4002"])
+            .args(["--engine", "kvs", "--addr", "127.0.0.1:4002"])
             .current_dir(&temp_dir)
             .spawn()
             .unwrap();
         thread::sleep(Duration::from_secs(1));
         child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait on server");
 
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
-        cmd.args(&["--engine", "sled", "--addr", "127.0.0.1:4003"])
+        cmd.args(["--engine", "sled", "--addr", "127.0.0.1:4003"])
             .current_dir(&temp_dir)
             .assert()
             .failure();
     }
 }
 
+// Should keep the engine config file and the logs in the data directory
+#[test]
+fn cli_data_dir() {
+    let temp_dir = TempDir::new().unwrap();
+    let data_dir = temp_dir.path().join("data");
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(["--engine", "kvs", "--addr", "127.0.0.1:4008", "--data-dir"])
+        .arg(&data_dir)
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+
+    assert_eq!(fs::read_to_string(data_dir.join(".config")).unwrap(), "kvs");
+    assert!(data_dir.join("logs").join("1.log").exists());
+    assert!(!temp_dir.path().join(".config").exists());
+
+    // The engine saved in the data directory is still checked
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    cmd.args(["--engine", "sled", "--addr", "127.0.0.1:4008", "--data-dir"])
+        .arg(&data_dir)
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+// Should refuse to start with an unreadable config file unless forced
+#[test]
+fn cli_corrupt_config() {
+    let temp_dir = TempDir::new().unwrap();
+    fs::write(temp_dir.path().join(".config"), "sle").unwrap();
+
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    cmd.args(["--engine", "kvs", "--addr", "127.0.0.1:4009"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("--force-engine"));
+    assert_eq!(fs::read_to_string(temp_dir.path().join(".config")).unwrap(), "sle");
+
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(["--engine", "kvs", "--addr", "127.0.0.1:4009", "--force-engine"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+
+    assert_eq!(fs::read_to_string(temp_dir.path().join(".config")).unwrap(), "kvs");
+}
+
 fn cli_access_server(engine: &str, addr: &str) {
     let (sender, receiver) = mpsc::sync_channel(0);
     let temp_dir = TempDir::new().unwrap();
     let mut server = Command::cargo_bin("kvs-server").unwrap();
     let mut child = server
-        .args(&["--engine", engine, "--addr", addr])
+        .args(["--engine", engine, "--addr", addr])
         .current_dir(&temp_dir)
         .spawn()
         .unwrap();
     let handle = thread::spawn(move || {
         let _ = receiver.recv(); // wait for main thread to finish
         child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait on server");
     });
     thread::sleep(Duration::from_secs(1));
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["--addr", addr, "set", "key1", "value1"])
+        .args(["--addr", addr, "set", "key1", "value1"])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -237,7 +464,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["--addr", addr, "get", "key1"])
+        .args(["--addr", addr, "get", "key1"])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -245,7 +472,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["--addr", addr, "set", "key1", "value2"])
+        .args(["--addr", addr, "set", "key1", "value2"])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -253,7 +480,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["--addr", addr, "get", "key1"])
+        .args(["--addr", addr, "get", "key1"])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -261,7 +488,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["--addr", addr, "get", "key2"])
+        .args(["--addr", addr, "get", "key2"])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -269,7 +496,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["--addr", addr, "rm", "key2"])
+        .args(["--addr", addr, "rm", "key2"])
         .current_dir(&temp_dir)
         .assert()
         .failure()
@@ -277,7 +504,144 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["--addr", addr, "set", "key2", "value3"])
+        .args(["--addr", addr, "exists", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("true\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "exists", "key2"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("false\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "len"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "ping"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(format!("{}\n", env!("CARGO_PKG_VERSION")));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "server-info"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains(format!("engine: {}\n", engine)))
+        .stdout(contains(format!("version: {}\n", env!("CARGO_PKG_VERSION"))))
+        .stdout(contains(format!("data directory: {}\n", temp_dir.path().canonicalize().unwrap().display())));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "stats"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("keys: 1\ntotal value bytes: 6\naverage value bytes: 6\nmax value bytes: 6\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "stat", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("value bytes: 6\n").and(contains("expires at").not()));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "keys", "--prefix", "key"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("key1\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "get-many", "key1", "missing", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value2\nKey not found\nvalue2\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "incr", "counter", "5"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("5\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "incr", "counter", "-7"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("-2\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "incr", "key1", "1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("not an integer"));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "rm", "counter"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "append", "log", "line1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("5\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "append", "log", ",line2"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("11\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "get", "log"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("line1,line2\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "remove-prefix", "lo"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("1\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", addr, "set", "key2", "value3"])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -285,7 +649,7 @@ fn cli_access_server(engine: &str, addr: &str) {
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["--addr", addr, "rm", "key1"])
+        .args(["--addr", addr, "rm", "key1"])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -298,26 +662,27 @@ fn cli_access_server(engine: &str, addr: &str) {
     let (sender, receiver) = mpsc::sync_channel(0);
     let mut server = Command::cargo_bin("kvs-server").unwrap();
     let mut child = server
-        .args(&["--engine", engine, "--addr", addr])
+        .args(["--engine", engine, "--addr", addr])
         .current_dir(&temp_dir)
         .spawn()
         .unwrap();
     let handle = thread::spawn(move || {
         let _ = receiver.recv(); // wait for main thread to finish
         child.kill().expect("server exited before killed");
+        child.wait().expect("failed to wait on server");
     });
     thread::sleep(Duration::from_secs(1));
 
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["--addr", addr, "get", "key2"])
+        .args(["--addr", addr, "get", "key2"])
         .current_dir(&temp_dir)
         .assert()
         .success()
         .stdout(contains("value3"));
     Command::cargo_bin("kvs-client")
         .unwrap()
-        .args(&["--addr", addr, "get", "key1"])
+        .args(["--addr", addr, "get", "key1"])
         .current_dir(&temp_dir)
         .assert()
         .success()
@@ -334,4 +699,298 @@ fn cli_access_server_kvs_engine() {
 #[test]
 fn cli_access_server_sled_engine() {
     cli_access_server("sled", "127.0.0.1:4005");
-}
\ No newline at end of file
+}
+#[test]
+fn cli_warmup_file() {
+    let temp_dir = TempDir::new().unwrap();
+    let stderr_path = temp_dir.path().join("stderr");
+    fs::write(temp_dir.path().join("warmup"), "key1\nkey2\n").unwrap();
+
+    // Store one of the listed keys
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(["--addr", "127.0.0.1:4006"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4006", "set", "key1", "value1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(["--addr", "127.0.0.1:4006", "--warmup-file", "warmup"])
+        .current_dir(&temp_dir)
+        .stderr(File::create(&stderr_path).unwrap())
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+
+    let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
+    assert!(content.contains("Warmed up 1 keys"));
+
+    // A missing warm-up file stops the server before it starts listening
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4007", "--warmup-file", "missing"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+}
+
+// `kvs-server self-test` should validate the data directory and exit without listening
+#[test]
+fn cli_self_test() {
+    for engine in ["kvs", "sled"] {
+        let temp_dir = TempDir::new().unwrap();
+        let stderr_path = temp_dir.path().join("stderr");
+
+        Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(["--engine", engine, "self-test", "--data-dir", "data"])
+            .current_dir(&temp_dir)
+            .stderr(File::create(&stderr_path).unwrap())
+            .assert()
+            .success();
+
+        let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
+        assert!(content.contains("Self-test of engine"));
+        assert!(content.contains("passed"));
+
+        // The self-test does not save the engine in the config file
+        assert!(!temp_dir.path().join(".config").exists());
+    }
+}
+
+// `kvs-server compaction-preview` should print the bytes a compaction would free and exit without listening
+#[test]
+fn cli_compaction_preview() {
+    let temp_dir = TempDir::new().unwrap();
+    let config = KvStoreConfig { log_dir: Some("logs".into()), ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config).unwrap();
+
+    for iter in 0..10 {
+        store.set("key1".to_owned(), format!("value{}", iter)).unwrap();
+    }
+    let preview = store.compaction_preview().unwrap();
+    drop(store);
+
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["compaction-preview"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains(format!("Reclaimable: {} bytes", preview.reclaimable_bytes)));
+
+    // The preview does not save the engine in the config file
+    assert!(!temp_dir.path().join(".config").exists());
+}
+
+// A write failing part way through, here because the log file reached the file size limit,
+// should leave nothing behind, so later writes are kept and the store loads them back
+#[cfg(unix)]
+#[test]
+fn cli_failed_write_is_rolled_back() {
+    let temp_dir = TempDir::new().unwrap();
+    let server = assert_cmd::cargo::cargo_bin("kvs-server");
+    let client = |args: &[&str]| {
+        let mut cmd = Command::cargo_bin("kvs-client").unwrap();
+        cmd.args(["--addr", "127.0.0.1:4016"]).args(args).current_dir(&temp_dir);
+        cmd
+    };
+
+    // Writes past the limit fail with an error instead of a signal
+    let mut child = Command::new("sh")
+        .args(["-c", "trap '' XFSZ; ulimit -f 1; exec \"$0\" --addr 127.0.0.1:4016"])
+        .arg(&server)
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    client(&["set", "key1", "value1"]).assert().success();
+    client(&["set", "key2", &"x".repeat(4000)]).assert().failure();
+    client(&["set", "key3", "value3"]).assert().success();
+    client(&["get", "key2"]).assert().success().stdout("Key not found\n");
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+
+    let mut child = Command::new(&server)
+        .args(["--addr", "127.0.0.1:4016"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    client(&["get", "key1"]).assert().success().stdout("value1\n");
+    client(&["get", "key3"]).assert().success().stdout("value3\n");
+    client(&["get", "key2"]).assert().success().stdout("Key not found\n");
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+}
+
+// `kvs-server` should listen on every address given with a repeated `--addr`
+#[test]
+fn cli_multiple_addresses() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(["--addr", "127.0.0.1:4012", "--addr", "127.0.0.1:4013"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4012", "set", "key1", "value1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4013", "get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    // An address already listened on stops the server and is named in the error
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4014", "--addr", "127.0.0.1:4013", "--data-dir", "other"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("127.0.0.1:4013"));
+
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+}
+
+// `kvs-client --output json` should print results as JSON and exit codes should tell errors apart
+#[test]
+fn cli_json_output_and_exit_codes() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(["--addr", "127.0.0.1:4015"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4015", "--output", "json", "set", "key1", "value1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(is_empty());
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4015", "--output", "json", "get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("\"value1\"\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4015", "--output", "json", "get", "key2"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("null\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4015", "--output", "json", "get-many", "key1", "key2"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("[\"value1\",null]\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4015", "--output", "yaml", "get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .failure();
+
+    // Removing a missing key exits with `ExitStatus::KeyNotFound`
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4015", "rm", "key2"])
+        .current_dir(&temp_dir)
+        .assert()
+        .code(ExitStatus::KeyNotFound.code() as i32)
+        .stdout(is_empty());
+
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+
+    // A server that cannot be reached exits with `ExitStatus::Unavailable`
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4015", "get", "key1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .code(ExitStatus::Unavailable.code() as i32);
+}
+
+// `kvs-client get <KEY> --out <FILE>` should write the value to the file instead of printing it
+#[test]
+fn cli_get_to_file() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let mut cmd = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = cmd
+        .args(["--addr", "127.0.0.1:4019"])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4019", "set", "key1", "value1"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success();
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4019", "get", "key1", "--out", "value.txt"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(is_empty());
+    assert_eq!(fs::read_to_string(temp_dir.path().join("value.txt")).unwrap(), "value1");
+
+    // A missing key leaves no file behind
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["--addr", "127.0.0.1:4019", "get", "key2", "--out", "missing.txt"])
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout("Key not found\n");
+    assert!(!temp_dir.path().join("missing.txt").exists());
+
+    child.kill().expect("server exited before killed");
+    child.wait().expect("failed to wait on server");
+}
@@ -0,0 +1,45 @@
+use kvs::{read_frame, write_frame, Command, KvsError, Result, MAX_FRAME_SIZE};
+use std::io::Cursor;
+
+// Should read back the written frames in order and stop at the end of the stream
+#[test]
+fn frame_round_trip() -> Result<()> {
+    let mut buf = Vec::new();
+    let written = write_frame(&mut buf, &Command::Get { key: "key1".to_owned(), out: None })?;
+    assert_eq!(written, buf.len());
+    write_frame(&mut buf, &Command::Remove { key: "key2".to_owned() })?;
+
+    // Every frame starts with its big-endian payload length
+    let len = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+    assert_eq!(len, serde_json::to_vec(&Command::Get { key: "key1".to_owned(), out: None })?.len());
+
+    let mut reader = Cursor::new(buf);
+    assert_eq!(read_frame(&mut reader)?, Some(Command::Get { key: "key1".to_owned(), out: None }));
+    assert_eq!(read_frame(&mut reader)?, Some(Command::Remove { key: "key2".to_owned() }));
+    assert_eq!(read_frame::<_, Command>(&mut reader)?, None);
+
+    Ok(())
+}
+
+// Should reject frames bigger than the maximum size and truncated frames
+#[test]
+fn frame_errors() -> Result<()> {
+    let mut reader = Cursor::new((MAX_FRAME_SIZE + 1).to_be_bytes().to_vec());
+    assert!(matches!(read_frame::<_, Command>(&mut reader), Err(KvsError::RequestError(_))));
+
+    let value = "v".repeat(MAX_FRAME_SIZE as usize);
+    let mut buf = Vec::new();
+    assert!(matches!(
+        write_frame(&mut buf, &Command::Set { key: "key1".to_owned(), value, expires_at: None }),
+        Err(KvsError::RequestError(_))
+    ));
+    assert!(buf.is_empty());
+
+    // The payload ends before the announced length
+    let mut buf = Vec::new();
+    write_frame(&mut buf, &Command::Get { key: "key1".to_owned(), out: None })?;
+    buf.pop();
+    assert!(matches!(read_frame::<_, Command>(&mut Cursor::new(buf)), Err(KvsError::IOError(_))));
+
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use kvs::{KvStore, KvsEngine, Result};
+use kvs::{export, import, BufReaderWithPos, CacheSize, ChangeEvent, Codec, Command, CommandResponse, CompactionMode, CompactionOrder, CompactionPreview, ErrorKind, IndexKind, KvStore, KvStoreConfig, KeyStat, KvsEngine, KvsError, LogFileNames, LogFormat, Result, SizeStats, SledConfig, SledKvsEngine, WriteBatch};
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
@@ -6,7 +6,7 @@ use walkdir::WalkDir;
 #[test]
 fn get_stored_value() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     store.set("key2".to_owned(), "value2".to_owned())?;
@@ -16,18 +16,33 @@ fn get_stored_value() -> Result<()> {
 
     // Open from disk again and check persistent data
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
     assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
 
     Ok(())
 }
 
+// Should fall back to the default value for a missing key without storing it
+#[test]
+fn get_or_default() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    assert_eq!(store.get_or("key1".to_owned(), "default".to_owned())?, "value1");
+    assert_eq!(store.get_or("key2".to_owned(), "default".to_owned())?, "default");
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    Ok(())
+}
+
 // Should overwrite existent value
 #[test]
 fn overwrite_value() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
@@ -36,7 +51,7 @@ fn overwrite_value() -> Result<()> {
 
     // Open from disk again and check persistent data
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
     store.set("key1".to_owned(), "value3".to_owned())?;
     assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
@@ -48,14 +63,14 @@ fn overwrite_value() -> Result<()> {
 #[test]
 fn get_non_existent_value() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
 
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert_eq!(store.get("key2".to_owned())?, None);
 
     // Open from disk again and check persistent data
     drop(store);
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     assert_eq!(store.get("key2".to_owned())?, None);
 
     Ok(())
@@ -64,7 +79,7 @@ fn get_non_existent_value() -> Result<()> {
 #[test]
 fn remove_non_existent_key() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     assert!(store.remove("key1".to_owned()).is_err());
     Ok(())
 }
@@ -72,7 +87,7 @@ fn remove_non_existent_key() -> Result<()> {
 #[test]
 fn remove_key() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
     store.set("key1".to_owned(), "value1".to_owned())?;
     assert!(store.remove("key1".to_owned()).is_ok());
     assert_eq!(store.get("key1".to_owned())?, None);
@@ -84,7 +99,7 @@ fn remove_key() -> Result<()> {
 #[test]
 fn compaction() -> Result<()> {
     let temp_dir = TempDir::new().expect("unable to create temporary working directory");
-    let mut store = KvStore::open(temp_dir.path())?;
+    let store = KvStore::open(temp_dir.path())?;
 
     let dir_size = || {
         let entries = WalkDir::new(temp_dir.path()).into_iter();
@@ -114,7 +129,7 @@ fn compaction() -> Result<()> {
 
         drop(store);
         // reopen and check content
-        let mut store = KvStore::open(temp_dir.path())?;
+        let store = KvStore::open(temp_dir.path())?;
         for key_id in 0..1000 {
             let key = format!("key{}", key_id);
             assert_eq!(store.get(key)?, Some(format!("{}", iter)));
@@ -123,4 +138,2210 @@ fn compaction() -> Result<()> {
     }
 
     panic!("No compaction detected");
-}
\ No newline at end of file
+}
+// Should apply every command of a transaction
+#[test]
+fn transaction_applies_all_commands() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.transaction(vec![
+        Command::Set { key: "key2".to_owned(), value: "value1".to_owned(), expires_at: None },
+        Command::Remove { key: "key1".to_owned() },
+    ])?;
+
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value1".to_owned()));
+
+    // Open from disk again and check persistent data
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value1".to_owned()));
+
+    Ok(())
+}
+
+// Should apply none of the commands if one of them fails
+#[test]
+fn transaction_is_all_or_nothing() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let result = store.transaction(vec![
+        Command::Set { key: "key1".to_owned(), value: "value1".to_owned(), expires_at: None },
+        Command::Remove { key: "key2".to_owned() },
+    ]);
+    assert!(matches!(result, Err(KvsError::KeyNotFound)));
+    assert_eq!(store.get("key1".to_owned())?, None);
+
+    // A key set earlier in the same transaction can be removed
+    store.transaction(vec![
+        Command::Set { key: "key2".to_owned(), value: "value2".to_owned(), expires_at: None },
+        Command::Remove { key: "key2".to_owned() },
+    ])?;
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    // Open from disk again and check persistent data
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should give the command type, key and outcome recorded in the spans of served commands
+#[test]
+fn command_span_fields() {
+    let set = Command::Set { key: "key1".to_owned(), value: "value1".to_owned(), expires_at: None };
+    assert_eq!((set.name(), set.key()), ("set", Some("key1")));
+
+    let rename = Command::Rename { from: "key1".to_owned(), to: "key2".to_owned() };
+    assert_eq!((rename.name(), rename.key()), ("rename", Some("key1")));
+
+    let get_many = Command::GetMany { keys: vec!["key2".to_owned(), "key3".to_owned()] };
+    assert_eq!((get_many.name(), get_many.key()), ("get_many", Some("key2")));
+
+    assert_eq!((Command::Len.name(), Command::Len.key()), ("len", None));
+    assert_eq!((Command::Begin.name(), Command::Begin.key()), ("begin", None));
+
+    // The outcome is the name of the response to the command
+    assert_eq!(CommandResponse::Value("value1".to_owned()).name(), "value");
+    assert_eq!(CommandResponse::KeyNotFound.name(), "key_not_found");
+    assert_eq!(CommandResponse::Committed(2).name(), "committed");
+    assert_eq!(CommandResponse::Error(ErrorKind::of(&KvsError::KeyNotFound), "Remove command error".to_owned()).name(), "error");
+}
+
+// Should apply every command of a write batch
+#[test]
+fn commit_write_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    let mut batch = WriteBatch::new();
+    assert!(batch.is_empty());
+    batch.set("key2".to_owned(), "value2".to_owned());
+    batch.set("key3".to_owned(), "value3".to_owned());
+    batch.remove("key1".to_owned());
+    assert_eq!(batch.len(), 3);
+    store.commit_batch(batch)?;
+
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+
+    // Open from disk again and check persistent data
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+
+    Ok(())
+}
+
+// Should apply none of the commands of a batch whose commit marker never reached the log
+#[test]
+fn uncommitted_batch_is_discarded() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    let mut batch = WriteBatch::new();
+    batch.set("key2".to_owned(), "value2".to_owned());
+    batch.remove("key1".to_owned());
+    store.commit_batch(batch)?;
+    drop(store);
+
+    // Simulate a crash while the commit marker, which is the last record, was being written
+    let path = temp_dir.path().join("1.log");
+    let file = std::fs::OpenOptions::new().write(true).open(&path).expect("unable to open log file");
+    let len = file.metadata().expect("unable to read log file metadata").len();
+    file.set_len(len - 1).expect("unable to truncate log file");
+    drop(file);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    // The store keeps working after the discarded batch
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+// Should send an event for every change to a subscribed key
+#[test]
+fn subscribe_to_key_changes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let receiver = store.subscribe("key1".to_owned());
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.clone().remove("key1".to_owned())?;
+    store.rename("key2".to_owned(), "key1".to_owned())?;
+    store.clear()?;
+
+    let events: Vec<ChangeEvent> = receiver.try_iter().collect();
+    assert_eq!(events, vec![
+        ChangeEvent::Set { key: "key1".to_owned(), value: "value1".to_owned() },
+        ChangeEvent::Remove { key: "key1".to_owned() },
+        ChangeEvent::Set { key: "key1".to_owned(), value: "value2".to_owned() },
+        ChangeEvent::Remove { key: "key1".to_owned() },
+    ]);
+
+    // Failed writes send nothing
+    assert!(store.remove("key1".to_owned()).is_err());
+    assert!(receiver.try_recv().is_err());
+
+    // Writes keep working once the receiver is dropped
+    drop(receiver);
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    Ok(())
+}
+
+// Should only list log files that are no longer written to
+#[test]
+fn log_segments_since_generation() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(store.generation(), 0);
+    assert!(store.log_segments_since(0).is_empty());
+
+    // Reopening starts a new log file and the previous one becomes immutable
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.generation(), 1);
+    assert_eq!(store.log_segments_since(0), vec![(1, temp_dir.path().join("1.log"))]);
+
+    let gen = store.generation();
+    assert!(store.log_segments_since(gen).is_empty());
+
+    // Compaction writes the live commands to a new immutable log file
+    store.compact()?;
+    let segments = store.log_segments_since(gen);
+    assert_eq!(segments.len(), 1);
+    assert!(segments[0].1.exists());
+
+    Ok(())
+}
+
+// Should refuse to compact without creating files when the disk is too full
+#[test]
+fn compaction_insufficient_space() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    store.set_min_free_space(u64::MAX);
+    let log_files = || std::fs::read_dir(temp_dir.path()).unwrap().count();
+    let before = log_files();
+
+    assert!(matches!(store.compact(), Err(KvsError::InsufficientSpace { .. })));
+    assert_eq!(log_files(), before);
+
+    // Writes keep working even though automatic compaction cannot run
+    for iter in 0..40000 {
+        store.set("key1".to_owned(), format!("value{}", iter))?;
+    }
+    assert_eq!(store.get("key1".to_owned())?, Some("value39999".to_owned()));
+
+    store.set_min_free_space(0);
+    store.compact()?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value39999".to_owned()));
+
+    Ok(())
+}
+
+// Should keep the original write order in the compaction file when asked to
+#[test]
+fn compaction_write_order() -> Result<()> {
+    let compacted_keys = |order: CompactionOrder| -> Result<Vec<String>> {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let store = KvStore::open(temp_dir.path())?;
+        store.set_compaction_order(order);
+
+        store.set("key3".to_owned(), "value1".to_owned())?;
+        store.set("key1".to_owned(), "value1".to_owned())?;
+        store.set("key2".to_owned(), "value1".to_owned())?;
+        store.set("key1".to_owned(), "value2".to_owned())?;
+
+        let gen = store.generation();
+        store.compact()?;
+        let (_, path) = store.log_segments_since(gen).remove(0);
+
+        // Every record is a big-endian length and a checksum followed by the command
+        let bytes = std::fs::read(path)?;
+        let mut keys = Vec::new();
+        let mut pos = 0;
+
+        while pos < bytes.len() {
+            let len = u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]) as usize;
+            let cmd: Command = serde_json::from_slice(&bytes[pos + 8..pos + 8 + len])?;
+            keys.push(cmd.key().unwrap().to_owned());
+            pos += 8 + len;
+        }
+
+        Ok(keys)
+    };
+
+    assert_eq!(compacted_keys(CompactionOrder::KeySorted)?, vec!["key1", "key2", "key3"]);
+    assert_eq!(compacted_keys(CompactionOrder::WriteOrder)?, vec!["key3", "key2", "key1"]);
+
+    Ok(())
+}
+
+// Should never compact automatically in manual mode
+#[test]
+fn manual_compaction_mode() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set_compaction_mode(CompactionMode::Manual);
+
+    for iter in 0..40000 {
+        store.set("key1".to_owned(), format!("value{}", iter))?;
+    }
+
+    // All writes are still in the first log file
+    assert_eq!(store.generation(), 0);
+
+    store.compact()?;
+    assert_eq!(store.log_segments_since(0).len(), 1);
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value39999".to_owned()));
+
+    Ok(())
+}
+
+// Should compact on a background thread while writes keep going
+#[test]
+fn background_compaction_mode() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { compaction_threshold: 4 * 1024, ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    store.set_compaction_mode(CompactionMode::Background);
+
+    // Two threads overwrite their own keys continuously while compactions run
+    let writers: Vec<_> = (0..2)
+        .map(|thread_id| {
+            let store = store.clone();
+            std::thread::spawn(move || -> Result<()> {
+                for iter in 0..2000 {
+                    store.set(format!("key{}-{}", thread_id, iter % 10), format!("value{}", iter))?;
+                }
+                Ok(())
+            })
+        })
+        .collect();
+
+    for writer in writers {
+        writer.join().expect("writer thread panicked")?;
+    }
+
+    // The background thread stops once nothing is left to compact
+    let start = std::time::Instant::now();
+    while store.needs_compaction() {
+        assert!(start.elapsed() < std::time::Duration::from_secs(10), "background compaction did not finish");
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+
+    // Without compaction the log would hold every one of the 4000 writes
+    assert!(store.generation() > 0);
+    assert!(store.stats().uncompacted <= 4 * 1024);
+    assert_eq!(store.len()?, 20);
+
+    // Newer writes were not overwritten by the copies made by compaction
+    for thread_id in 0..2 {
+        for key_id in 0..10 {
+            assert_eq!(
+                store.get(format!("key{}-{}", thread_id, key_id))?,
+                Some(format!("value{}", 1990 + key_id))
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Should report the stale bytes and tell when compaction is due
+#[test]
+fn compaction_stats() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { compaction_threshold: 100, ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    store.set_compaction_mode(CompactionMode::Manual);
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+
+    let stats = store.stats();
+    assert_eq!(stats.uncompacted, 0);
+    assert_eq!(stats.live_keys, 2);
+    assert_eq!(stats.log_files, 1);
+    assert_eq!(stats.compaction_threshold, 100);
+    assert!(!store.needs_compaction());
+
+    for iter in 0..10 {
+        store.set("key1".to_owned(), format!("value{}", iter))?;
+    }
+
+    assert!(store.stats().uncompacted > 100);
+    assert!(store.needs_compaction());
+
+    store.compact()?;
+    let stats = store.stats();
+    assert_eq!(stats.uncompacted, 0);
+    assert_eq!(stats.live_keys, 2);
+    assert_eq!(stats.log_files, 2);
+    assert!(!store.needs_compaction());
+
+    Ok(())
+}
+
+// Should estimate the bytes reclaimed by compaction without writing anything
+#[test]
+fn compaction_preview() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set_compaction_mode(CompactionMode::Manual);
+    let log_size = || std::fs::metadata(temp_dir.path().join("1.log")).unwrap().len();
+
+    for iter in 0..10 {
+        store.set("key1".to_owned(), format!("value{}", iter))?;
+        store.set("key2".to_owned(), format!("value{}", iter))?;
+    }
+    store.remove("key2".to_owned())?;
+
+    let preview = store.compaction_preview()?;
+    assert_eq!(preview.total_bytes, log_size());
+    assert_eq!(preview.reclaimable_bytes, store.stats().uncompacted);
+    assert_eq!(preview.live_bytes + preview.reclaimable_bytes, preview.total_bytes);
+
+    // Nothing was written to the log
+    assert_eq!(log_size(), preview.total_bytes);
+    assert_eq!(store.stats().log_files, 1);
+
+    // Compaction leaves only the live bytes
+    store.compact()?;
+    assert_eq!(store.compaction_preview()?, CompactionPreview {
+        total_bytes: preview.live_bytes,
+        live_bytes: preview.live_bytes,
+        reclaimable_bytes: 0,
+    });
+
+    Ok(())
+}
+
+// Should count every stale byte exactly once across repeated sets and removes of a key
+#[test]
+fn uncompacted_bytes_after_set_remove_sequences() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set_compaction_mode(CompactionMode::Manual);
+    let log_size = || std::fs::metadata(temp_dir.path().join("1.log")).unwrap().len();
+
+    // Once the key is removed again, every byte in the log is stale
+    for iter in 0..3 {
+        store.set("key".to_owned(), format!("value{}", iter))?;
+        store.remove("key".to_owned())?;
+        assert_eq!(store.stats().uncompacted, log_size());
+    }
+
+    // Only the last Set command is live
+    store.set("key".to_owned(), "value".to_owned())?;
+    let stale = store.stats().uncompacted;
+    store.set("key".to_owned(), "last".to_owned())?;
+    assert!(store.stats().uncompacted > stale);
+    let live = log_size() - store.stats().uncompacted;
+
+    // Loading the log counts the same stale bytes
+    let uncompacted = store.stats().uncompacted;
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.stats().uncompacted, uncompacted);
+    store.set_compaction_mode(CompactionMode::Manual);
+
+    // The compaction threshold is crossed by the write that reaches it
+    drop(store);
+    let config = KvStoreConfig { compaction_threshold: uncompacted + live, ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    store.set_compaction_mode(CompactionMode::Manual);
+    assert!(!store.needs_compaction());
+    store.set("key".to_owned(), "last".to_owned())?;
+    assert_eq!(store.stats().uncompacted, uncompacted + live);
+    assert!(!store.needs_compaction());
+    store.remove("key".to_owned())?;
+    assert!(store.needs_compaction());
+
+    Ok(())
+}
+
+// Should not keep counting the stale bytes deleted by an incremental compaction
+#[test]
+fn uncompacted_bytes_during_incremental_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set_compaction_mode(CompactionMode::Manual);
+
+    for key_id in 0..10 {
+        store.set(format!("key{}", key_id), "value".to_owned())?;
+    }
+    store.set("key0".to_owned(), "stale".to_owned())?;
+
+    // Keys that were not copied yet are overwritten and removed in the middle of the compaction
+    assert!(store.compact_step(1)?);
+    store.set("key8".to_owned(), "new value".to_owned())?;
+    store.set("key8".to_owned(), "newer value".to_owned())?;
+    store.remove("key9".to_owned())?;
+    while store.compact_step(1)? {}
+
+    // Only the bytes written since the compaction started are left, like when loading the log
+    let uncompacted = store.stats().uncompacted;
+    assert!(uncompacted > 0);
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.stats().uncompacted, uncompacted);
+    assert_eq!(store.get("key8".to_owned())?, Some("newer value".to_owned()));
+    assert_eq!(store.get("key9".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should move a value to another key
+#[test]
+fn rename_key() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.rename("key1".to_owned(), "key2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value1".to_owned()));
+
+    // Renaming a key to itself keeps it
+    store.rename("key2".to_owned(), "key2".to_owned())?;
+    assert_eq!(store.get("key2".to_owned())?, Some("value1".to_owned()));
+
+    assert!(matches!(
+        store.rename("key1".to_owned(), "key3".to_owned()),
+        Err(KvsError::KeyNotFound)
+    ));
+    assert_eq!(store.get("key3".to_owned())?, None);
+
+    // Open from disk again and check persistent data
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value1".to_owned()));
+
+    Ok(())
+}
+
+// Should keep the latest command of each key when loading several log files
+#[test]
+fn open_multiple_log_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+
+    // Every reopen starts a new log file
+    {
+        let store = KvStore::open(temp_dir.path())?;
+        store.set("key1".to_owned(), "value1".to_owned())?;
+        store.set("key2".to_owned(), "value1".to_owned())?;
+        store.set("key3".to_owned(), "value1".to_owned())?;
+    }
+    {
+        let store = KvStore::open(temp_dir.path())?;
+        store.set("key1".to_owned(), "value2".to_owned())?;
+        store.remove("key2".to_owned())?;
+    }
+    {
+        let store = KvStore::open(temp_dir.path())?;
+        store.set("key2".to_owned(), "value3".to_owned())?;
+        store.remove("key3".to_owned())?;
+        store.set("key3".to_owned(), "value3".to_owned())?;
+        store.remove("key1".to_owned())?;
+    }
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    assert!(store.remove("key1".to_owned()).is_err());
+
+    Ok(())
+}
+
+// Should keep the log files in the configured subdirectory
+#[test]
+fn open_with_log_dir() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { log_dir: Some("logs".into()), ..KvStoreConfig::default() };
+
+    {
+        let store = KvStore::open_with_options(temp_dir.path(), config.clone())?;
+        store.set("key1".to_owned(), "value1".to_owned())?;
+    }
+
+    assert!(temp_dir.path().join("logs").join("1.log").exists());
+    assert!(!temp_dir.path().join("1.log").exists());
+
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    Ok(())
+}
+
+// Should fail to open when the newest log file uses the biggest possible id
+#[test]
+fn open_log_id_exhausted() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    std::fs::File::create(temp_dir.path().join(format!("{}.log", u64::MAX)))?;
+
+    assert!(matches!(KvStore::open(temp_dir.path()), Err(KvsError::LogIdExhausted)));
+
+    Ok(())
+}
+
+// Should refuse to compact without creating files when log file ids run out
+#[test]
+fn compaction_log_id_exhausted() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    std::fs::File::create(temp_dir.path().join(format!("{}.log", u64::MAX - 2)))?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(store.generation(), u64::MAX - 2);
+
+    let log_files = || std::fs::read_dir(temp_dir.path()).unwrap().count();
+    let before = log_files();
+
+    assert!(matches!(store.compact(), Err(KvsError::LogIdExhausted)));
+    assert_eq!(log_files(), before);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+// Should iterate live entries in the order they were last written
+#[test]
+fn iter_by_seq_write_order() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set("key3".to_owned(), "value1".to_owned())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value1".to_owned())?;
+    store.set("key4".to_owned(), "value1".to_owned())?;
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    store.remove("key4".to_owned())?;
+
+    let expected = vec![
+        ("key3".to_owned(), "value1".to_owned()),
+        ("key2".to_owned(), "value1".to_owned()),
+        ("key1".to_owned(), "value2".to_owned()),
+    ];
+    assert_eq!(store.iter_by_seq().collect::<Result<Vec<_>>>()?, expected);
+
+    // Open from disk again and check persistent data
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.iter_by_seq().collect::<Result<Vec<_>>>()?, expected);
+
+    Ok(())
+}
+
+// Should compact once the configured threshold is crossed and reject a threshold of 0
+#[test]
+fn configured_compaction_threshold() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { compaction_threshold: 100, ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+
+    for iter in 0..10 {
+        store.set("key1".to_owned(), format!("value{}", iter))?;
+    }
+
+    // Compaction moved the writes out of the first log file
+    assert!(store.generation() > 0);
+    assert_eq!(store.get("key1".to_owned())?, Some("value9".to_owned()));
+
+    let config = KvStoreConfig { compaction_threshold: 0, ..KvStoreConfig::default() };
+    assert!(matches!(
+        KvStore::open_with_options(temp_dir.path(), config),
+        Err(KvsError::InvalidCompactionThreshold)
+    ));
+
+    Ok(())
+}
+
+// Should store values that are not valid UTF-8 next to string values
+#[test]
+fn binary_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let bytes = vec![0, 159, 146, 150, 255];
+
+    store.set_bytes("key1".to_owned(), bytes.clone())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+
+    assert_eq!(store.get_bytes("key1".to_owned())?, Some(bytes.clone()));
+    assert_eq!(store.get_bytes("key2".to_owned())?, Some(b"value2".to_vec()));
+    assert_eq!(store.get_bytes("key3".to_owned())?, None);
+    assert!(matches!(store.get("key1".to_owned()), Err(KvsError::Utf8Error(_))));
+
+    // Valid UTF-8 bytes can be read back as a string
+    store.set_bytes("key3".to_owned(), b"value3".to_vec())?;
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+
+    // Open from disk again and check persistent data, also after compaction
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get_bytes("key1".to_owned())?, Some(bytes.clone()));
+
+    store.compact()?;
+    assert_eq!(store.get_bytes("key1".to_owned())?, Some(bytes));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+// Should check whether keys exist
+#[test]
+fn contains_key() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.remove("key2".to_owned())?;
+
+    assert!(store.contains_key("key1".to_owned())?);
+    assert!(!store.contains_key("key2".to_owned())?);
+    assert!(!store.contains_key("key3".to_owned())?);
+    assert_eq!(store.len()?, 1);
+
+    store.remove("key1".to_owned())?;
+    assert!(store.is_empty()?);
+
+    Ok(())
+}
+
+// Should scan the live entries of a key range in key order
+#[test]
+fn scan_range() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set("b2".to_owned(), "value1".to_owned())?;
+    store.set("a1".to_owned(), "value1".to_owned())?;
+    store.set("b1".to_owned(), "value1".to_owned())?;
+    store.set("b3".to_owned(), "value1".to_owned())?;
+    store.set("c1".to_owned(), "value1".to_owned())?;
+    store.set("b1".to_owned(), "value2".to_owned())?;
+    store.remove("b3".to_owned())?;
+
+    // Prefix scan of the "b" keys
+    let entries = store.scan("b".to_owned().."c".to_owned()).collect::<Result<Vec<_>>>()?;
+    assert_eq!(entries, vec![
+        ("b1".to_owned(), "value2".to_owned()),
+        ("b2".to_owned(), "value1".to_owned()),
+    ]);
+
+    assert_eq!(store.scan("b2".to_owned()..).count(), 2);
+    assert_eq!(store.scan(..).count(), 4);
+    assert_eq!(store.scan("d".to_owned()..).count(), 0);
+
+    Ok(())
+}
+
+// Should iterate all keys and values in key order
+#[test]
+fn iter_all_entries() -> Result<()> {
+    let store = KvStore::new_in_memory()?;
+
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    store.remove("key2".to_owned())?;
+
+    assert_eq!(store.keys().collect::<Vec<_>>(), vec!["key1", "key3"]);
+    assert_eq!(store.iter().collect::<Result<Vec<_>>>()?, vec![
+        ("key1".to_owned(), "value1".to_owned()),
+        ("key3".to_owned(), "value3".to_owned()),
+    ]);
+
+    // Keys removed after the iterator was created are skipped
+    let mut entries = store.iter();
+    store.remove("key3".to_owned())?;
+    assert_eq!(entries.next().transpose()?, Some(("key1".to_owned(), "value1".to_owned())));
+    assert!(entries.next().is_none());
+
+    Ok(())
+}
+
+// Should navigate the live keys in key order
+#[test]
+fn ordered_key_navigation() -> Result<()> {
+    let store = KvStore::new_in_memory()?;
+    assert_eq!(store.first_key()?, None);
+    assert_eq!(store.last_key()?, None);
+
+    for key in &["b", "d", "a", "c"] {
+        store.set(key.to_string(), "value".to_owned())?;
+    }
+    store.remove("c".to_owned())?;
+
+    assert_eq!(store.first_key()?, Some("a".to_owned()));
+    assert_eq!(store.last_key()?, Some("d".to_owned()));
+    assert_eq!(store.next_key("a")?, Some("b".to_owned()));
+    assert_eq!(store.next_key("b")?, Some("d".to_owned()));
+    assert_eq!(store.next_key("bb")?, Some("d".to_owned()));
+    assert_eq!(store.next_key("d")?, None);
+
+    // Paging through the keys sees the keys set in the meantime
+    let mut keys = vec![store.first_key()?.unwrap()];
+    store.set("e".to_owned(), "value".to_owned())?;
+    while let Some(key) = store.next_key(keys.last().unwrap())? {
+        keys.push(key);
+    }
+    assert_eq!(keys, vec!["a", "b", "d", "e"]);
+
+    Ok(())
+}
+
+// Should serve point lookups and sorted key listings from a hash index, but no scans
+#[test]
+fn hash_index() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { index: IndexKind::Hash, ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config.clone())?;
+
+    for key in &["b2", "a1", "b1", "c1", "b3"] {
+        store.set(key.to_string(), format!("value of {}", key))?;
+    }
+    store.remove("b3".to_owned())?;
+    assert_eq!(store.get("b1".to_owned())?, Some("value of b1".to_owned()));
+    assert_eq!(store.get("b3".to_owned())?, None);
+
+    // Listing keys sorts them, while ordered navigation is unsupported
+    assert_eq!(store.keys().collect::<Vec<_>>(), vec!["a1", "b1", "b2", "c1"]);
+    assert_eq!(store.keys_page("b".to_owned(), Some("b1".to_owned()), 10)?, vec!["b2"]);
+    assert_eq!(store.iter().count(), 4);
+    assert!(matches!(store.scan(..).collect::<Result<Vec<_>>>(), Err(KvsError::Unsupported(_))));
+    assert!(matches!(store.first_key(), Err(KvsError::Unsupported(_))));
+    assert!(matches!(store.next_key("a1"), Err(KvsError::Unsupported(_))));
+
+    // Compaction copies the keys sorted and reopening rebuilds the same index
+    store.compact()?;
+    assert_eq!(store.remove_prefix("b".to_owned())?, 2);
+    drop(store);
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    assert_eq!(store.keys().collect::<Vec<_>>(), vec!["a1", "c1"]);
+    assert_eq!(store.get("c1".to_owned())?, Some("value of c1".to_owned()));
+
+    Ok(())
+}
+
+// Should keep writes buffered until flushed when not syncing on every write
+#[test]
+fn buffered_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { sync_on_write: false, ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    let log_size = || std::fs::metadata(temp_dir.path().join("1.log")).unwrap().len();
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    assert_eq!(log_size(), 0);
+
+    store.flush()?;
+    let flushed_size = log_size();
+    assert!(flushed_size > 0);
+
+    // Reading flushes buffered writes first
+    store.set("key1".to_owned(), "value3".to_owned())?;
+    assert_eq!(log_size(), flushed_size);
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert!(log_size() > flushed_size);
+
+    // Dropping the store flushes the remaining writes
+    store.remove("key2".to_owned())?;
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should return the replaced and removed values
+#[test]
+fn set_and_take_return_previous_value() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    assert_eq!(store.set_and_get_previous("key1".to_owned(), "value1".to_owned())?, None);
+    assert_eq!(
+        store.set_and_get_previous("key1".to_owned(), "value2".to_owned())?,
+        Some("value1".to_owned())
+    );
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+
+    assert_eq!(store.take("key1".to_owned())?, "value2".to_owned());
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert!(matches!(store.take("key1".to_owned()), Err(KvsError::KeyNotFound)));
+
+    Ok(())
+}
+
+// Should share one store between threads through its clones
+#[test]
+fn concurrent_clones() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    let handles: Vec<_> = (0..8)
+        .map(|thread_id| {
+            let store = store.clone();
+
+            std::thread::spawn(move || -> Result<()> {
+                for iter in 0..200 {
+                    let key = format!("key{}_{}", thread_id, iter % 20);
+                    store.set(key.clone(), format!("value{}", iter))?;
+                    assert_eq!(store.get(key)?, Some(format!("value{}", iter)));
+                }
+
+                Ok(())
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().expect("thread panicked")?;
+    }
+
+    // Every thread's last writes are visible, also after reopening
+    assert_eq!(store.get("key3_19".to_owned())?, Some("value199".to_owned()));
+    assert_eq!(store.scan(..).count(), 8 * 20);
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key7_0".to_owned())?, Some("value180".to_owned()));
+
+    Ok(())
+}
+
+// Should treat expired keys as removed, also after reopening and compacting
+#[test]
+fn keys_expire_after_ttl() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let ttl = std::time::Duration::from_millis(200);
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set_with_ttl("key1".to_owned(), "value2".to_owned(), ttl)?;
+    store.set_with_ttl("key2".to_owned(), "value2".to_owned(), ttl)?;
+    store.set_with_ttl("key3".to_owned(), "value3".to_owned(), std::time::Duration::from_secs(3600))?;
+    store.set_with_ttl("key4".to_owned(), "value4".to_owned(), ttl)?;
+    store.set("key4".to_owned(), "value5".to_owned())?;
+
+    assert_eq!(store.get("key1".to_owned())?, Some("value2".to_owned()));
+    assert!(store.contains_key("key2".to_owned())?);
+
+    std::thread::sleep(ttl);
+
+    // The expired value hides the older value of the key
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert!(!store.contains_key("key2".to_owned())?);
+    assert!(matches!(store.remove("key2".to_owned()), Err(KvsError::KeyNotFound)));
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(store.get("key4".to_owned())?, Some("value5".to_owned()));
+
+    // Expired keys do not come back when the log is loaded again
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.scan(..).count(), 2);
+
+    // Compaction purges expired keys and keeps the expiry of the others
+    let gen = store.generation();
+    store.compact()?;
+    let (_, path) = store.log_segments_since(gen).remove(0);
+    let compacted = String::from_utf8_lossy(&std::fs::read(path)?).into_owned();
+    assert!(!compacted.contains("key1") && !compacted.contains("key2"));
+    assert!(compacted.contains("expires_at"));
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+
+    // Set commands without an expiry keep the format of logs written before expiries existed
+    let old_format = r#"{"Set":{"key":"key1","value":"value1"}}"#;
+    let cmd = Command::Set { key: "key1".to_owned(), value: "value1".to_owned(), expires_at: None };
+    assert_eq!(serde_json::to_string(&cmd)?, old_format);
+    assert_eq!(serde_json::from_str::<Command>(old_format)?, cmd);
+
+    Ok(())
+}
+
+// Should keep the expiry of a Set in a KvStore transaction, which sled cannot store
+#[test]
+fn transaction_with_expiry() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    let expired = Command::Set { key: "key1".to_owned(), value: "value1".to_owned(), expires_at: Some(1) };
+
+    store.transaction(vec![expired])?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+
+    let sled_dir = TempDir::new().expect("unable to create temporary working directory");
+    let sled = SledKvsEngine::open(sled_dir.path())?;
+    let result = sled.transaction(vec![
+        Command::Set { key: "key1".to_owned(), value: "value1".to_owned(), expires_at: None },
+        Command::Set { key: "key2".to_owned(), value: "value2".to_owned(), expires_at: Some(u64::MAX) },
+    ]);
+    assert!(matches!(result, Err(KvsError::Unsupported(_))));
+    assert_eq!(sled.get("key1".to_owned())?, None);
+    assert_eq!(sled.get("key2".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should remove expired keys that are never read, writing a Remove command for each of them
+#[test]
+fn expiry_sweep() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let ttl = std::time::Duration::from_millis(50);
+
+    {
+        let store = KvStore::open(temp_dir.path())?;
+        for i in 0..2500 {
+            store.set_with_ttl(format!("key{}", i), "value".to_owned(), ttl)?;
+        }
+        store.set_with_ttl("kept1".to_owned(), "value".to_owned(), std::time::Duration::from_secs(3600))?;
+        store.set("kept2".to_owned(), "value".to_owned())?;
+        std::thread::sleep(ttl);
+
+        // Every expired key is removed, over several batches, and its bytes count as stale
+        assert_eq!(store.sweep_expired()?, 2500);
+        assert_eq!(store.sweep_expired()?, 0);
+        assert_eq!(store.stats().live_keys, 2);
+        assert!(store.stats().uncompacted > 0);
+        assert_eq!(store.get("kept1".to_owned())?, Some("value".to_owned()));
+    }
+
+    // The Remove commands are in the log
+    let log = String::from_utf8_lossy(&std::fs::read(temp_dir.path().join("1.log"))?).into_owned();
+    assert_eq!(log.matches("Remove").count(), 2500);
+
+    // A configured interval sweeps the expired keys in the background, without any access
+    let config = KvStoreConfig { expiry_sweep_interval: Some(std::time::Duration::from_millis(20)), ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    store.set_with_ttl("key1".to_owned(), "value".to_owned(), ttl)?;
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    let log = String::from_utf8_lossy(&std::fs::read(temp_dir.path().join("2.log"))?).into_owned();
+    assert!(log.contains(r#"{"Remove":{"key":"key1"}}"#));
+    assert_eq!(store.stats().live_keys, 2);
+    drop(store);
+
+    // A store in memory sweeps in the background too
+    let config = KvStoreConfig { expiry_sweep_interval: Some(std::time::Duration::from_millis(20)), ..KvStoreConfig::default() };
+    let store = KvStore::new_in_memory_with_options(config)?;
+    store.set_with_ttl("key1".to_owned(), "value".to_owned(), ttl)?;
+    std::thread::sleep(std::time::Duration::from_millis(300));
+    assert_eq!(store.stats().live_keys, 0);
+    drop(store);
+
+    // The sweeper stops when the store is dropped, without waiting for the next sweep
+    let config = KvStoreConfig { expiry_sweep_interval: Some(std::time::Duration::from_secs(3600)), ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    // A new thread names itself once it runs
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    #[cfg(target_os = "linux")]
+    assert_eq!(expiry_sweeper_threads(), 1);
+    drop(store);
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    #[cfg(target_os = "linux")]
+    assert_eq!(expiry_sweeper_threads(), 0);
+
+    let config = KvStoreConfig { expiry_sweep_interval: Some(std::time::Duration::ZERO), ..KvStoreConfig::default() };
+    let empty_dir = TempDir::new().expect("unable to create temporary working directory");
+    assert!(matches!(KvStore::open_with_options(empty_dir.path(), config), Err(KvsError::InvalidSweepInterval)));
+
+    Ok(())
+}
+
+// Number of running expiry sweeper threads of the process, whose names are cut to 15 bytes
+#[cfg(target_os = "linux")]
+fn expiry_sweeper_threads() -> usize {
+    std::fs::read_dir("/proc/self/task")
+        .expect("unable to list the threads")
+        .filter_map(|task| std::fs::read_to_string(task.ok()?.path().join("comm")).ok())
+        .filter(|name| name.trim_end() == "kvs-expiry-swee")
+        .count()
+}
+
+// Should only swap values that match the expected value, also between threads
+#[test]
+fn compare_and_swap() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    assert!(store.compare_and_swap("key1".to_owned(), None, Some("value1".to_owned()))?);
+    assert!(!store.compare_and_swap("key1".to_owned(), None, Some("value2".to_owned()))?);
+    assert!(!store.compare_and_swap("key1".to_owned(), Some("value2".to_owned()), None)?);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    assert!(store.compare_and_swap("key1".to_owned(), Some("value1".to_owned()), None)?);
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert!(store.compare_and_swap("key1".to_owned(), None, None)?);
+
+    // Concurrent increments of a counter never lose an update
+    store.set("counter".to_owned(), "0".to_owned())?;
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let store = store.clone();
+
+            std::thread::spawn(move || -> Result<()> {
+                for _ in 0..50 {
+                    loop {
+                        let current = store.get("counter".to_owned())?.unwrap();
+                        let next = (current.parse::<u32>().unwrap() + 1).to_string();
+
+                        if store.compare_and_swap("counter".to_owned(), Some(current), Some(next))? {
+                            break;
+                        }
+                    }
+                }
+
+                Ok(())
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().expect("thread panicked")?;
+    }
+    assert_eq!(store.get("counter".to_owned())?, Some("200".to_owned()));
+
+    Ok(())
+}
+
+// Should load the records before a truncated or corrupt record instead of failing to open
+#[test]
+fn open_corrupt_log() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let log_path = temp_dir.path().join("1.log");
+
+    {
+        let store = KvStore::open(temp_dir.path())?;
+        store.set("key1".to_owned(), "value1".to_owned())?;
+        store.set("key2".to_owned(), "value2".to_owned())?;
+        store.set("key3".to_owned(), "value3".to_owned())?;
+    }
+
+    // Cut the last record in the middle of its command
+    let len = std::fs::metadata(&log_path)?.len();
+    std::fs::OpenOptions::new().write(true).open(&log_path)?.set_len(len - 5)?;
+
+    {
+        let store = KvStore::open(temp_dir.path())?;
+        assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+        assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+        assert_eq!(store.get("key3".to_owned())?, None);
+    }
+
+    // Flip a byte inside the command of the second record
+    let mut bytes = std::fs::read(&log_path)?;
+    let position = bytes.windows(4).position(|window| window == b"key2").expect("key2 not found");
+    bytes[position] = b'K';
+    std::fs::write(&log_path, bytes)?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.get("Key2".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should write one command per line and load it back, also after a crash mid-line
+#[test]
+fn json_lines_log_format() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let log_path = temp_dir.path().join("1.log");
+    let config = KvStoreConfig { log_format: LogFormat::JsonLines, ..KvStoreConfig::default() };
+
+    {
+        let store = KvStore::open_with_options(temp_dir.path(), config.clone())?;
+        store.set("key1".to_owned(), "value\n1".to_owned())?;
+        store.set("key2".to_owned(), "value2".to_owned())?;
+        store.remove("key2".to_owned())?;
+        store.set("key3".to_owned(), "value3".to_owned())?;
+    }
+
+    let content = std::fs::read_to_string(&log_path)?;
+    assert_eq!(content.lines().count(), 4);
+    assert_eq!(content.lines().nth(2), Some(r#"{"Remove":{"key":"key2"}}"#));
+
+    // Cut the last line in the middle of its command
+    std::fs::write(&log_path, &content[..content.len() - 5])?;
+
+    // The format of every log file is detected when loading
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value\n1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.get("key3".to_owned())?, None);
+    drop(store);
+
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    let gen = store.generation();
+    store.compact()?;
+    let (_, path) = store.log_segments_since(gen).remove(0);
+    assert_eq!(std::fs::read_to_string(path)?, "{\"Set\":{\"key\":\"key1\",\"value\":\"value\\n1\"}}\n");
+    assert_eq!(store.get("key1".to_owned())?, Some("value\n1".to_owned()));
+
+    Ok(())
+}
+
+// Should serialize commands with bincode and read them whatever the configured codec is
+#[test]
+fn bincode_codec() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { codec: Codec::Bincode, ..KvStoreConfig::default() };
+
+    {
+        let store = KvStore::open_with_options(temp_dir.path(), config)?;
+        store.set("key1".to_owned(), "value1".to_owned())?;
+        store.set_with_ttl("key2".to_owned(), "value2".to_owned(), std::time::Duration::from_secs(3600))?;
+        store.set_bytes("key3".to_owned(), b"value3".to_vec())?;
+        store.set("key4".to_owned(), "value4".to_owned())?;
+        store.remove("key4".to_owned())?;
+        assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    }
+
+    // The JSON serialization is not found in the log
+    let log = std::fs::read(temp_dir.path().join("1.log"))?;
+    assert!(!log.windows(5).any(|window| window == b"\"key\""));
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(store.get_bytes("key3".to_owned())?, Some(b"value3".to_vec()));
+    assert_eq!(store.get("key4".to_owned())?, None);
+
+    // Compaction serializes the commands again with the configured codec
+    let gen = store.generation();
+    store.compact()?;
+    let (_, path) = store.log_segments_since(gen).remove(0);
+    let compacted = String::from_utf8_lossy(&std::fs::read(path)?).into_owned();
+    assert!(compacted.contains(r#""key":"key2""#) && compacted.contains("expires_at"));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+// Should load log files written before commands were framed in records
+#[test]
+fn open_unframed_log() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    std::fs::write(
+        temp_dir.path().join("1.log"),
+        r#"{"Set":{"key":"key1","value":"value1"}}{"Set":{"key":"key2","value":"value2"}}{"Remove":{"key":"key1"}}"#
+    )?;
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    // Compaction rewrites the commands in records
+    store.compact()?;
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+// Should behave like a store on disk, including compaction, without touching the disk
+#[test]
+fn in_memory_store() -> Result<()> {
+    let store = KvStore::new_in_memory()?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.remove("key2".to_owned())?;
+
+    for iter in 0..40000 {
+        store.set("key3".to_owned(), format!("value{}", iter))?;
+    }
+
+    // Compaction ran automatically and kept the live values
+    assert!(store.generation() > 0);
+    assert!(store.log_segments_since(0).is_empty());
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+    assert_eq!(store.get("key3".to_owned())?, Some("value39999".to_owned()));
+
+    store.compact()?;
+    assert_eq!(store.stats().log_files, 2);
+    assert_eq!(store.scan(..).collect::<Result<Vec<_>>>()?, vec![
+        ("key1".to_owned(), "value1".to_owned()),
+        ("key3".to_owned(), "value39999".to_owned()),
+    ]);
+
+    // Clones share the same contents
+    let clone = store.clone();
+    std::thread::spawn(move || clone.set("key4".to_owned(), "value4".to_owned()))
+        .join()
+        .expect("thread panicked")?;
+    assert_eq!(store.get("key4".to_owned())?, Some("value4".to_owned()));
+
+    Ok(())
+}
+
+// Should add to integer values, starting missing keys at 0
+#[test]
+fn increment() -> Result<()> {
+    let store = KvStore::new_in_memory()?;
+
+    assert_eq!(store.increment("counter".to_owned(), 5)?, 5);
+    assert_eq!(store.increment("counter".to_owned(), -7)?, -2);
+    assert_eq!(store.get("counter".to_owned())?, Some("-2".to_owned()));
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    assert!(matches!(store.increment("key1".to_owned(), 1), Err(KvsError::NotAnInteger)));
+
+    store.set("key2".to_owned(), i64::MAX.to_string())?;
+    assert!(matches!(store.increment("key2".to_owned(), 1), Err(KvsError::IntegerOverflow)));
+    assert_eq!(store.get("key2".to_owned())?, Some(i64::MAX.to_string()));
+
+    Ok(())
+}
+
+// Should append to values, starting missing keys empty, and count the old values as stale
+#[test]
+fn append_to_value() -> Result<()> {
+    let store = KvStore::new_in_memory()?;
+    store.set_compaction_mode(CompactionMode::Manual);
+
+    assert_eq!(store.append("log".to_owned(), "line1".to_owned())?, 5);
+    assert_eq!(store.stats().uncompacted, 0);
+
+    assert_eq!(store.append("log".to_owned(), ",line2".to_owned())?, 11);
+    assert_eq!(store.get("log".to_owned())?, Some("line1,line2".to_owned()));
+    assert!(store.stats().uncompacted > 0);
+
+    store.compact()?;
+    assert_eq!(store.stats().uncompacted, 0);
+    assert_eq!(store.get("log".to_owned())?, Some("line1,line2".to_owned()));
+
+    Ok(())
+}
+
+// Should remove only the keys starting with the prefix and count them as stale
+#[test]
+fn remove_prefix() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set_compaction_mode(CompactionMode::Manual);
+
+    store.set("user:1:name".to_owned(), "name1".to_owned())?;
+    store.set("user:1:session".to_owned(), "session1".to_owned())?;
+    store.set("user:10:name".to_owned(), "name10".to_owned())?;
+    store.set("user:2:name".to_owned(), "name2".to_owned())?;
+    store.set("user:".to_owned(), "users".to_owned())?;
+
+    assert_eq!(store.remove_prefix("user:1:".to_owned())?, 2);
+    assert_eq!(store.remove_prefix("user:1:".to_owned())?, 0);
+    assert_eq!(store.remove_prefix("missing".to_owned())?, 0);
+    assert!(matches!(store.remove_prefix("".to_owned()), Err(KvsError::InvalidKey)));
+    assert!(store.stats().uncompacted > 0);
+
+    assert_eq!(store.keys().collect::<Vec<_>>(), vec!["user:", "user:10:name", "user:2:name"]);
+
+    // The removals are in the log
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.len()?, 3);
+    assert_eq!(store.get("user:1:name".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should read and write values through buffers of any configured capacity
+#[test]
+fn configured_buffer_capacity() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+
+    // A buffer smaller than a single record, then one bigger than the whole log file
+    for buf_capacity in [16, 1024 * 1024] {
+        let config = KvStoreConfig { buf_capacity, sync_on_write: false, ..KvStoreConfig::default() };
+        let store = KvStore::open_with_options(temp_dir.path(), config.clone())?;
+
+        for key_id in 0..100 {
+            store.set(format!("key{}", key_id), format!("value{}", buf_capacity))?;
+        }
+        assert_eq!(store.get("key50".to_owned())?, Some(format!("value{}", buf_capacity)));
+
+        drop(store);
+        let store = KvStore::open_with_options(temp_dir.path(), config)?;
+        assert_eq!(store.len()?, 100);
+        assert_eq!(store.get("key99".to_owned())?, Some(format!("value{}", buf_capacity)));
+    }
+
+    Ok(())
+}
+
+// Should answer lookups the same way with a bloom filter, including after it is rebuilt
+#[test]
+fn bloom_filter_lookups() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { bloom_filter: true, ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config.clone())?;
+
+    // More keys than the filter is first sized for
+    for key_id in 0..5000 {
+        store.set(format!("key{}", key_id), format!("value{}", key_id))?;
+    }
+
+    for key_id in 0..5000 {
+        assert_eq!(store.get(format!("key{}", key_id))?, Some(format!("value{}", key_id)));
+    }
+    assert_eq!(store.get("missing".to_owned())?, None);
+    assert!(!store.contains_key("missing".to_owned())?);
+    assert_eq!(store.get_many(vec!["key1".to_owned(), "missing".to_owned()])?, vec![Some("value1".to_owned()), None]);
+
+    store.remove("key1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+
+    // The filter is rebuilt from the log files
+    drop(store);
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    assert_eq!(store.get("key4999".to_owned())?, Some("value4999".to_owned()));
+
+    store.clear()?;
+    assert_eq!(store.get("key2".to_owned())?, None);
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    assert!(store.contains_key("key2".to_owned())?);
+
+    Ok(())
+}
+
+// Should only read, write and delete the log files named as configured
+#[test]
+fn custom_log_file_names() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let names = LogFileNames { prefix: "kvs-".to_owned(), extension: "dat".to_owned() };
+    let config = KvStoreConfig { log_file_names: names, ..KvStoreConfig::default() };
+
+    // Files that look like default log files but belong to something else
+    std::fs::write(temp_dir.path().join("1.log"), "not a log file").expect("unable to write file");
+    std::fs::write(temp_dir.path().join("kvs-01.dat"), "not a log file").expect("unable to write file");
+
+    let store = KvStore::open_with_options(temp_dir.path(), config.clone())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+    store.remove("key2".to_owned())?;
+    assert!(temp_dir.path().join("kvs-1.dat").is_file());
+
+    store.compact()?;
+    assert!(!temp_dir.path().join("kvs-1.dat").exists());
+
+    drop(store);
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    // The other files are left untouched
+    assert_eq!(std::fs::read_to_string(temp_dir.path().join("1.log")).expect("unable to read file"), "not a log file");
+    assert_eq!(std::fs::read_to_string(temp_dir.path().join("kvs-01.dat")).expect("unable to read file"), "not a log file");
+
+    Ok(())
+}
+
+// Should move on to a new log file once the current one exceeds the maximum size
+#[test]
+fn log_rotation() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { max_log_size: Some(200), ..KvStoreConfig::default() };
+
+    {
+        let store = KvStore::open_with_options(temp_dir.path(), config.clone())?;
+        store.set_compaction_mode(CompactionMode::Manual);
+
+        for iter in 0..20 {
+            store.set(format!("key{}", iter), format!("value{}", iter))?;
+        }
+        store.remove("key0".to_owned())?;
+
+        // Every rotated log file stays below the maximum size plus one record
+        let segments = store.log_segments_since(0);
+        assert!(segments.len() > 1);
+        assert!(segments.iter().all(|(_, path)| std::fs::metadata(path).unwrap().len() < 300));
+
+        assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+        assert_eq!(store.get("key19".to_owned())?, Some("value19".to_owned()));
+    }
+
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    assert_eq!(store.len()?, 19);
+    assert_eq!(store.get("key0".to_owned())?, None);
+
+    // Compaction merges the rotated log files
+    store.compact()?;
+    assert_eq!(store.stats().log_files, 2);
+    assert_eq!(store.get("key19".to_owned())?, Some("value19".to_owned()));
+
+    Ok(())
+}
+
+// Should remove every key and keep accepting writes
+#[test]
+fn clear() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+
+    store.clear()?;
+    assert!(store.is_empty()?);
+    assert_eq!(store.stats().uncompacted, 0);
+    assert_eq!(store.stats().log_files, 1);
+
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.keys().collect::<Vec<_>>(), vec!["key3"]);
+
+    Ok(())
+}
+
+// Should restore the live keys of a backup and reject corrupt backups
+#[test]
+fn backup_and_restore() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key1".to_owned(), "value2".to_owned())?;
+    store.set("key2".to_owned(), "value3".to_owned())?;
+    store.set_bytes("key3".to_owned(), vec![0, 159, 146, 150])?;
+    store.remove("key2".to_owned())?;
+
+    let mut backup = Vec::new();
+    store.backup_to(&mut backup)?;
+
+    let restore_dir = TempDir::new().expect("unable to create temporary working directory");
+    let restored = KvStore::restore_from(restore_dir.path(), &backup[..], false)?;
+    assert_eq!(restored.len()?, 2);
+    assert_eq!(restored.get("key1".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(restored.get("key2".to_owned())?, None);
+    assert_eq!(restored.get_bytes("key3".to_owned())?, Some(vec![0, 159, 146, 150]));
+    drop(restored);
+
+    // The restored contents survive reopening the store
+    let restored = KvStore::open(restore_dir.path())?;
+    assert_eq!(restored.get("key1".to_owned())?, Some("value2".to_owned()));
+    drop(restored);
+
+    // A directory with files is only restored into when overwriting
+    assert!(matches!(
+        KvStore::restore_from(temp_dir.path(), &backup[..], false),
+        Err(KvsError::DirectoryNotEmpty(_))
+    ));
+
+    store.set("key4".to_owned(), "value4".to_owned())?;
+    drop(store);
+
+    let restored = KvStore::restore_from(temp_dir.path(), &backup[..], true)?;
+    assert_eq!(restored.get("key1".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(restored.get("key4".to_owned())?, None);
+
+    // Corrupt or truncated backups are rejected before anything is written
+    let empty_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut corrupt = backup.clone();
+    let middle = corrupt.len() / 2;
+    corrupt[middle] ^= 1;
+
+    assert!(matches!(
+        KvStore::restore_from(empty_dir.path(), &corrupt[..], false),
+        Err(KvsError::InvalidBackup(_))
+    ));
+    assert!(matches!(
+        KvStore::restore_from(empty_dir.path(), &backup[..backup.len() - 1], false),
+        Err(KvsError::InvalidBackup(_))
+    ));
+    assert_eq!(std::fs::read_dir(empty_dir.path()).unwrap().count(), 0);
+
+    Ok(())
+}
+
+// Should compact a chunk at a time while writes interleave with the steps
+#[test]
+fn incremental_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    for iter in 0..100 {
+        store.set(format!("key{}", iter), "value".repeat(10))?;
+        store.set(format!("key{}", iter), format!("value{}", iter))?;
+    }
+
+    // Every step copies at least one command, and the old log file is kept until the last step
+    assert!(store.compact_step(1)?);
+    assert_eq!(store.stats().log_files, 3);
+
+    // Writes during the compaction are not overwritten by it
+    store.set("key0".to_owned(), "new".to_owned())?;
+    store.set("key50".to_owned(), "new".to_owned())?;
+    store.remove("key60".to_owned())?;
+    store.set("key100".to_owned(), "value100".to_owned())?;
+
+    assert!(store.compact_step(100)?);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    let mut steps = 0;
+    while store.compact_step(100)? {
+        steps += 1;
+    }
+    assert!(steps > 1);
+
+    // Only the compaction file and the new log file are left
+    assert_eq!(store.stats().log_files, 2);
+    assert_eq!(store.len()?, 100);
+    assert_eq!(store.get("key0".to_owned())?, Some("new".to_owned()));
+    assert_eq!(store.get("key50".to_owned())?, Some("new".to_owned()));
+    assert_eq!(store.get("key60".to_owned())?, None);
+    assert_eq!(store.get("key99".to_owned())?, Some("value99".to_owned()));
+    assert_eq!(store.get("key100".to_owned())?, Some("value100".to_owned()));
+    drop(store);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.len()?, 100);
+    assert_eq!(store.get("key0".to_owned())?, Some("new".to_owned()));
+    assert_eq!(store.get("key60".to_owned())?, None);
+
+    // Compact finishes a compaction in progress
+    assert!(store.compact_step(1)?);
+    store.compact()?;
+    assert_eq!(store.stats().uncompacted, 0);
+    assert_eq!(store.stats().log_files, 2);
+    assert_eq!(store.get("key99".to_owned())?, Some("value99".to_owned()));
+
+    Ok(())
+}
+
+// Should reject empty keys and values above the configured maximum size
+#[test]
+fn invalid_keys_and_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    assert!(matches!(store.set("".to_owned(), "value1".to_owned()), Err(KvsError::InvalidKey)));
+    assert!(matches!(store.get("".to_owned()), Err(KvsError::InvalidKey)));
+    assert!(matches!(store.remove("".to_owned()), Err(KvsError::InvalidKey)));
+    assert!(matches!(store.rename("key1".to_owned(), "".to_owned()), Err(KvsError::KeyNotFound)));
+    assert!(matches!(
+        store.transaction(vec![Command::Set { key: "".to_owned(), value: "value1".to_owned(), expires_at: None }]),
+        Err(KvsError::InvalidKey)
+    ));
+
+    // Values of any size are accepted by default
+    store.set("key1".to_owned(), "v".repeat(100_000))?;
+    drop(store);
+
+    let config = KvStoreConfig { max_value_size: Some(10), ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+
+    store.set("key2".to_owned(), "v".repeat(10))?;
+    assert!(matches!(
+        store.set("key2".to_owned(), "v".repeat(11)),
+        Err(KvsError::ValueTooLarge { size: 11, max: 10 })
+    ));
+    assert!(matches!(store.set_bytes("key3".to_owned(), vec![0; 11]), Err(KvsError::ValueTooLarge { .. })));
+    assert_eq!(store.get("key2".to_owned())?, Some("v".repeat(10)));
+    assert_eq!(store.get("key3".to_owned())?, None);
+
+    // Values stored before the limit was set can still be read
+    assert_eq!(store.get("key1".to_owned())?.map(|value| value.len()), Some(100_000));
+
+    Ok(())
+}
+
+// Should get several values at once in the order of the keys
+#[test]
+fn get_many_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { max_log_size: Some(100), ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+
+    // Values are spread over several log files, written in another order than requested
+    for iter in (0..20).rev() {
+        store.set(format!("key{}", iter), format!("value{}", iter))?;
+    }
+    store.set("key5".to_owned(), "new".to_owned())?;
+    store.remove("key7".to_owned())?;
+    assert!(store.stats().log_files > 2);
+
+    let values = store.get_many(vec![
+        "key0".to_owned(),
+        "key19".to_owned(),
+        "key7".to_owned(),
+        "missing".to_owned(),
+        "key5".to_owned(),
+        "key0".to_owned(),
+    ])?;
+    assert_eq!(values, vec![
+        Some("value0".to_owned()),
+        Some("value19".to_owned()),
+        None,
+        None,
+        Some("new".to_owned()),
+        Some("value0".to_owned()),
+    ]);
+
+    assert_eq!(store.get_many(vec![])?, Vec::<Option<String>>::new());
+    assert!(matches!(store.get_many(vec!["key0".to_owned(), "".to_owned()]), Err(KvsError::InvalidKey)));
+
+    Ok(())
+}
+
+// Should fail the operations on a key whose log file was emptied under the store
+// instead of panicking, while other keys keep working
+#[test]
+fn log_file_emptied_under_index() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { max_log_size: Some(10), ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    store.set_compaction_mode(CompactionMode::Manual);
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+
+    // An open file handle outlives the deletion of its file on some platforms,
+    // so the log file of key1 is emptied instead
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(temp_dir.path().join("1.log"))
+        .expect("unable to open log file")
+        .set_len(0)
+        .expect("unable to truncate log file");
+
+    assert!(matches!(
+        store.get("key1".to_owned()),
+        Err(KvsError::CorruptLog { position: 8, key: Some(key) }) if key == "key1"
+    ));
+    assert!(matches!(store.compact(), Err(KvsError::CorruptLog { .. })));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    store.set("key3".to_owned(), "value3".to_owned())?;
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+
+    Ok(())
+}
+
+// Should detect a value modified in the log file after it was indexed
+#[test]
+fn log_record_modified_under_index() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "value2".to_owned())?;
+
+    // Change a byte of the value of key1 without changing the size of the log file
+    let path = temp_dir.path().join("1.log");
+    let mut bytes = std::fs::read(&path).expect("unable to read log file");
+    let position = bytes
+        .windows(6)
+        .position(|window| window == b"value1")
+        .expect("value not found in log file");
+    bytes[position] = b'V';
+    std::fs::write(&path, bytes).expect("unable to write log file");
+
+    assert!(matches!(
+        store.get("key1".to_owned()),
+        Err(KvsError::CorruptLog { key: Some(key), .. }) if key == "key1"
+    ));
+    assert!(matches!(store.get_bytes("key1".to_owned()), Err(KvsError::CorruptLog { .. })));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+// Should refuse to open a directory already opened by another store until it is dropped
+#[test]
+fn open_locked_directory() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    assert!(matches!(KvStore::open(temp_dir.path()), Err(KvsError::AlreadyLocked(_))));
+
+    // Clones share the lock of the store they were cloned from
+    let clone = store.clone();
+    drop(store);
+    assert!(matches!(KvStore::open(temp_dir.path()), Err(KvsError::AlreadyLocked(_))));
+
+    drop(clone);
+    KvStore::open(temp_dir.path())?;
+
+    Ok(())
+}
+
+// Should flush buffered writes once the last clone of the store is dropped
+#[test]
+fn drop_flushes_writes() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { sync_on_write: false, ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+
+    for iter in 0..10 {
+        store.set(format!("key{}", iter), format!("value{}", iter))?;
+    }
+    store.remove("key9".to_owned())?;
+
+    // The copied commands of a compaction in progress are buffered too
+    assert!(store.compact_step(1)?);
+    store.set("key10".to_owned(), "value10".to_owned())?;
+
+    // Other clones keep the store open
+    let clone = store.clone();
+    drop(store);
+    clone.set("key11".to_owned(), "value11".to_owned())?;
+    drop(clone);
+
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.len()?, 11);
+    assert_eq!(store.get("key0".to_owned())?, Some("value0".to_owned()));
+    assert_eq!(store.get("key9".to_owned())?, None);
+    assert_eq!(store.get("key11".to_owned())?, Some("value11".to_owned()));
+
+    Ok(())
+}
+
+// Should open a sled store with the given options and keep its data across openings
+#[test]
+fn sled_open_with_config() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = SledConfig { cache_capacity: 16 * 1024 * 1024, flush_every_ms: None, ..SledConfig::default() };
+
+    let store = SledKvsEngine::open_with_config(temp_dir.path(), config.clone())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    // Nothing is flushed automatically, so writes are made durable explicitly
+    store.flush()?;
+    drop(store);
+
+    let store = SledKvsEngine::open_with_config(temp_dir.path(), config)?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    Ok(())
+}
+
+// Should migrate every key/value pair between engines through a JSON lines export
+#[test]
+fn export_and_import_between_engines() -> Result<()> {
+    let kvs_dir = TempDir::new().expect("unable to create temporary working directory");
+    let sled_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(kvs_dir.path())?;
+
+    store.set("key1".to_owned(), "value1".to_owned())?;
+    store.set("key2".to_owned(), "line\nbreak".to_owned())?;
+    store.set_bytes("binary".to_owned(), vec![0, 159, 146, 150, 255])?;
+    store.set("removed".to_owned(), "value".to_owned())?;
+    store.remove("removed".to_owned())?;
+
+    let mut exported = Vec::new();
+    assert_eq!(export(&store, &mut exported)?, 3);
+    let lines: Vec<&str> = std::str::from_utf8(&exported).unwrap().lines().collect();
+    assert_eq!(lines, vec![
+        r#"{"key":"binary","value_base64":"AJ+Slv8="}"#,
+        r#"{"key":"key1","value":"value1"}"#,
+        r#"{"key":"key2","value":"line\nbreak"}"#,
+    ]);
+
+    // Values that are not UTF-8 keep their bytes in sled
+    let sled = SledKvsEngine::open(sled_dir.path())?;
+    assert_eq!(import(&sled, exported.as_slice())?, 3);
+    assert_eq!(sled.get("key2".to_owned())?, Some("line\nbreak".to_owned()));
+    assert_eq!(sled.get_bytes("binary".to_owned())?, Some(vec![0, 159, 146, 150, 255]));
+
+    // And back into a new KvStore
+    let mut migrated = Vec::new();
+    assert_eq!(export(&sled, &mut migrated)?, 3);
+    assert_eq!(migrated, exported);
+    let store = KvStore::new_in_memory()?;
+    assert_eq!(import(&store, migrated.as_slice())?, 3);
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get_bytes("binary".to_owned())?, Some(vec![0, 159, 146, 150, 255]));
+
+    // Invalid lines are reported with their line number
+    let invalid = "{\"key\":\"a\",\"value\":\"b\"}\n\n{\"key\":\"c\",\"value_base64\":\"#\"}\n";
+    match import(&store, invalid.as_bytes()) {
+        Err(KvsError::InvalidExport { line: 3, .. }) => {},
+        res => panic!("expected an invalid export error, got {:?}", res)
+    }
+    assert_eq!(store.get("a".to_owned())?, Some("b".to_owned()));
+    assert!(import(&store, "{\"key\":\"a\"}".as_bytes()).is_err());
+
+    Ok(())
+}
+
+// Should report the progress of the log load once before it and once per log file
+#[test]
+fn open_with_progress() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { max_log_size: Some(100), ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+
+    for key_id in 0..20 {
+        store.set(format!("key{}", key_id), format!("value{}", key_id))?;
+    }
+    drop(store);
+
+    let files: Vec<u64> = std::fs::read_dir(temp_dir.path())?
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "log"))
+        .map(|path| std::fs::metadata(path).unwrap().len())
+        .collect();
+    assert!(files.len() > 2);
+
+    let mut calls = Vec::new();
+    let store = KvStore::open_with_progress(temp_dir.path(), |loaded, total, bytes| calls.push((loaded, total, bytes)))?;
+    assert_eq!(store.get("key19".to_owned())?, Some("value19".to_owned()));
+
+    assert_eq!(calls.len(), files.len() + 1);
+    assert_eq!(calls[0], (0, files.len(), 0));
+    assert_eq!(calls.last(), Some(&(files.len(), files.len(), files.iter().sum())));
+    assert!(calls.windows(2).all(|pair| pair[0].0 + 1 == pair[1].0 && pair[0].2 < pair[1].2));
+
+    // An empty store reports that there is nothing to load
+    let empty_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut calls = Vec::new();
+    KvStore::open_with_progress(empty_dir.path(), |loaded, total, bytes| calls.push((loaded, total, bytes)))?;
+    assert_eq!(calls, vec![(0, 0, 0)]);
+
+    Ok(())
+}
+
+// Should report the number of keys and the sizes of their values after writes, reopening and compaction
+#[test]
+fn size_stats() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.size_stats()?, SizeStats::default());
+
+    store.set("key1".to_owned(), "a".repeat(10))?;
+    store.set("key2".to_owned(), "b".repeat(100))?;
+    store.set("key1".to_owned(), "c".repeat(20))?;
+    store.set_bytes("key3".to_owned(), vec![0xff; 30])?;
+    store.set("key4".to_owned(), "d".repeat(1000))?;
+    store.remove("key4".to_owned())?;
+    store.transaction(vec![Command::Set { key: "key5".to_owned(), value: "e".repeat(50), expires_at: None }])?;
+
+    let expected = SizeStats { keys: 4, total_value_bytes: 200, average_value_bytes: 50, max_value_bytes: 100 };
+    assert_eq!(store.size_stats()?, expected);
+
+    // The lengths of the values are rebuilt when the log is loaded and kept by compaction
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.size_stats()?, expected);
+
+    store.compact()?;
+    assert_eq!(store.size_stats()?, expected);
+
+    // The sled engine reports the same stats for the same values
+    let sled_dir = TempDir::new().expect("unable to create temporary working directory");
+    let sled = SledKvsEngine::open(sled_dir.path())?;
+    let mut exported = Vec::new();
+    export(&store, &mut exported)?;
+    import(&sled, exported.as_slice())?;
+    assert_eq!(sled.size_stats()?, expected);
+
+    Ok(())
+}
+
+// Should report the log file, value length and expiry of a key from the index
+#[test]
+fn stat_key() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.stat_key("key1"), None);
+
+    store.set("key1".to_owned(), "a".repeat(10))?;
+    store.set_with_ttl("key2".to_owned(), "b".repeat(20), std::time::Duration::from_secs(3600))?;
+    store.set_with_ttl("key3".to_owned(), "c".repeat(30), std::time::Duration::from_millis(1))?;
+    std::thread::sleep(std::time::Duration::from_millis(10));
+
+    let stat = store.stat_key("key1").expect("key1 is missing");
+    assert_eq!(stat.value_bytes, 10);
+    assert_eq!(stat.expires_at, None);
+    let stat = store.stat_key("key2").expect("key2 is missing");
+    assert_eq!(stat.value_bytes, 20);
+    assert!(stat.expires_at.is_some());
+    assert_eq!(store.stat_key("key3"), None);
+
+    // Compaction moves the value to a newer log file
+    let before = store.stat_key("key1").and_then(|stat| stat.log_file_id);
+    store.compact()?;
+    let after = store.stat_key("key1").and_then(|stat| stat.log_file_id);
+    assert!(after > before);
+    assert_eq!(KvsEngine::stat_key(&store, "key1".to_owned())?, store.stat_key("key1"));
+
+    // The sled engine only knows the length of the value
+    let sled_dir = TempDir::new().expect("unable to create temporary working directory");
+    let sled = SledKvsEngine::open(sled_dir.path())?;
+    sled.set("key1".to_owned(), "a".repeat(10))?;
+    let expected = KeyStat { log_file_id: None, value_bytes: 10, expires_at: None };
+    assert_eq!(sled.stat_key("key1".to_owned())?, Some(expected));
+    assert_eq!(sled.stat_key("key2".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should list pages of keys starting with a prefix in key order with both engines
+#[test]
+fn keys_page() -> Result<()> {
+    let kvs_dir = TempDir::new().expect("unable to create temporary working directory");
+    let sled_dir = TempDir::new().expect("unable to create temporary working directory");
+    let engines: Vec<Box<dyn KvsEngine>> = vec![
+        Box::new(KvStore::open(kvs_dir.path())?),
+        Box::new(SledKvsEngine::open(sled_dir.path())?),
+    ];
+
+    for engine in engines {
+        for key in ["b", "a2", "a1", "a3", "ab", "c"] {
+            engine.set(key.to_owned(), "value".to_owned())?;
+        }
+        engine.remove("a3".to_owned())?;
+
+        let page = |prefix: &str, after: Option<&str>, limit| engine.keys_page(prefix.to_owned(), after.map(str::to_owned), limit);
+
+        assert_eq!(page("a", None, 10)?, vec!["a1", "a2", "ab"]);
+        assert_eq!(page("a", None, 2)?, vec!["a1", "a2"]);
+        assert_eq!(page("a", Some("a2"), 2)?, vec!["ab"]);
+        assert_eq!(page("", Some("ab"), 10)?, vec!["b", "c"]);
+        // A cursor before the prefix starts at the prefix
+        assert_eq!(page("b", Some("a1"), 10)?, vec!["b"]);
+        assert!(page("d", None, 10)?.is_empty());
+    }
+
+    Ok(())
+}
+
+// Should set or remove a key with the result of a function of its value
+#[test]
+fn update() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+
+    // A missing key is set if the function returns a value
+    store.update("counter".to_owned(), |value| {
+        assert_eq!(value, None);
+        Some("1".to_owned())
+    })?;
+    assert_eq!(store.get("counter".to_owned())?, Some("1".to_owned()));
+
+    store.update("counter".to_owned(), |value| value.map(|value| (value.parse::<u64>().unwrap() + 1).to_string()))?;
+    assert_eq!(store.get("counter".to_owned())?, Some("2".to_owned()));
+
+    // The key is removed if the function returns None, which does nothing for a missing key
+    store.update("counter".to_owned(), |_| None)?;
+    assert_eq!(store.get("counter".to_owned())?, None);
+    store.update("missing".to_owned(), |_| None)?;
+    assert!(store.is_empty()?);
+
+    // The stale bytes are the same as with the equivalent sets and removes
+    let other_dir = TempDir::new().expect("unable to create temporary working directory");
+    let other = KvStore::open(other_dir.path())?;
+    other.set("counter".to_owned(), "1".to_owned())?;
+    other.set("counter".to_owned(), "2".to_owned())?;
+    other.remove("counter".to_owned())?;
+    assert_eq!(store.stats().uncompacted, other.stats().uncompacted);
+
+    // The new value keeps the expiry of the current one and is checked against the maximum size
+    store.set_with_ttl("session".to_owned(), "a".to_owned(), std::time::Duration::from_millis(100))?;
+    store.update("session".to_owned(), |value| value.map(|value| value + "b"))?;
+    assert_eq!(store.get("session".to_owned())?, Some("ab".to_owned()));
+    std::thread::sleep(std::time::Duration::from_millis(150));
+    assert_eq!(store.get("session".to_owned())?, None);
+
+    drop(store);
+    let config = KvStoreConfig { max_value_size: Some(4), ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    assert!(matches!(store.update("key".to_owned(), |_| Some("too long".to_owned())), Err(KvsError::ValueTooLarge { .. })));
+    assert!(matches!(store.update(String::new(), |_| None), Err(KvsError::InvalidKey)));
+
+    Ok(())
+}
+
+// Should answer reads of recently read keys from the value cache and never return a stale value
+#[test]
+fn value_cache() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { value_cache: Some(CacheSize::Entries(2)), ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    let hits_and_misses = |store: &KvStore| {
+        let stats = store.cache_stats().expect("cache is enabled");
+        (stats.hits, stats.misses)
+    };
+
+    for key_id in 1..=3 {
+        store.set(format!("key{}", key_id), format!("value{}", key_id))?;
+    }
+
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(hits_and_misses(&store), (1, 1));
+
+    // Writes and removes are never hidden by a cached value
+    store.set("key1".to_owned(), "new".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("new".to_owned()));
+    store.remove("key1".to_owned())?;
+    assert_eq!(store.get("key1".to_owned())?, None);
+    store.set_bytes("key1".to_owned(), vec![0xff, 0])?;
+    assert_eq!(store.get_bytes("key1".to_owned())?, Some(vec![0xff, 0]));
+    assert_eq!(store.get_bytes("key1".to_owned())?, Some(vec![0xff, 0]));
+    assert_eq!(hits_and_misses(&store), (2, 3));
+
+    // The least recently used value is evicted
+    store.get("key2".to_owned())?;
+    store.get("key3".to_owned())?;
+    assert_eq!(store.cache_stats().map(|stats| stats.entries), Some(2));
+    store.get_bytes("key1".to_owned())?;
+    assert_eq!(hits_and_misses(&store), (2, 6));
+
+    // Cached values stay valid once compaction moved their commands
+    store.get("key3".to_owned())?;
+    store.compact()?;
+    assert_eq!(store.get("key3".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(hits_and_misses(&store), (4, 6));
+
+    // A value bigger than a cache sized in bytes is not cached
+    let bytes_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { value_cache: Some(CacheSize::Bytes(16)), ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(bytes_dir.path(), config)?;
+    store.set("small".to_owned(), "value".to_owned())?;
+    store.set("big".to_owned(), "a".repeat(100))?;
+    store.get("small".to_owned())?;
+    store.get("big".to_owned())?;
+    assert_eq!(store.cache_stats().map(|stats| (stats.entries, stats.bytes)), Some((1, 10)));
+
+    // The cache is disabled by default
+    let default_dir = TempDir::new().expect("unable to create temporary working directory");
+    assert_eq!(KvStore::open(default_dir.path())?.cache_stats(), None);
+
+    Ok(())
+}
+
+// Should compact once there are more log files than the maximum, even without stale bytes
+#[test]
+fn compaction_on_log_file_count() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let config = KvStoreConfig { max_log_size: Some(100), max_log_files: Some(4), ..KvStoreConfig::default() };
+    let store = KvStore::open_with_options(temp_dir.path(), config.clone())?;
+
+    // Every key is written once, so the stale bytes stay far below the threshold
+    for key_id in 0..200 {
+        store.set(format!("key{}", key_id), format!("value{}", key_id))?;
+        assert!(store.stats().log_files <= 4);
+    }
+    assert_eq!(store.stats().uncompacted, 0);
+    assert_eq!(store.stats().max_log_files, Some(4));
+
+    drop(store);
+    let store = KvStore::open_with_options(temp_dir.path(), config)?;
+    for key_id in 0..200 {
+        assert_eq!(store.get(format!("key{}", key_id))?, Some(format!("value{}", key_id)));
+    }
+
+    // Without a maximum the log files pile up
+    let other_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open_with_options(other_dir.path(), KvStoreConfig { max_log_size: Some(100), ..KvStoreConfig::default() })?;
+    for key_id in 0..200 {
+        store.set(format!("key{}", key_id), format!("value{}", key_id))?;
+    }
+    assert!(store.stats().log_files > 50);
+
+    // Compaction leaves two log files, so a smaller maximum is rejected
+    let config = KvStoreConfig { max_log_files: Some(1), ..KvStoreConfig::default() };
+    assert!(matches!(KvStore::open_with_options(other_dir.path(), config), Err(KvsError::InvalidMaxLogFiles)));
+
+    Ok(())
+}
+
+// Should read the same bytes whether moving to a position reuses the buffer or seeks the file
+#[test]
+fn reader_seek_to() -> Result<()> {
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+
+    let bytes: Vec<u8> = (0..=255).collect();
+    let mut buffered = BufReaderWithPos::with_capacity(16, Cursor::new(bytes.clone()));
+    let mut seeking = BufReaderWithPos::with_capacity(16, Cursor::new(bytes));
+
+    // The same position, forward and backward inside the buffer, and out of the buffer both ways
+    for (start, len) in [(0, 4), (4, 4), (10, 3), (6, 2), (6, 2), (100, 8), (20, 30), (255, 10), (0, 1)] {
+        buffered.seek_to(start)?;
+        seeking.seek(SeekFrom::Start(start))?;
+
+        let mut from_buffer = Vec::new();
+        let mut from_seek = Vec::new();
+        (&mut buffered).take(len).read_to_end(&mut from_buffer)?;
+        (&mut seeking).take(len).read_to_end(&mut from_seek)?;
+
+        assert_eq!(from_buffer, from_seek);
+        assert_eq!(buffered.pos, seeking.pos);
+    }
+
+    // Values read one after the other come out the same as in any other order
+    for log_format in [LogFormat::Framed, LogFormat::JsonLines] {
+        let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+        let config = KvStoreConfig { log_format, ..KvStoreConfig::default() };
+        let store = KvStore::open_with_options(temp_dir.path(), config)?;
+
+        for key_id in 0..100 {
+            store.set(format!("key{:03}", key_id), "v".repeat(key_id))?;
+        }
+
+        let in_write_order: Vec<(String, String)> = store.iter_by_seq().collect::<Result<_>>()?;
+        assert_eq!(in_write_order.len(), 100);
+        for (key_id, (key, value)) in in_write_order.into_iter().enumerate() {
+            assert_eq!((key, value), (format!("key{:03}", key_id), "v".repeat(key_id)));
+        }
+
+        for key_id in (0..100).rev() {
+            assert_eq!(store.get(format!("key{:03}", key_id))?, Some("v".repeat(key_id)));
+        }
+    }
+
+    Ok(())
+}
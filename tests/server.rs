@@ -0,0 +1,972 @@
+use kvs::{read_frame, write_frame, Command, CommandResponse, ErrorKind, KvStore, KvStoreConfig, KvsClient, KvsEngine, KvsError, KvsServer, Listener, Protocol, Result, ServerAddr, Stream, MAX_CHUNK_SIZE, MAX_FRAME_SIZE, MAX_KEYS_PER_RESPONSE};
+use slog::o;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+use tempfile::TempDir;
+
+fn logger() -> slog::Logger {
+    slog::Logger::root(slog::Discard, o!())
+}
+
+// Start a server with the kvs engine on a background thread
+fn start_server(addr: SocketAddr, temp_dir: &TempDir) {
+    let path = temp_dir.path().to_owned();
+
+    thread::spawn(move || {
+        let engine = KvStore::open(path).expect("unable to open KvStore");
+        let mut server = KvsServer::new(addr, Box::new(engine), logger());
+        server.set_threads(4);
+        server.run().expect("server failed");
+    });
+    thread::sleep(Duration::from_secs(1));
+}
+
+#[test]
+fn client_transaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4100".parse().unwrap();
+    start_server(addr, &temp_dir);
+
+    let client = KvsClient::new(addr, logger());
+
+    let count = client.transaction(vec![
+        Command::Set { key: "key1".to_owned(), value: "value1".to_owned(), expires_at: None },
+        Command::Set { key: "key2".to_owned(), value: "value2".to_owned(), expires_at: None },
+        Command::Remove { key: "key1".to_owned() },
+    ])?;
+    assert_eq!(count, 3);
+
+    // A failing command aborts the whole transaction
+    assert!(client
+        .transaction(vec![
+            Command::Set { key: "key3".to_owned(), value: "value3".to_owned(), expires_at: None },
+            Command::Remove { key: "key1".to_owned() },
+        ])
+        .is_err());
+
+    // Nothing from the failed transaction is visible
+    assert_eq!(client.transaction(vec![Command::Remove { key: "key2".to_owned() }])?, 1);
+    assert!(matches!(
+        client.transaction(vec![Command::Remove { key: "key3".to_owned() }]),
+        Err(KvsError::KeyNotFound)
+    ));
+
+    Ok(())
+}
+
+// Should keep serving other clients while a connection stays open
+#[test]
+fn concurrent_connections() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4101".parse().unwrap();
+    start_server(addr, &temp_dir);
+
+    // A client that connects without sending anything
+    let _idle = std::net::TcpStream::connect(addr)?;
+
+    let client = KvsClient::new(addr, logger());
+    assert_eq!(client.transaction(vec![
+        Command::Set { key: "key1".to_owned(), value: "value1".to_owned(), expires_at: None },
+    ])?, 1);
+
+    Ok(())
+}
+
+// Should answer an oversized frame with an error and close the connection
+#[test]
+fn oversized_frame() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4102".parse().unwrap();
+    start_server(addr, &temp_dir);
+
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    stream.write_all(&(MAX_FRAME_SIZE + 1).to_be_bytes())?;
+
+    let response: Option<CommandResponse> = read_frame(&mut stream)?;
+    assert!(matches!(response, Some(CommandResponse::Error(..))));
+    assert!(read_frame::<_, CommandResponse>(&mut stream)?.is_none());
+
+    Ok(())
+}
+
+// Should answer every command of a batch in order, including failing ones
+#[test]
+fn client_batch() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4103".parse().unwrap();
+    start_server(addr, &temp_dir);
+
+    let client = KvsClient::new(addr, logger());
+
+    let responses = client.run_batch(vec![
+        Command::Set { key: "key1".to_owned(), value: "value1".to_owned(), expires_at: None },
+        Command::Remove { key: "key2".to_owned() },
+        Command::Get { key: "key1".to_owned(), out: None },
+    ])?;
+    assert_eq!(responses.len(), 3);
+    assert!(matches!(responses[0], CommandResponse::Success));
+    assert!(matches!(responses[1], CommandResponse::Error(ErrorKind::KeyNotFound, _)));
+    assert!(matches!(&responses[2], CommandResponse::Value(value) if value == "value1"));
+
+    let responses = client.run_batch(vec![
+        Command::GetMany { keys: vec!["key2".to_owned(), "key1".to_owned()] },
+    ])?;
+    assert!(matches!(&responses[0], CommandResponse::Values(values) if values == &[None, Some("value1".to_owned())]));
+
+    // Compare and swap over the network
+    let responses = client.run_batch(vec![
+        Command::Cas { key: "key1".to_owned(), expected: Some("value1".to_owned()), new: Some("value2".to_owned()) },
+        Command::Cas { key: "key1".to_owned(), expected: Some("value1".to_owned()), new: None },
+    ])?;
+    assert!(matches!(responses[0], CommandResponse::Swapped(true)));
+    assert!(matches!(responses[1], CommandResponse::Swapped(false)));
+
+    // Big batches do not block on unread responses
+    let commands = (0..5000)
+        .map(|iter| Command::Set { key: format!("key{}", iter), value: "value".repeat(100), expires_at: None })
+        .collect();
+    let responses = client.run_batch(commands)?;
+    assert_eq!(responses.len(), 5000);
+    assert!(responses.iter().all(|response| matches!(response, CommandResponse::Success)));
+
+    assert_eq!(client.ping()?, env!("CARGO_PKG_VERSION"));
+
+    // Empty keys are rejected before reaching the engine
+    let responses = client.run_batch(vec![
+        Command::Set { key: "".to_owned(), value: "value1".to_owned(), expires_at: None },
+        Command::Get { key: "".to_owned(), out: None },
+    ])?;
+    assert!(responses.iter().all(|response| matches!(response, CommandResponse::Error(ErrorKind::Other, _))));
+
+    // Counts are sent as 64-bit integers whatever the platform
+    let responses = client.run_batch(vec![
+        Command::Len,
+        Command::RemovePrefix { prefix: "key1".to_owned() },
+        Command::Append { key: "key1".to_owned(), value: "abc".to_owned() },
+    ])?;
+    assert!(matches!(responses[0], CommandResponse::Count(5000)));
+    assert!(matches!(responses[1], CommandResponse::Count(1111)));
+    assert!(matches!(responses[2], CommandResponse::Count(3)));
+    assert_eq!(serde_json::to_string(&CommandResponse::Count(u64::MAX))?, format!("{{\"Count\":{}}}", u64::MAX));
+
+    let responses = client.run_batch(vec![Command::Clear, Command::Len])?;
+    assert!(matches!(responses[0], CommandResponse::Success));
+    assert!(matches!(responses[1], CommandResponse::Count(0)));
+
+    Ok(())
+}
+
+// Should retry connecting until the server is up
+#[test]
+fn client_connect_retry() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4104".parse().unwrap();
+
+    // Without retries the client fails right away
+    let mut client = KvsClient::new(addr, logger());
+    assert!(matches!(client.run_batch(vec![]), Err(KvsError::IOError(_))));
+
+    // The server only starts after the first attempts failed
+    let server = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        start_server(addr, &temp_dir);
+        temp_dir
+    });
+
+    client.set_connect_retry(10, Duration::from_millis(50));
+    let responses = client.run_batch(vec![Command::Get { key: "key1".to_owned(), out: None }])?;
+    assert!(matches!(responses[0], CommandResponse::KeyNotFound));
+
+    let _temp_dir = server.join().unwrap();
+
+    Ok(())
+}
+
+// Should count the served commands, errors and response bytes
+#[test]
+fn server_metrics() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4105".parse().unwrap();
+
+    let engine = KvStore::open(temp_dir.path())?;
+    let mut server = KvsServer::new(addr, Box::new(engine), logger());
+    server.set_threads(4);
+
+    // Clones share their counters
+    let running = server.clone();
+    thread::spawn(move || running.run().expect("server failed"));
+    thread::sleep(Duration::from_secs(1));
+
+    let metrics = server.metrics();
+    assert_eq!(metrics.total_commands(), 0);
+    assert_eq!(metrics.bytes_served, 0);
+
+    let client = KvsClient::new(addr, logger());
+    client.run_batch(vec![
+        Command::Set { key: "key1".to_owned(), value: "value1".to_owned(), expires_at: None },
+        Command::Get { key: "key1".to_owned(), out: None },
+        Command::Get { key: "key2".to_owned(), out: None },
+        Command::Remove { key: "key2".to_owned() },
+    ])?;
+    client.ping()?;
+    client.size_stats()?;
+    assert_eq!(client.keys(None, None)?, vec!["key1"]);
+    assert_eq!(client.stat_key("key1".to_owned())?.map(|stat| stat.value_bytes), Some(6));
+
+    let metrics = server.metrics();
+    assert_eq!(metrics.total_commands(), 8);
+    assert_eq!(metrics.commands["get"], 2);
+    assert_eq!(metrics.commands["set"], 1);
+    assert_eq!(metrics.commands["rm"], 1);
+    assert_eq!(metrics.commands["ping"], 1);
+    assert_eq!(metrics.commands["stats"], 1);
+    assert_eq!(metrics.commands["keys"], 1);
+    assert_eq!(metrics.commands["stat"], 1);
+    assert_eq!(metrics.commands["cas"], 0);
+    assert_eq!(metrics.errors, 1);
+    assert!(metrics.bytes_served > 0);
+
+    Ok(())
+}
+
+// Should serve clients over a Unix domain socket given as `unix:PATH`
+#[cfg(unix)]
+#[test]
+fn unix_socket() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let socket = temp_dir.path().join("kvs.sock");
+    let addr: ServerAddr = format!("unix:{}", socket.display()).parse()?;
+    assert_eq!(addr, ServerAddr::Unix(socket.clone()));
+    assert!("unix:".parse::<ServerAddr>().is_err());
+    assert!("localhost".parse::<ServerAddr>().is_err());
+
+    // A socket file left behind by a previous server is replaced
+    drop(std::os::unix::net::UnixListener::bind(&socket)?);
+
+    let path = temp_dir.path().to_owned();
+    let server_addr = addr.clone();
+    thread::spawn(move || {
+        let engine = KvStore::open(path).expect("unable to open KvStore");
+        let mut server = KvsServer::new(server_addr, Box::new(engine), logger());
+        server.set_threads(4);
+        server.run().expect("server failed");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    let client = KvsClient::new(addr, logger());
+    assert_eq!(client.ping()?, env!("CARGO_PKG_VERSION"));
+
+    let responses = client.run_batch(vec![
+        Command::Set { key: "key1".to_owned(), value: "value1".to_owned(), expires_at: None },
+        Command::Get { key: "key1".to_owned(), out: None },
+    ])?;
+    assert!(matches!(&responses[1], CommandResponse::Value(value) if value == "value1"));
+
+    Ok(())
+}
+
+// Should speak RESP2 with Redis clients when configured to
+#[test]
+fn resp_protocol() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4106".parse().unwrap();
+
+    let engine = KvStore::open(temp_dir.path())?;
+    let mut server = KvsServer::new(addr, Box::new(engine), logger());
+    server.set_threads(4);
+    server.set_protocol(Protocol::Resp);
+    thread::spawn(move || server.run().expect("server failed"));
+    thread::sleep(Duration::from_secs(1));
+
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    let mut reader = std::io::BufReader::new(stream.try_clone()?);
+
+    // Send a request and read back as many reply bytes as expected
+    let mut request = |request: &str, expected: &str| -> Result<()> {
+        stream.write_all(request.as_bytes())?;
+
+        let mut reply = vec![0; expected.len()];
+        std::io::Read::read_exact(&mut reader, &mut reply)?;
+        assert_eq!(String::from_utf8_lossy(&reply), expected);
+
+        Ok(())
+    };
+
+    request("*1\r\n$4\r\nPING\r\n", "+PONG\r\n")?;
+    request("*3\r\n$3\r\nSET\r\n$4\r\nkey1\r\n$6\r\nvalue1\r\n", "+OK\r\n")?;
+    request("*2\r\n$3\r\nget\r\n$4\r\nkey1\r\n", "$6\r\nvalue1\r\n")?;
+    request("*2\r\n$3\r\nGET\r\n$4\r\nkey2\r\n", "$-1\r\n")?;
+    request("*3\r\n$3\r\nDEL\r\n$4\r\nkey1\r\n$4\r\nkey2\r\n", ":1\r\n")?;
+    request("*2\r\n$3\r\nDEL\r\n$4\r\nkey1\r\n", ":0\r\n")?;
+    request("*1\r\n$3\r\nGET\r\n", "-ERR wrong number of arguments for 'get' command\r\n")?;
+    request("*1\r\n$5\r\nMULTI\r\n", "-ERR unknown command 'multi'\r\n")?;
+    request("*2\r\n$3\r\nGET\r\n$0\r\n\r\n", "-ERR Invalid command: Keys cannot be empty\r\n")?;
+
+    // Anything else than an array of bulk strings ends the connection
+    request("GET key1\r\n", "-ERR Protocol error: expected '*'\r\n")?;
+    let mut rest = Vec::new();
+    std::io::Read::read_to_end(&mut reader, &mut rest)?;
+    assert!(rest.is_empty());
+
+    // So does a request whose arguments together are bigger than a frame, before the
+    // argument going over the limit is read
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let len = MAX_FRAME_SIZE as usize / 2;
+    stream.write_all(format!("*3\r\n$3\r\nSET\r\n${}\r\n", len).as_bytes())?;
+    stream.write_all(&vec![b'a'; len])?;
+    stream.write_all(format!("\r\n${}\r\n", len).as_bytes())?;
+
+    let mut reply = String::new();
+    std::io::Read::read_to_string(&mut stream, &mut reply)?;
+    assert_eq!(reply, format!("-ERR Protocol error: request bigger than the maximum of {} bytes\r\n", MAX_FRAME_SIZE));
+
+    Ok(())
+}
+
+// Should stop accepting connections and flush the engine when shut down
+#[test]
+fn shutdown_flushes_engine() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4107".parse().unwrap();
+
+    // Writes stay buffered until the engine is flushed
+    let config = KvStoreConfig { sync_on_write: false, ..KvStoreConfig::default() };
+    let engine = KvStore::open_with_options(temp_dir.path(), config)?;
+    let mut server = KvsServer::new(addr, Box::new(engine), logger());
+    server.set_threads(4);
+
+    // The clone keeps the engine alive, so it is not flushed by being dropped
+    let handle = {
+        let server = server.clone();
+        thread::spawn(move || server.run())
+    };
+    thread::sleep(Duration::from_secs(1));
+
+    let client = KvsClient::new(addr, logger());
+    client.transaction(vec![
+        Command::Set { key: "key1".to_owned(), value: "value1".to_owned(), expires_at: None },
+    ])?;
+
+    server.shutdown()?;
+    handle.join().expect("server thread panicked")?;
+
+    // The store is still open, so its log file is read directly
+    let log = std::fs::read(temp_dir.path().join("1.log"))?;
+    assert!(log.windows(6).any(|window| window == b"value1"));
+
+    Ok(())
+}
+
+// Should return values from the typed client methods instead of printing them
+#[test]
+fn typed_client_api() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4108".parse().unwrap();
+    start_server(addr, &temp_dir);
+
+    let client = KvsClient::new(addr, logger());
+
+    assert_eq!(client.get("key1".to_owned())?, None);
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    client.remove("key1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, None);
+    assert!(matches!(client.remove("key1".to_owned()), Err(KvsError::KeyNotFound)));
+    assert!(client.set("".to_owned(), "value1".to_owned()).is_err());
+
+    Ok(())
+}
+
+// Should send every command over the connection opened by `connect` and reopen it once closed
+#[test]
+fn persistent_client_connection() -> Result<()> {
+    let addr: SocketAddr = "127.0.0.1:4109".parse().unwrap();
+    let listener = std::net::TcpListener::bind(addr)?;
+
+    // Answer commands like a server, but accept a single connection at a time
+    let server = thread::spawn(move || -> Result<()> {
+        let respond = |stream: &std::net::TcpStream, count: usize| -> Result<()> {
+            let mut reader = std::io::BufReader::new(stream);
+
+            for _ in 0..count {
+                let res = match read_frame::<_, Command>(&mut reader)? {
+                    Some(Command::Get { .. }) => CommandResponse::Value("value1".to_owned()),
+                    _ => CommandResponse::Success
+                };
+                write_frame(&mut std::io::BufWriter::new(stream), &res)?;
+            }
+
+            Ok(())
+        };
+
+        // A client connecting once per command would be refused from the second command on
+        let (stream, _) = listener.accept()?;
+        drop(listener);
+        respond(&stream, 2)?;
+
+        // Closing the connection makes the client open a new one
+        let listener = std::net::TcpListener::bind(addr)?;
+        drop(stream);
+        let (stream, _) = listener.accept()?;
+        respond(&stream, 1)
+    });
+
+    let mut client = KvsClient::new(addr, logger());
+    client.connect()?;
+
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+
+    server.join().expect("server thread panicked")
+}
+
+// Should only send a command again over a reopened connection if it cannot change the store
+#[test]
+fn persistent_connection_closed_before_response() -> Result<()> {
+    let addr: SocketAddr = "127.0.0.1:4120".parse().unwrap();
+    let listener = std::net::TcpListener::bind(addr)?;
+
+    // Read one command per connection and close it without responding, except on the last one
+    let server = thread::spawn(move || -> Result<Vec<Command>> {
+        let mut received = Vec::new();
+
+        for connection in 0..3 {
+            let (stream, _) = listener.accept()?;
+            let mut reader = std::io::BufReader::new(&stream);
+
+            if let Some(command) = read_frame::<_, Command>(&mut reader)? {
+                received.push(command);
+            }
+
+            if connection == 2 {
+                write_frame(&mut std::io::BufWriter::new(&stream), &CommandResponse::Value("1".to_owned()))?;
+            }
+        }
+
+        Ok(received)
+    });
+
+    let mut client = KvsClient::new(addr, logger());
+    client.connect()?;
+
+    // The increment may have been applied, so it is not sent again
+    let incr = client.run(Command::Incr { key: "key1".to_owned(), delta: 1 }, kvs::OutputFormat::Text);
+    assert!(matches!(incr, Err(KvsError::RequestError(e)) if e.contains("may have run")));
+
+    // A read is sent again over the connection opened once more
+    assert_eq!(client.get("key1".to_owned())?, Some("1".to_owned()));
+
+    let received = server.join().expect("server thread panicked")?;
+    assert_eq!(received, vec![
+        Command::Incr { key: "key1".to_owned(), delta: 1 },
+        Command::Get { key: "key1".to_owned(), out: None },
+        Command::Get { key: "key1".to_owned(), out: None },
+    ]);
+
+    Ok(())
+}
+
+// Should give up on a server which accepts the connection but never answers
+#[test]
+fn client_request_timeout() -> Result<()> {
+    let addr: SocketAddr = "127.0.0.1:4110".parse().unwrap();
+    let listener = std::net::TcpListener::bind(addr)?;
+
+    // Keep the connections open without reading from them until the client is done
+    let (done, finished) = std::sync::mpsc::channel::<()>();
+    let server = thread::spawn(move || -> Result<()> {
+        let (first, _) = listener.accept()?;
+        let (second, _) = listener.accept()?;
+        let _ = finished.recv();
+        drop((first, second));
+
+        Ok(())
+    });
+
+    let mut client = KvsClient::new(addr, logger());
+    client.set_timeout(Some(Duration::from_millis(200)));
+
+    let start = std::time::Instant::now();
+    assert!(matches!(client.get("key1".to_owned()), Err(KvsError::Timeout(timeout)) if timeout == Duration::from_millis(200)));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // The connection opened by `connect` is dropped after a timeout instead of being reused
+    client.connect()?;
+    assert!(matches!(client.get("key1".to_owned()), Err(KvsError::Timeout(_))));
+
+    done.send(()).expect("server thread stopped");
+    server.join().expect("server thread panicked")
+}
+
+// Should refuse commands that change the store on a read-only server, and commands missing from the allow-list
+#[test]
+fn read_only_server() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1".to_owned())?;
+
+    let addr: SocketAddr = "127.0.0.1:4111".parse().unwrap();
+    let mut server = KvsServer::new(addr, Box::new(store.clone()), logger());
+    server.set_read_only(true);
+    thread::spawn(move || server.run().expect("server failed"));
+
+    let allow_list_addr: SocketAddr = "127.0.0.1:4112".parse().unwrap();
+    let mut server = KvsServer::new(allow_list_addr, Box::new(store.clone()), logger());
+    server.set_allowed_commands(Some(["get", "ping"].iter().map(|name| name.to_string()).collect()));
+    thread::spawn(move || server.run().expect("server failed"));
+    thread::sleep(Duration::from_secs(1));
+
+    let client = KvsClient::new(addr, logger());
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+    match client.set("key1".to_owned(), "value2".to_owned()) {
+        Err(KvsError::RequestError(e)) => assert!(e.contains("read-only")),
+        res => panic!("expected a read-only error, got {:?}", res)
+    }
+    assert!(client.remove("key1".to_owned()).is_err());
+
+    // Transactions cannot queue mutations either
+    assert!(client.transaction(vec![Command::Set { key: "key2".to_owned(), value: "value2".to_owned(), expires_at: None }]).is_err());
+
+    let client = KvsClient::new(allow_list_addr, logger());
+    client.ping()?;
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert!(client.set("key1".to_owned(), "value2".to_owned()).is_err());
+    assert!(matches!(
+        client.run_batch(vec![Command::Len])?.as_slice(),
+        [CommandResponse::Error(ErrorKind::Other, e)] if e == "Command not allowed: len"
+    ));
+
+    // The store is unchanged
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should end a connection closed in the middle of a frame quietly and keep a connection
+// going after a frame which is not a command
+#[test]
+fn client_disconnect_and_invalid_frame() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4113".parse().unwrap();
+    start_server(addr, &temp_dir);
+
+    // A client going away after part of a frame
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    stream.write_all(&100u32.to_be_bytes())?;
+    stream.write_all(b"{\"G")?;
+    drop(stream);
+
+    // A complete frame which is not a command is answered with an error
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    stream.write_all(&8u32.to_be_bytes())?;
+    stream.write_all(b"not json")?;
+
+    let response: Option<CommandResponse> = read_frame(&mut stream)?;
+    assert!(matches!(response, Some(CommandResponse::Error(ErrorKind::Other, _))));
+
+    // The same connection still serves the next command
+    write_frame(&mut stream, &Command::Ping)?;
+    let response: Option<CommandResponse> = read_frame(&mut stream)?;
+    assert!(matches!(response, Some(CommandResponse::Pong(_))));
+
+    assert_eq!(KvsClient::new(addr, logger()).ping()?, env!("CARGO_PKG_VERSION"));
+
+    Ok(())
+}
+
+// Should close a silent connection after the idle timeout, freeing its thread for other clients
+#[test]
+fn idle_timeout() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4117".parse().unwrap();
+    let path = temp_dir.path().to_owned();
+    thread::spawn(move || {
+        let engine = KvStore::open(path).expect("unable to open KvStore");
+        let mut server = KvsServer::new(addr, Box::new(engine), logger());
+        server.set_threads(1);
+        server.set_idle_timeout(Some(Duration::from_millis(200)));
+        server.run().expect("server failed");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    // The only thread is busy with a client that never sends anything until the connection is closed
+    let idle = std::net::TcpStream::connect(addr)?;
+    idle.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let response: Option<CommandResponse> = read_frame(&mut &idle)?;
+    assert!(response.is_none());
+
+    // A client pausing for less than the timeout keeps its connection
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    thread::sleep(Duration::from_millis(50));
+    write_frame(&mut stream, &Command::Ping)?;
+    let response: Option<CommandResponse> = read_frame(&mut stream)?;
+    assert!(matches!(response, Some(CommandResponse::Pong(_))));
+    drop(stream);
+
+    assert_eq!(KvsClient::new(addr, logger()).ping()?, env!("CARGO_PKG_VERSION"));
+
+    Ok(())
+}
+
+// Should queue more connections than the default backlog and serve clients with TCP_NODELAY unset
+#[cfg(target_os = "linux")]
+#[test]
+fn listen_backlog_and_nodelay() -> Result<()> {
+    let addr = ServerAddr::Tcp("127.0.0.1:4114".parse().unwrap());
+    let listener = Listener::bind(&addr)?;
+    listener.set_backlog(1024)?;
+
+    // None of the connections is accepted, so they all wait in the backlog
+    let waiting = (0..300)
+        .map(|_| Stream::connect_timeout(&addr, Some(Duration::from_secs(1))))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    assert_eq!(waiting.len(), 300);
+    drop(waiting);
+    drop(listener);
+
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4115".parse().unwrap();
+    let path = temp_dir.path().to_owned();
+    thread::spawn(move || {
+        let engine = KvStore::open(path).expect("unable to open KvStore");
+        let mut server = KvsServer::new(addr, Box::new(engine), logger());
+        server.set_backlog(Some(1024));
+        server.set_nodelay(true);
+        server.run().expect("server failed");
+    });
+    thread::sleep(Duration::from_secs(1));
+
+    let mut client = KvsClient::new(addr, logger());
+    client.set_nodelay(true);
+    client.connect()?;
+    client.set("key".to_owned(), "value".to_owned())?;
+    assert_eq!(client.get("key".to_owned())?, Some("value".to_owned()));
+
+    // The server and the client run in this process, so the options set on their sockets can be read
+    let sockets = tcp_sockets_on_port(addr.port());
+    let listening: Vec<_> = sockets.iter().filter(|socket| socket.backlog.is_some()).collect();
+    let connected: Vec<_> = sockets.iter().filter(|socket| socket.backlog.is_none()).collect();
+
+    assert_eq!(listening.len(), 1);
+    assert_eq!(listening[0].backlog, Some(1024));
+
+    // Both ends of the client's connection, which TCP_NODELAY is not set on by default
+    assert!(connected.iter().any(|socket| socket.accepted));
+    assert!(connected.iter().any(|socket| !socket.accepted));
+    assert!(connected.iter().all(|socket| socket.nodelay));
+
+    Ok(())
+}
+
+/// TCP socket of this process
+#[cfg(target_os = "linux")]
+struct TcpSocket {
+    /// Maximum number of waiting connections of a listening socket, `None` for a connected one
+    backlog: Option<u32>,
+    /// Whether it was accepted on the port rather than connected to it
+    accepted: bool,
+    nodelay: bool,
+}
+
+/// TCP sockets of this process with the given port at either end
+#[cfg(target_os = "linux")]
+fn tcp_sockets_on_port(port: u16) -> Vec<TcpSocket> {
+    use std::mem::{size_of, ManuallyDrop};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+
+    let fds = std::fs::read_dir("/proc/self/fd").expect("unable to list file descriptors")
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<i32>().ok());
+
+    fds.filter_map(|fd| {
+        // SAFETY: `ManuallyDrop` keeps the descriptor open, so it stays owned by whoever opened it.
+        // Descriptors which are not TCP sockets make the calls below fail instead of misbehaving.
+        let socket = ManuallyDrop::new(unsafe { std::net::TcpStream::from_raw_fd(fd) });
+        let local = socket.local_addr().ok()?;
+
+        match socket.peer_addr() {
+            Ok(peer) if local.port() == port || peer.port() == port => {
+                Some(TcpSocket { backlog: None, accepted: local.port() == port, nodelay: socket.nodelay().ok()? })
+            },
+            Err(_) if local.port() == port => {
+                // Linux reports the backlog of a listening socket as `tcpi_sacked` in its TCP_INFO
+                let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+                let mut len = size_of::<libc::tcp_info>() as libc::socklen_t;
+                // SAFETY: `info` and `len` are valid for writes of the size passed in `len`
+                let res = unsafe {
+                    libc::getsockopt(socket.as_raw_fd(), libc::IPPROTO_TCP, libc::TCP_INFO, &mut info as *mut _ as *mut libc::c_void, &mut len)
+                };
+
+                (res == 0).then_some(TcpSocket { backlog: Some(info.tcpi_sacked), accepted: false, nodelay: false })
+            },
+            _ => None
+        }
+    }).collect()
+}
+
+// Should fail to change the listen backlog where it could be silently ignored
+#[cfg(not(target_os = "linux"))]
+#[test]
+fn listen_backlog_unsupported() -> Result<()> {
+    let addr = ServerAddr::Tcp("127.0.0.1:4114".parse().unwrap());
+    let listener = Listener::bind(&addr)?;
+
+    let err = listener.set_backlog(1024).expect_err("the backlog cannot be changed outside Linux");
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+
+    Ok(())
+}
+
+// Should list keys a bounded page at a time and let the client follow the pages
+#[test]
+fn remote_keys() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4116".parse().unwrap();
+    start_server(addr, &temp_dir);
+
+    let client = KvsClient::new(addr, logger());
+    let count = MAX_KEYS_PER_RESPONSE * 2 + 10;
+    let mut commands: Vec<Command> = (0..count)
+        .map(|i| Command::Set { key: format!("a{:05}", i), value: "value".to_owned(), expires_at: None })
+        .collect();
+    commands.push(Command::Set { key: "b".to_owned(), value: "value".to_owned(), expires_at: None });
+    client.run_batch(commands)?;
+
+    // A single response never holds more keys than the maximum
+    let responses = client.run_batch(vec![
+        Command::Keys { prefix: None, after: None, limit: None },
+        Command::Keys { prefix: Some("a".to_owned()), after: Some("a00002".to_owned()), limit: Some(2) },
+        Command::Keys { prefix: Some("b".to_owned()), after: None, limit: Some(2) },
+        Command::Keys { prefix: None, after: None, limit: Some(0) },
+    ])?;
+    match &responses[0] {
+        CommandResponse::Keys(keys, next) => {
+            assert_eq!(keys.len(), MAX_KEYS_PER_RESPONSE);
+            assert_eq!(next.as_ref(), keys.last());
+        },
+        response => panic!("unexpected response: {:?}", response)
+    }
+    assert!(matches!(
+        &responses[1],
+        CommandResponse::Keys(keys, Some(next)) if keys == &["a00003", "a00004"] && next == "a00004"
+    ));
+    assert!(matches!(&responses[2], CommandResponse::Keys(keys, None) if keys == &["b"]));
+    assert!(matches!(&responses[3], CommandResponse::Error(ErrorKind::Other, _)));
+
+    // The client follows the pages up to the limit
+    let keys = client.keys(Some("a".to_owned()), None)?;
+    assert_eq!(keys.len(), count);
+    assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+
+    assert_eq!(client.keys(None, Some(MAX_KEYS_PER_RESPONSE + 5))?.len(), MAX_KEYS_PER_RESPONSE + 5);
+    assert_eq!(client.keys(None, None)?.len(), count + 1);
+
+    Ok(())
+}
+
+// Should answer gets of cached values without asking the server until they are invalidated
+#[test]
+fn client_value_cache() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4118".parse().unwrap();
+
+    let engine = KvStore::open(temp_dir.path())?;
+    let mut server = KvsServer::new(addr, Box::new(engine), logger());
+    server.set_threads(4);
+
+    let running = server.clone();
+    thread::spawn(move || running.run().expect("server failed"));
+    thread::sleep(Duration::from_secs(1));
+
+    let mut client = KvsClient::new(addr, logger());
+    client.set_value_cache(Some(2));
+    client.connect()?;
+    let other = KvsClient::new(addr, logger());
+
+    // A local set caches the value
+    client.set("key1".to_owned(), "value1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(server.metrics().commands["get"], 0);
+
+    // A write by another client is not seen until the key is invalidated
+    other.set("key1".to_owned(), "value2".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, Some("value1".to_owned()));
+    client.invalidate("key1");
+    assert_eq!(client.get("key1".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(client.get("key1".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(server.metrics().commands["get"], 1);
+
+    // Missing keys are not cached
+    assert_eq!(client.get("key2".to_owned())?, None);
+    assert_eq!(client.get("key2".to_owned())?, None);
+    assert_eq!(server.metrics().commands["get"], 3);
+
+    // The least recently used value is evicted once the cache is full
+    other.set("key2".to_owned(), "value2".to_owned())?;
+    other.set("key3".to_owned(), "value3".to_owned())?;
+    assert_eq!(client.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(client.get("key3".to_owned())?, Some("value3".to_owned()));
+    assert_eq!(client.get("key2".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(server.metrics().commands["get"], 5);
+    assert_eq!(client.get("key1".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(server.metrics().commands["get"], 6);
+
+    // Writes sent by the client remove the values they change
+    client.remove("key1".to_owned())?;
+    assert_eq!(client.get("key1".to_owned())?, None);
+    client.run_batch(vec![Command::Rename { from: "key2".to_owned(), to: "key1".to_owned() }])?;
+    assert_eq!(client.get("key2".to_owned())?, None);
+    assert_eq!(server.metrics().commands["get"], 8);
+
+    other.set("key3".to_owned(), "value4".to_owned())?;
+    client.invalidate_all();
+    assert_eq!(client.get("key1".to_owned())?, Some("value2".to_owned()));
+    assert_eq!(client.get("key3".to_owned())?, Some("value4".to_owned()));
+    assert_eq!(server.metrics().commands["get"], 10);
+
+    Ok(())
+}
+
+// Should reject Set commands with an expiry, whether run right away or queued in a transaction
+#[test]
+fn set_with_expiry_rejected() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4119".parse().unwrap();
+    start_server(addr, &temp_dir);
+
+    let client = KvsClient::new(addr, logger());
+    let set = |key: &str| Command::Set { key: key.to_owned(), value: "value1".to_owned(), expires_at: Some(u64::MAX) };
+
+    let responses = client.run_batch(vec![set("key1"), Command::Get { key: "key1".to_owned(), out: None }])?;
+    assert!(matches!(&responses[0], CommandResponse::Error(ErrorKind::Other, e) if e.contains("expiry")));
+    assert!(matches!(responses[1], CommandResponse::KeyNotFound));
+
+    let result = client.transaction(vec![Command::Set { key: "key2".to_owned(), value: "value2".to_owned(), expires_at: None }, set("key3")]);
+    assert!(matches!(result, Err(KvsError::RequestError(e)) if e.contains("expiry")));
+    assert_eq!(client.get("key2".to_owned())?, None);
+    assert_eq!(client.get("key3".to_owned())?, None);
+
+    Ok(())
+}
+
+// Should send a value in chunks cut between characters and write them to the client's writer
+#[test]
+fn chunked_get() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4122".parse().unwrap();
+    start_server(addr, &temp_dir);
+
+    // Bigger than a chunk, with chunks ending in the middle of a character
+    let value = "aé".repeat(1_200_000);
+    assert!(value.len() > MAX_CHUNK_SIZE as usize);
+
+    let mut client = KvsClient::new(addr, logger());
+    client.set("key1".to_owned(), value.clone())?;
+    client.set("key2".to_owned(), "aé".to_owned())?;
+
+    let mut out = Vec::new();
+    assert!(client.get_to("key1".to_owned(), &mut out)?);
+    assert!(out == value.as_bytes());
+    assert!(!client.get_to("missing".to_owned(), &mut Vec::new())?);
+
+    // Every chunk is read, so a persistent connection can go on with the next command
+    client.connect()?;
+    let mut out = Vec::new();
+    assert!(client.get_to("key1".to_owned(), &mut out)?);
+    assert!(out == value.as_bytes());
+    assert_eq!(client.get("key2".to_owned())?, Some("aé".to_owned()));
+
+    // A chunk is only bigger than asked when a single character is
+    let mut stream = std::net::TcpStream::connect(addr)?;
+    write_frame(&mut stream, &Command::GetChunked { key: "key2".to_owned(), chunk_size: 1 })?;
+    assert!(matches!(read_frame(&mut stream)?, Some(CommandResponse::ValueStart(3))));
+    assert!(matches!(read_frame(&mut stream)?, Some(CommandResponse::Chunk(chunk)) if chunk == "a"));
+    assert!(matches!(read_frame(&mut stream)?, Some(CommandResponse::Chunk(chunk)) if chunk == "é"));
+
+    Ok(())
+}
+
+// Should record the command type, key, outcome and latency of every request in a span
+#[cfg(feature = "tracing")]
+#[test]
+fn client_request_spans() -> Result<()> {
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Name and value of the fields of a span
+    #[derive(Default)]
+    struct Fields(Vec<(String, String)>);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0.push((field.name().to_owned(), format!("{:?}", value)));
+        }
+    }
+
+    /// Fields recorded in every span, by span id
+    #[derive(Default, Clone)]
+    struct Spans(Arc<Mutex<Vec<Fields>>>);
+
+    impl Subscriber for Spans {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = self.0.lock().unwrap();
+            let mut fields = Fields(vec![("name".to_owned(), span.metadata().name().to_owned())]);
+            span.record(&mut fields);
+            spans.push(fields);
+
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            values.record(&mut self.0.lock().unwrap()[span.into_u64() as usize - 1]);
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, _: &Event<'_>) {}
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let addr: SocketAddr = "127.0.0.1:4121".parse().unwrap();
+    start_server(addr, &temp_dir);
+
+    let spans = Spans::default();
+    let client = KvsClient::new(addr, logger());
+
+    tracing::subscriber::with_default(spans.clone(), || -> Result<()> {
+        client.set("key1".to_owned(), "value1".to_owned())?;
+        assert_eq!(client.get("key2".to_owned())?, None);
+
+        Ok(())
+    })?;
+
+    let spans = spans.0.lock().unwrap();
+    let field = |span: &Fields, name: &str| {
+        span.0.iter().find(|(field, _)| field == name).map(|(_, value)| value.clone())
+    };
+
+    let requests: Vec<_> = spans.iter().filter(|span| field(span, "name").as_deref() == Some("request")).collect();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(field(requests[0], "command").as_deref(), Some("\"set\""));
+    assert_eq!(field(requests[0], "key").as_deref(), Some("\"key1\""));
+    assert_eq!(field(requests[0], "outcome").as_deref(), Some("\"success\""));
+    assert_eq!(field(requests[1], "outcome").as_deref(), Some("\"key_not_found\""));
+    assert!(requests.iter().all(|span| field(span, "latency_us").is_some()));
+
+    Ok(())
+}
@@ -0,0 +1,58 @@
+use kvs::{Result, SharedQueueThreadPool, ThreadPool};
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::Duration;
+
+// Should run every spawned job
+#[test]
+fn shared_queue_runs_jobs() -> Result<()> {
+    let pool = SharedQueueThreadPool::new(4)?;
+    let counter = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = mpsc::channel();
+
+    for _ in 0..100 {
+        let counter = Arc::clone(&counter);
+        let sender = sender.clone();
+
+        pool.spawn(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            sender.send(()).unwrap();
+        });
+    }
+
+    for _ in 0..100 {
+        receiver.recv_timeout(Duration::from_secs(5)).expect("job did not run");
+    }
+    assert_eq!(counter.load(Ordering::SeqCst), 100);
+
+    Ok(())
+}
+
+// Should keep running jobs after jobs panicked
+#[test]
+fn shared_queue_survives_panics() -> Result<()> {
+    // Keep the test output free of the expected panic messages
+    panic::set_hook(Box::new(|_| {}));
+
+    let pool = SharedQueueThreadPool::new(2)?;
+
+    for _ in 0..4 {
+        pool.spawn(|| panic!("job panicked"));
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    for _ in 0..4 {
+        let sender = sender.clone();
+        pool.spawn(move || sender.send(()).unwrap());
+    }
+
+    for _ in 0..4 {
+        receiver.recv_timeout(Duration::from_secs(5)).expect("job did not run");
+    }
+
+    let _ = panic::take_hook();
+
+    Ok(())
+}